@@ -0,0 +1,84 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Throughput of decoding events into record batches. The earlier pipeline serialized
+//! every event and parsed it again for schema inference, decoding and alerts. Events are
+//! now decoded straight from the parsed body with the schema of the stream.
+//!
+//! Run with `cargo bench --bench decode`
+
+use std::io::BufReader;
+use std::iter;
+use std::sync::Arc;
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use datafusion::arrow::datatypes::Schema;
+use datafusion::arrow::json::reader::{
+    infer_json_schema, infer_json_schema_from_iterator, Decoder, DecoderOptions, Reader,
+};
+use serde_json::{json, Value};
+
+fn reparsed(body: &Value) {
+    let text = body.to_string();
+    let schema = infer_json_schema(&mut BufReader::new(text.as_bytes()), None).unwrap();
+    let mut reader = Reader::new(
+        text.as_bytes(),
+        Arc::new(schema),
+        DecoderOptions::new().with_batch_size(1024),
+    );
+    black_box(reader.next().unwrap().unwrap());
+    black_box(serde_json::from_str::<Value>(&text).unwrap());
+}
+
+fn parsed_once(body: Value, schema: &Arc<Schema>) {
+    let decoder = Decoder::new(Arc::clone(schema), DecoderOptions::new());
+    let record = decoder
+        .next_batch(&mut iter::once(Ok(body)))
+        .unwrap()
+        .unwrap();
+    black_box(record);
+}
+
+fn decode(c: &mut Criterion) {
+    let body = json!({
+        "host": "192.168.1.10",
+        "method": "GET",
+        "path": "/api/v1/query",
+        "status": 200,
+        "latency": 12.5,
+        "user_agent": "Mozilla/5.0 (X11; Linux x86_64)",
+    });
+    // only the first event of a stream has its schema inferred
+    let schema = Arc::new(infer_json_schema_from_iterator(iter::once(Ok(body.clone()))).unwrap());
+
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("reparsed", |b| b.iter(|| reparsed(&body)));
+    // the body is owned by the pipeline, its copy is made outside of the measurement
+    group.bench_function("parsed_once", |b| {
+        b.iter_batched(
+            || body.clone(),
+            |body| parsed_once(body, &schema),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
@@ -16,9 +16,11 @@
  *
  */
 
+use datafusion::arrow::record_batch::RecordBatch;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+pub mod queue;
 pub mod rule;
 pub mod target;
 
@@ -43,15 +45,15 @@ pub struct Alert {
 }
 
 impl Alert {
-    pub fn check_alert(&self, stream_name: String, event_json: &serde_json::Value) {
-        let resolves = self.rule.resolves(event_json);
-
-        match resolves {
-            AlertState::Listening | AlertState::Firing => (),
-            alert_state @ (AlertState::SetToFiring | AlertState::Resolved) => {
-                let context = self.get_context(stream_name, alert_state);
-                for target in &self.targets {
-                    target.call(context.clone());
+    pub fn check_alert(&self, stream_name: &str, events: &RecordBatch) {
+        for resolves in self.rule.resolves(events) {
+            match resolves {
+                AlertState::Listening | AlertState::Firing => (),
+                alert_state @ (AlertState::SetToFiring | AlertState::Resolved) => {
+                    let context = self.get_context(stream_name.to_owned(), alert_state);
+                    for target in &self.targets {
+                        target.call(context.clone());
+                    }
                 }
             }
         }
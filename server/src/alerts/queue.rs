@@ -0,0 +1,210 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use actix_web::rt::System;
+use datafusion::arrow::record_batch::RecordBatch;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::metadata::STREAM_INFO;
+use crate::option::CONFIG;
+
+struct Job {
+    stream_name: String,
+    events: RecordBatch,
+}
+
+// Batches waiting to be evaluated, the alerts of a stream are always evaluated
+// by the same worker so that consecutive rules see its events in order
+struct Queue {
+    senders: Vec<mpsc::Sender<Job>>,
+    // handed over to the workers when these are started
+    receivers: Mutex<Vec<mpsc::Receiver<Job>>>,
+    capacity: usize,
+    depth: AtomicUsize,
+    dropped: AtomicU64,
+    evaluated: AtomicU64,
+}
+
+lazy_static! {
+    static ref QUEUE: Queue = Queue::new(
+        CONFIG.parseable.alert_workers,
+        CONFIG.parseable.alert_queue_size
+    );
+}
+
+impl Queue {
+    fn new(workers: usize, capacity: usize) -> Self {
+        let per_worker = capacity / workers;
+        let (senders, receivers) = (0..workers).map(|_| mpsc::channel(per_worker)).unzip();
+
+        Self {
+            senders,
+            receivers: Mutex::new(receivers),
+            capacity: per_worker * workers,
+            depth: AtomicUsize::new(0),
+            dropped: AtomicU64::new(0),
+            evaluated: AtomicU64::new(0),
+        }
+    }
+
+    fn sender(&self, stream_name: &str) -> &mpsc::Sender<Job> {
+        let mut hasher = DefaultHasher::new();
+        stream_name.hash(&mut hasher);
+        &self.senders[hasher.finish() as usize % self.senders.len()]
+    }
+
+    fn enqueue(&self, stream_name: &str, events: RecordBatch) {
+        let job = Job {
+            stream_name: stream_name.to_owned(),
+            events,
+        };
+
+        // counted before the send, so that a worker never takes the depth below zero
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        match self.sender(stream_name).try_send(job) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => {
+                self.depth.fetch_sub(1, Ordering::Relaxed);
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                log::debug!(
+                    "alert queue is full, events of {} are not evaluated",
+                    stream_name
+                );
+            }
+            Err(TrySendError::Closed(_)) => {
+                self.depth.fetch_sub(1, Ordering::Relaxed);
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                log::error!(
+                    "alert workers are not running, events of {} are not evaluated",
+                    stream_name
+                );
+            }
+        }
+    }
+
+    fn evaluate(&self, job: Job) {
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+        if let Err(e) = STREAM_INFO.check_alerts(&job.stream_name, &job.events) {
+            log::error!("Error checking for alerts. {:?}", e);
+        }
+        self.evaluated.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueMetrics {
+    pub queue_depth: usize,
+    pub queue_capacity: usize,
+    pub dropped: u64,
+    pub evaluated: u64,
+}
+
+/// Queue the events ingested to a stream for alert evaluation. This never waits,
+/// the events are dropped from evaluation when the queue of the stream is full.
+pub fn enqueue(stream_name: &str, events: RecordBatch) {
+    QUEUE.enqueue(stream_name, events)
+}
+
+/// Start the workers evaluating queued events, each on its own thread. Targets are
+/// called from the worker, off the threads serving requests.
+pub fn start_workers() {
+    let receivers = std::mem::take(&mut *QUEUE.receivers.lock().unwrap());
+
+    for (id, receiver) in receivers.into_iter().enumerate() {
+        thread::Builder::new()
+            .name(format!("alert-worker-{}", id))
+            .spawn(move || System::new().block_on(run(receiver)))
+            .expect("alert worker thread is started");
+    }
+}
+
+pub fn metrics() -> QueueMetrics {
+    QueueMetrics {
+        queue_depth: QUEUE.depth.load(Ordering::Relaxed),
+        queue_capacity: QUEUE.capacity,
+        dropped: QUEUE.dropped.load(Ordering::Relaxed),
+        evaluated: QUEUE.evaluated.load(Ordering::Relaxed),
+    }
+}
+
+async fn run(mut receiver: mpsc::Receiver<Job>) {
+    while let Some(job) = receiver.recv().await {
+        QUEUE.evaluate(job);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    use datafusion::arrow::datatypes::Schema;
+    use datafusion::arrow::record_batch::RecordBatch;
+    use rstest::*;
+
+    use super::Queue;
+
+    fn events() -> RecordBatch {
+        RecordBatch::new_empty(Arc::new(Schema::empty()))
+    }
+
+    #[rstest]
+    fn stream_is_evaluated_by_one_worker() {
+        let queue = Queue::new(4, 10);
+        assert_eq!(queue.capacity, 8);
+
+        let sender = queue.sender("app");
+        assert!(sender.same_channel(queue.sender("app")));
+    }
+
+    #[rstest]
+    fn full_queue_drops_events() {
+        let queue = Queue::new(1, 1);
+        queue.enqueue("app", events());
+        queue.enqueue("app", events());
+
+        assert_eq!(queue.depth.load(Ordering::Relaxed), 1);
+        assert_eq!(queue.dropped.load(Ordering::Relaxed), 1);
+    }
+
+    #[rstest]
+    fn depth_is_zero_once_evaluated() {
+        let queue = Queue::new(1, 2);
+        queue.enqueue("app", events());
+        queue.enqueue("app", events());
+        assert_eq!(queue.depth.load(Ordering::Relaxed), 2);
+
+        let mut receiver = queue.receivers.lock().unwrap().pop().unwrap();
+        while let Ok(job) = receiver.try_recv() {
+            queue.evaluate(job);
+        }
+
+        assert_eq!(queue.depth.load(Ordering::Relaxed), 0);
+        assert_eq!(queue.evaluated.load(Ordering::Relaxed), 2);
+        assert_eq!(queue.dropped.load(Ordering::Relaxed), 0);
+    }
+}
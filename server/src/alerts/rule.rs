@@ -16,6 +16,7 @@
  *
  */
 
+use datafusion::arrow::record_batch::RecordBatch;
 use serde::{Deserialize, Serialize};
 use std::sync::atomic::{AtomicU32, Ordering};
 
@@ -34,9 +35,10 @@ pub enum Rule {
 }
 
 impl Rule {
-    pub fn resolves(&self, event: &serde_json::Value) -> AlertState {
+    /// State of the alert after each event of the batch, in order
+    pub fn resolves(&self, events: &RecordBatch) -> Vec<AlertState> {
         match self {
-            Rule::Column(rule) => rule.resolves(event),
+            Rule::Column(rule) => rule.resolves(events),
         }
     }
 
@@ -61,10 +63,10 @@ pub enum ColumnRule {
 }
 
 impl ColumnRule {
-    fn resolves(&self, event: &serde_json::Value) -> AlertState {
+    fn resolves(&self, events: &RecordBatch) -> Vec<AlertState> {
         match self {
-            Self::ConsecutiveNumeric(rule) => rule.resolves(event),
-            Self::ConsecutiveString(rule) => rule.resolves(event),
+            Self::ConsecutiveNumeric(rule) => rule.resolves(events),
+            Self::ConsecutiveString(rule) => rule.resolves(events),
         }
     }
 
@@ -161,12 +163,8 @@ pub struct ConsecutiveNumericRule {
 }
 
 impl ConsecutiveNumericRule {
-    fn resolves(&self, event: &serde_json::Value) -> AlertState {
-        if self.base_rule.resolves(event) {
-            self.state.update_and_fetch_state()
-        } else {
-            self.state.fetch_state()
-        }
+    fn resolves(&self, events: &RecordBatch) -> Vec<AlertState> {
+        self.state.fetch_states(self.base_rule.resolves(events))
     }
 }
 
@@ -180,12 +178,8 @@ pub struct ConsecutiveStringRule {
 }
 
 impl ConsecutiveStringRule {
-    fn resolves(&self, event: &serde_json::Value) -> AlertState {
-        if self.base_rule.resolves(event) {
-            self.state.update_and_fetch_state()
-        } else {
-            self.state.fetch_state()
-        }
+    fn resolves(&self, events: &RecordBatch) -> Vec<AlertState> {
+        self.state.fetch_states(self.base_rule.resolves(events))
     }
 }
 
@@ -210,6 +204,19 @@ impl ConsecutiveRepeatState {
         self._fetch_state(false)
     }
 
+    fn fetch_states(&self, matches: Vec<bool>) -> Vec<AlertState> {
+        matches
+            .into_iter()
+            .map(|matched| {
+                if matched {
+                    self.update_and_fetch_state()
+                } else {
+                    self.fetch_state()
+                }
+            })
+            .collect()
+    }
+
     fn _fetch_state(&self, update: bool) -> AlertState {
         let mut repeated = self.repeated.load(Ordering::Acquire);
         let mut state = AlertState::Listening;
@@ -239,11 +246,33 @@ impl ConsecutiveRepeatState {
 #[cfg(test)]
 mod tests {
     use std::sync::atomic::AtomicU32;
+    use std::sync::Arc;
 
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::record_batch::RecordBatch;
     use rstest::*;
+    use serde_json::json;
 
+    use super::base::{NumericRule, StringRule};
     use super::{AlertState, ConsecutiveRepeatState};
 
+    #[fixture]
+    pub fn events() -> RecordBatch {
+        let schema = Schema::new(vec![
+            Field::new("status", DataType::Int64, true),
+            Field::new("message", DataType::Utf8, true),
+        ]);
+        RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(Int64Array::from(vec![Some(200), Some(500), None])),
+                Arc::new(StringArray::from(vec![Some("ok"), Some("Failed"), None])),
+            ],
+        )
+        .unwrap()
+    }
+
     #[fixture]
     pub fn rule(#[default(5)] repeats: u32, #[default(0)] repeated: u32) -> ConsecutiveRepeatState {
         ConsecutiveRepeatState {
@@ -271,13 +300,54 @@ mod tests {
         assert_eq!(rule.update_and_fetch_state(), AlertState::Listening);
         assert_eq!(rule.update_and_fetch_state(), AlertState::SetToFiring);
     }
+
+    #[rstest]
+    #[case::greater_than(">", 400, vec![false, true, false])]
+    #[case::not_equal("!=", 200, vec![false, true, false])]
+    fn numeric_rule_on_batch(
+        events: RecordBatch,
+        #[case] operator: &str,
+        #[case] value: i64,
+        #[case] expected: Vec<bool>,
+    ) {
+        let rule: NumericRule = serde_json::from_value(
+            json!({ "column": "status", "operator": operator, "value": value }),
+        )
+        .unwrap();
+        assert_eq!(rule.resolves(&events), expected);
+    }
+
+    #[rstest]
+    fn string_rule_on_batch(events: RecordBatch) {
+        let rule: StringRule = serde_json::from_value(
+            json!({ "column": "message", "operator": "contains", "ignoreCase": true, "value": "fail" }),
+        )
+        .unwrap();
+        assert_eq!(rule.resolves(&events), vec![false, true, false]);
+    }
+
+    #[rstest]
+    fn missing_column_never_matches(events: RecordBatch) {
+        let rule: StringRule =
+            serde_json::from_value(json!({ "column": "host", "value": "a" })).unwrap();
+        assert_eq!(rule.resolves(&events), vec![false; 3]);
+    }
 }
 
 pub mod base {
+    use datafusion::arrow::array::{Array, ArrayRef, Float64Array, StringArray};
+    use datafusion::arrow::compute::cast;
+    use datafusion::arrow::datatypes::DataType;
+    use datafusion::arrow::record_batch::RecordBatch;
     use serde::{Deserialize, Serialize};
 
     use self::ops::{NumericOperator, StringOperator};
 
+    fn column<'a>(events: &'a RecordBatch, name: &str) -> Option<&'a ArrayRef> {
+        let index = events.schema().index_of(name).ok()?;
+        Some(events.column(index))
+    }
+
     #[derive(Debug, Serialize, Deserialize)]
     #[serde(rename_all = "camelCase")]
     pub struct NumericRule {
@@ -289,28 +359,34 @@ pub mod base {
     }
 
     impl NumericRule {
-        pub fn resolves(&self, event: &serde_json::Value) -> bool {
-            let number = match event.get(&self.column).expect("column exists") {
-                serde_json::Value::Number(number) => number,
-                _ => unreachable!("right rule is set for right column type"),
+        /// Whether each event of the batch matches the rule, an event
+        /// with a null value never matches
+        pub fn resolves(&self, events: &RecordBatch) -> Vec<bool> {
+            // every numeric type is compared as float
+            let numbers = column(events, &self.column)
+                .and_then(|column| cast(column, &DataType::Float64).ok());
+            let Some(numbers) = numbers
+                .as_ref()
+                .and_then(|numbers| numbers.as_any().downcast_ref::<Float64Array>())
+            else {
+                return vec![false; events.num_rows()];
             };
-
-            match self.operator {
-                NumericOperator::EqualTo => number == &self.value,
-                NumericOperator::NotEqualTo => number != &self.value,
-                NumericOperator::GreaterThan => {
-                    number.as_f64().unwrap() > self.value.as_f64().unwrap()
-                }
-                NumericOperator::GreaterThanEquals => {
-                    number.as_f64().unwrap() >= self.value.as_f64().unwrap()
-                }
-                NumericOperator::LessThan => {
-                    number.as_f64().unwrap() < self.value.as_f64().unwrap()
-                }
-                NumericOperator::LessThanEquals => {
-                    number.as_f64().unwrap() <= self.value.as_f64().unwrap()
-                }
-            }
+            let value = self.value.as_f64().unwrap();
+
+            numbers
+                .iter()
+                .map(|number| match number {
+                    Some(number) => match self.operator {
+                        NumericOperator::EqualTo => number == value,
+                        NumericOperator::NotEqualTo => number != value,
+                        NumericOperator::GreaterThan => number > value,
+                        NumericOperator::GreaterThanEquals => number >= value,
+                        NumericOperator::LessThan => number < value,
+                        NumericOperator::LessThanEquals => number <= value,
+                    },
+                    None => false,
+                })
+                .collect()
         }
     }
 
@@ -325,12 +401,22 @@ pub mod base {
     }
 
     impl StringRule {
-        pub fn resolves(&self, event: &serde_json::Value) -> bool {
-            let string = match event.get(&self.column).expect("column exists") {
-                serde_json::Value::String(s) => s,
-                _ => unreachable!("right rule is set for right column type"),
+        /// Whether each event of the batch matches the rule, an event
+        /// with a null value never matches
+        pub fn resolves(&self, events: &RecordBatch) -> Vec<bool> {
+            let Some(strings) = column(events, &self.column)
+                .and_then(|column| column.as_any().downcast_ref::<StringArray>())
+            else {
+                return vec![false; events.num_rows()];
             };
 
+            strings
+                .iter()
+                .map(|string| string.map_or(false, |string| self.matches(string)))
+                .collect()
+        }
+
+        fn matches(&self, string: &str) -> bool {
             if self.ignore_case.unwrap_or_default() {
                 match self.operator {
                     StringOperator::Exact => string.eq_ignore_ascii_case(&self.value),
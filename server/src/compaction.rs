@@ -0,0 +1,573 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::parquet::arrow::ArrowWriter;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use crate::manifest::{self, DataFile};
+use crate::metadata::STREAM_INFO;
+use crate::option::CONFIG;
+use crate::storage::{DataObject, ObjectStorage};
+use crate::utils;
+
+use self::error::CompactionError;
+
+// compacted files are written here before the upload, outside of the
+// stream directories so that the sync does not pick them up
+const LOCAL_COMPACTION_DIR: &str = ".compaction";
+
+// minutes the objects merged into a compacted one are kept after the swap,
+// queries planned before it still read them
+const SOURCES_KEPT_FOR: i64 = 15;
+
+/// Compacted objects of a stream, kept in `.compaction.json` of the stream.
+/// Queries read the compacted object of a period in place of everything
+/// else under it once it is recorded here, so a single put swaps them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Compacted object of a day `date=../` or an hour `date=../hour=../`
+    #[serde(default)]
+    pub compacted: BTreeMap<String, Compacted>,
+    /// Object being written for a period, it is never read
+    #[serde(default)]
+    pub pending: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Compacted {
+    pub object: String,
+    /// Objects merged into this one which are not deleted yet
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<String>,
+    /// When queries started to read this object in place of its sources
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub swapped_at: Option<DateTime<Utc>>,
+}
+
+impl Compacted {
+    /// Whether no query reads the sources anymore, so that these can be deleted
+    pub fn sources_expired(&self, now: DateTime<Utc>) -> bool {
+        self.swapped_at.map_or(true, |swapped_at| {
+            swapped_at + Duration::minutes(SOURCES_KEPT_FOR) <= now
+        })
+    }
+}
+
+impl Manifest {
+    /// Prefixes and objects to read for the prefixes of a query. A compacted period is
+    /// read whole, a period with a pending object is split so that it is left out.
+    /// This is only used for hours without a manifest. Objects uploaded to a compacted
+    /// period later on are added to the manifest of their hour, so those are read from
+    /// there until the next compaction merges them.
+    pub fn resolve(
+        &self,
+        stream_name: &str,
+        data_granularity: u32,
+        prefixes: Vec<String>,
+    ) -> Vec<String> {
+        let root = format!("{}/", stream_name);
+        let mut resolved = Vec::new();
+
+        for prefix in prefixes {
+            match prefix.strip_prefix(&root) {
+                Some(period) => self.resolve_period(&root, period, data_granularity, &mut resolved),
+                None => resolved.push(prefix),
+            }
+        }
+
+        // prefixes are in time order, the ones of a compacted period are next to each other
+        resolved.dedup();
+        resolved
+    }
+
+    fn resolve_period(
+        &self,
+        root: &str,
+        period: &str,
+        data_granularity: u32,
+        resolved: &mut Vec<String>,
+    ) {
+        let (day, hour) = periods_of(period);
+
+        let compacted = self
+            .compacted
+            .get(day)
+            .or_else(|| hour.and_then(|hour| self.compacted.get(hour)));
+        if let Some(compacted) = compacted {
+            resolved.push(compacted.object.clone());
+            return;
+        }
+
+        if period == day && self.has_objects_in(day) {
+            for hour in 0..24 {
+                let hour = day.to_owned() + &utils::hour_to_prefix(hour);
+                self.resolve_period(root, &hour, data_granularity, resolved);
+            }
+        } else if hour == Some(period) && self.pending.contains_key(period) {
+            for minute in (0..60).step_by(data_granularity as usize) {
+                if let Some(minute) = utils::minute_to_prefix(minute, data_granularity) {
+                    resolved.push(format!("{}{}{}", root, period, minute));
+                }
+            }
+        } else {
+            resolved.push(root.to_owned() + period);
+        }
+    }
+
+    /// Whether queries read the object, these leave out pending objects
+    /// and everything in a compacted period but its compacted object
+    pub fn is_read(&self, stream_name: &str, key: &str) -> bool {
+        let Some(path) = key.strip_prefix(&format!("{}/", stream_name)) else {
+            return false;
+        };
+        if self.pending.values().any(|object| object == key) {
+            return false;
+        }
+
+        let (day, hour) = periods_of(path);
+        let compacted = self
+            .compacted
+            .get(day)
+            .or_else(|| hour.and_then(|hour| self.compacted.get(hour)));
+
+        compacted.map_or(true, |compacted| compacted.object == key)
+    }
+
+    /// Drop the periods of a day which is gone from the stream,
+    /// returns whether anything was dropped
+    pub fn forget(&mut self, day: &str) -> bool {
+        let before = self.compacted.len() + self.pending.len();
+        self.compacted.retain(|period, _| !period.starts_with(day));
+        self.pending.retain(|period, _| !period.starts_with(day));
+
+        before != self.compacted.len() + self.pending.len()
+    }
+
+    // whether any object of this manifest is under the day
+    fn has_objects_in(&self, day: &str) -> bool {
+        self.compacted
+            .keys()
+            .chain(self.pending.keys())
+            .any(|period| period.starts_with(day))
+    }
+}
+
+// Day and hour a prefix or key of a stream is in, e.g. `date=2022-10-15/` and
+// `date=2022-10-15/hour=10/` for `date=2022-10-15/hour=10/minute=00/`
+pub fn periods_of(path: &str) -> (&str, Option<&str>) {
+    let day_end = path.find('/').map_or(path.len(), |end| end + 1);
+    let rest = &path[day_end..];
+    let hour = rest
+        .find('/')
+        .filter(|_| rest.starts_with("hour="))
+        .map(|end| &path[..day_end + end + 1]);
+
+    (&path[..day_end], hour)
+}
+
+// Time a day or an hour prefix starts at
+fn period_start(period: &str) -> Option<NaiveDateTime> {
+    let mut parts = period.split('/');
+    let date = parts.next()?.strip_prefix("date=")?;
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    let hour = match parts.next() {
+        Some(hour) if !hour.is_empty() => hour.strip_prefix("hour=")?.parse().ok()?,
+        _ => 0,
+    };
+
+    date.and_hms_opt(hour, 0, 0)
+}
+
+/// Merge the objects of every past hour into one object, and of every past day
+/// once it is over. A period is compacted once it is older than the compaction
+/// delay, and again when objects show up in it later on.
+pub async fn run(storage: &impl ObjectStorage) {
+    let Some(delay) = CONFIG.parseable.compaction_delay else {
+        return;
+    };
+    for stream in STREAM_INFO.list_streams() {
+        if let Err(e) = compact_stream(storage, &stream, Duration::hours(delay as i64)).await {
+            log::error!("could not compact stream {}. {:?}", stream, e);
+        }
+    }
+}
+
+async fn compact_stream(
+    storage: &impl ObjectStorage,
+    stream_name: &str,
+    delay: Duration,
+) -> Result<(), CompactionError> {
+    // a stream without a schema has no data yet
+    let Some(schema) = STREAM_INFO.schema(stream_name)? else {
+        return Ok(());
+    };
+    let schema = Arc::new(schema);
+
+    let mut manifest = storage.get_compaction(stream_name).await?;
+    clean_up(storage, stream_name, &mut manifest, Utc::now()).await?;
+
+    let root = format!("{}/", stream_name);
+    let mut days: BTreeMap<String, Vec<DataObject>> = BTreeMap::new();
+    for object in storage.list_objects(&format!("{}date=", root)).await? {
+        let Some(path) = object.key.strip_prefix(&root) else {
+            continue;
+        };
+        if !path.ends_with(".data.parquet") {
+            continue;
+        }
+        let day = periods_of(path).0.to_owned();
+        days.entry(day).or_default().push(object);
+    }
+
+    let cutoff = Utc::now().naive_utc() - delay;
+    for (day, objects) in days {
+        let Some(start) = period_start(&day) else {
+            continue;
+        };
+
+        // objects missing from the manifests of a day are not read by queries, these are
+        // left as they are. Of an hour without a manifest, queries read the listed objects
+        // other than sources of a compaction which are not deleted yet
+        let day_files =
+            manifest::day_files(storage, stream_name, &day, manifest::day_hours(&day)).await?;
+        let objects: Vec<DataObject> = match day_files {
+            Some(day_files) => {
+                let unlisted = objects.into_iter().filter(|object| {
+                    manifest::hour_of(&object.key)
+                        .map_or(false, |hour| day_files.unlisted.iter().any(|h| h == hour))
+                        && manifest.is_read(stream_name, &object.key)
+                });
+                day_files
+                    .files
+                    .into_iter()
+                    .map(DataObject::from)
+                    .chain(unlisted)
+                    .collect()
+            }
+            None => objects
+                .into_iter()
+                .filter(|object| manifest.is_read(stream_name, &object.key))
+                .collect(),
+        };
+
+        // a day which is over is compacted as a whole, without its hours first
+        if start + Duration::days(1) <= cutoff {
+            if needs_compaction(&manifest, &day, &objects) {
+                compact(storage, stream_name, &schema, &mut manifest, &day, &objects).await?;
+            }
+            continue;
+        }
+
+        let mut hours: BTreeMap<&str, Vec<DataObject>> = BTreeMap::new();
+        for object in &objects {
+            let hour = object
+                .key
+                .strip_prefix(&root)
+                .and_then(|path| periods_of(path).1);
+            if let Some(hour) = hour {
+                hours.entry(hour).or_default().push(object.clone());
+            }
+        }
+
+        for (hour, objects) in hours {
+            let Some(start) = period_start(hour) else {
+                continue;
+            };
+            if start + Duration::hours(1) <= cutoff && needs_compaction(&manifest, hour, &objects) {
+                compact(storage, stream_name, &schema, &mut manifest, hour, &objects).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// A period needs compaction while it has any object other than its compacted one
+fn needs_compaction(manifest: &Manifest, period: &str, objects: &[DataObject]) -> bool {
+    let compacted = manifest
+        .compacted
+        .get(period)
+        .map(|compacted| compacted.object.as_str());
+
+    objects
+        .iter()
+        .any(|object| Some(object.key.as_str()) != compacted)
+}
+
+async fn compact(
+    storage: &impl ObjectStorage,
+    stream_name: &str,
+    schema: &SchemaRef,
+    manifest: &mut Manifest,
+    period: &str,
+    sources: &[DataObject],
+) -> Result<(), CompactionError> {
+    let id = utils::uuid::gen().simple();
+    let object = format!("{}/{}compacted.{}.data.parquet", stream_name, period, id);
+
+    // recorded before it is written, a compaction which does not finish
+    // leaves no object behind which queries could read twice
+    manifest.pending.insert(period.to_owned(), object.clone());
+    storage.put_compaction(stream_name, manifest).await?;
+
+    let keys: Vec<String> = sources.iter().map(|source| source.key.clone()).collect();
+    let removed: u64 = sources.iter().map(|source| source.size).sum();
+    let dir = CONFIG.parseable.local_disk_path.join(LOCAL_COMPACTION_DIR);
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}.parquet", id));
+
+    let uploaded = match write_local(storage, stream_name, schema, &keys, &path).await {
+        Ok(()) => match DataFile::from_parquet(object.clone(), &path) {
+            Ok(file) => storage
+                .upload_file(&object, path.to_str().expect("path is valid unicode"))
+                .await
+                .map(|()| file)
+                .map_err(CompactionError::from),
+            Err(e) => Err(e.into()),
+        },
+        Err(e) => Err(e),
+    };
+    let _ = fs::remove_file(&path);
+    let file = uploaded?;
+    let size = file.size;
+
+    // the swap, queries read the compacted object from here on. Compacted
+    // hours of a day are among the sources of the day
+    manifest::replace_files(storage, stream_name, period, &keys, file).await?;
+    manifest.pending.remove(period);
+
+    // sources of earlier compactions in the period which are not deleted yet
+    let mut pending_sources: Vec<String> = manifest
+        .compacted
+        .iter()
+        .filter(|(compacted, _)| compacted.starts_with(period))
+        .flat_map(|(_, compacted)| compacted.sources.iter().cloned())
+        .collect();
+    pending_sources.extend(keys);
+    manifest
+        .compacted
+        .retain(|compacted, _| !compacted.starts_with(period));
+    manifest.compacted.insert(
+        period.to_owned(),
+        Compacted {
+            object,
+            sources: pending_sources,
+            swapped_at: Some(Utc::now()),
+        },
+    );
+    storage.put_compaction(stream_name, manifest).await?;
+
+    let stats = STREAM_INFO.replace_storage_size(stream_name, removed, size)?;
+    if let Err(e) = storage.put_stats(stream_name, &stats).await {
+        log::warn!("Error updating stats to s3 due to error [{}]", e);
+    }
+
+    log::info!(
+        "compacted {} objects of stream {} into {}",
+        sources.len(),
+        stream_name,
+        period
+    );
+
+    Ok(())
+}
+
+// Write the rows of the objects into a single local parquet file
+async fn write_local(
+    storage: &impl ObjectStorage,
+    stream_name: &str,
+    schema: &SchemaRef,
+    keys: &[String],
+    path: &Path,
+) -> Result<(), CompactionError> {
+    let props = STREAM_INFO
+        .parquet(stream_name)
+        .unwrap_or_default()
+        .writer_properties(schema);
+
+    let mut records = storage.read_objects(keys, Arc::clone(schema)).await?;
+    let mut writer = ArrowWriter::try_new(File::create(path)?, Arc::clone(schema), Some(props))?;
+    while let Some(record) = records.next().await {
+        writer.write(&record?)?;
+    }
+    writer.close()?;
+
+    Ok(())
+}
+
+// Delete what earlier compactions left behind, the object of a compaction which
+// did not finish and the objects merged into a compacted one once no query reads them
+async fn clean_up(
+    storage: &impl ObjectStorage,
+    stream_name: &str,
+    manifest: &mut Manifest,
+    now: DateTime<Utc>,
+) -> Result<(), CompactionError> {
+    let objects: Vec<String> = manifest
+        .pending
+        .values()
+        .chain(
+            manifest
+                .compacted
+                .values()
+                .filter(|compacted| compacted.sources_expired(now))
+                .flat_map(|compacted| &compacted.sources),
+        )
+        .cloned()
+        .collect();
+    if objects.is_empty() {
+        return Ok(());
+    }
+
+    storage.delete_objects(&objects).await?;
+
+    manifest.pending.clear();
+    for compacted in manifest.compacted.values_mut() {
+        if compacted.sources_expired(now) {
+            compacted.sources.clear();
+        }
+    }
+    storage.put_compaction(stream_name, manifest).await?;
+
+    Ok(())
+}
+
+pub mod error {
+    use datafusion::arrow::error::ArrowError;
+    use datafusion::parquet::errors::ParquetError;
+
+    use crate::metadata::error::stream_info::MetadataError;
+    use crate::storage::ObjectStorageError;
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum CompactionError {
+        #[error("Metadata Error: {0}")]
+        Metadata(#[from] MetadataError),
+        #[error("Object storage Error: {0}")]
+        ObjectStorage(#[from] ObjectStorageError),
+        #[error("Could not write compacted file: {0}")]
+        Io(#[from] std::io::Error),
+        #[error("Could not read objects to compact: {0}")]
+        Arrow(#[from] ArrowError),
+        #[error("Could not write compacted file: {0}")]
+        Parquet(#[from] ParquetError),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use serde_json::json;
+
+    use chrono::{Duration, Utc};
+
+    use super::{period_start, Compacted, Manifest};
+
+    #[fixture]
+    fn manifest() -> Manifest {
+        serde_json::from_value(json!({
+            "compacted": {
+                "date=2022-10-14/": { "object": "s/date=2022-10-14/compacted.a.data.parquet" },
+                "date=2022-10-15/hour=09/": { "object": "s/date=2022-10-15/hour=09/compacted.b.data.parquet" },
+            },
+            "pending": {
+                "date=2022-10-15/hour=10/": "s/date=2022-10-15/hour=10/compacted.c.data.parquet",
+            },
+        }))
+        .unwrap()
+    }
+
+    #[rstest]
+    #[case::compacted_day(
+        &["s/date=2022-10-14/hour=23/minute=58/", "s/date=2022-10-14/hour=23/minute=59/"],
+        &["s/date=2022-10-14/compacted.a.data.parquet"]
+    )]
+    #[case::compacted_hour(
+        &["s/date=2022-10-15/hour=09/minute=59/"],
+        &["s/date=2022-10-15/hour=09/compacted.b.data.parquet"]
+    )]
+    #[case::pending_hour(
+        &["s/date=2022-10-15/hour=10/"],
+        &[
+            "s/date=2022-10-15/hour=10/minute=00-29/",
+            "s/date=2022-10-15/hour=10/minute=30-59/"
+        ]
+    )]
+    #[case::minute_of_pending_hour(
+        &["s/date=2022-10-15/hour=10/minute=00-29/"],
+        &["s/date=2022-10-15/hour=10/minute=00-29/"]
+    )]
+    #[case::untouched_day(&["s/date=2022-10-16/"], &["s/date=2022-10-16/"])]
+    fn resolve(manifest: Manifest, #[case] prefixes: &[&str], #[case] right: &[&str]) {
+        let prefixes = prefixes.iter().map(|prefix| prefix.to_string()).collect();
+        let resolved = manifest.resolve("s", 30, prefixes);
+        assert_eq!(resolved, right);
+    }
+
+    #[rstest]
+    fn day_with_compacted_hours_is_split(manifest: Manifest) {
+        let resolved = manifest.resolve("s", 30, vec!["s/date=2022-10-15/".to_string()]);
+        // hours 00 to 08, the compacted hour 09, two halves of 10 and hours 11 to 23
+        assert_eq!(resolved.len(), 25);
+        assert_eq!(
+            resolved[9],
+            "s/date=2022-10-15/hour=09/compacted.b.data.parquet"
+        );
+        assert_eq!(resolved[10], "s/date=2022-10-15/hour=10/minute=00-29/");
+        assert_eq!(resolved[12], "s/date=2022-10-15/hour=11/");
+    }
+
+    #[rstest]
+    fn forget_day(mut manifest: Manifest) {
+        assert!(manifest.forget("date=2022-10-15/"));
+        assert_eq!(manifest.compacted.len(), 1);
+        assert!(manifest.pending.is_empty());
+        assert!(!manifest.forget("date=2022-10-15/"));
+    }
+
+    #[rstest]
+    #[case::just_swapped(Some(1), false)]
+    #[case::swapped_long_ago(Some(60), true)]
+    #[case::swapped_before_it_was_recorded(None, true)]
+    fn sources_are_kept_after_the_swap(#[case] minutes_ago: Option<i64>, #[case] expired: bool) {
+        let now = Utc::now();
+        let compacted = Compacted {
+            object: "s/date=2022-10-14/compacted.a.data.parquet".to_string(),
+            sources: vec!["s/date=2022-10-14/hour=00/minute=00/a.data.parquet".to_string()],
+            swapped_at: minutes_ago.map(|minutes| now - Duration::minutes(minutes)),
+        };
+        assert_eq!(compacted.sources_expired(now), expired);
+    }
+
+    #[rstest]
+    #[case("date=2022-10-15/", Some("2022-10-15T00:00:00"))]
+    #[case("date=2022-10-15/hour=10/", Some("2022-10-15T10:00:00"))]
+    #[case(".schema", None)]
+    fn start_of_period(#[case] period: &str, #[case] start: Option<&str>) {
+        let start = start.map(|start| start.parse().unwrap());
+        assert_eq!(period_start(period), start);
+    }
+}
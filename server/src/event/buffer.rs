@@ -0,0 +1,404 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use datafusion::arrow::record_batch::RecordBatch;
+
+use crate::storage::StorageDir;
+
+use super::error::StreamWriterError;
+use super::init_new_stream_writer_file;
+use super::wal::{WalOptions, WalWriter};
+
+/// In memory buffer of a stream. Events are spread over a few independently locked
+/// shards, each of which is written to the log file as a single batch once it is
+/// large or old enough. Only these writes take the lock of the log file.
+///
+/// Everything a write needs is given when the buffer is created, a write does not
+/// look up the stream, which may be locked by the caller.
+pub struct StreamBuffer {
+    shards: Vec<Mutex<Shard>>,
+    next_shard: AtomicUsize,
+    writer: Mutex<Option<WalWriter>>,
+    dir: StorageDir,
+    data_granularity: u32,
+    // rows a shard collects before it is written out
+    max_rows: usize,
+    wal: WalOptions,
+}
+
+#[derive(Default)]
+struct Shard {
+    batches: Vec<RecordBatch>,
+    // time slot the batches arrived in, a shard only has batches of one slot
+    slot: String,
+    rows: usize,
+    // when the oldest batch in the shard was added
+    since: Option<Instant>,
+}
+
+impl Shard {
+    fn push(&mut self, record: &RecordBatch, slot: String) {
+        self.batches.push(record.clone());
+        self.slot = slot;
+        self.rows += record.num_rows();
+        self.since.get_or_insert_with(Instant::now);
+    }
+
+    fn take(&mut self) -> (String, Vec<RecordBatch>) {
+        self.rows = 0;
+        self.since = None;
+        (
+            std::mem::take(&mut self.slot),
+            std::mem::take(&mut self.batches),
+        )
+    }
+}
+
+impl StreamBuffer {
+    pub fn new(dir: StorageDir, data_granularity: u32, max_rows: usize, wal: WalOptions) -> Self {
+        Self {
+            shards: (0..num_cpus::get()).map(|_| Mutex::default()).collect(),
+            next_shard: AtomicUsize::new(0),
+            writer: Mutex::new(None),
+            dir,
+            data_granularity,
+            max_rows,
+            wal,
+        }
+    }
+
+    pub fn append(&self, record: &RecordBatch) -> Result<(), StreamWriterError> {
+        // the slot of an event is the time it arrives, not the time it is written out
+        let slot = self.dir.slot_by_current_time(self.data_granularity);
+        self.append_in_slot(record, slot)
+    }
+
+    fn append_in_slot(&self, record: &RecordBatch, slot: String) -> Result<(), StreamWriterError> {
+        // round robin, so that concurrent requests rarely wait on the same shard
+        let position = self.next_shard.fetch_add(1, Ordering::Relaxed) % self.shards.len();
+        let mut shard = self.shards[position]
+            .lock()
+            .map_err(|_| StreamWriterError::MutexPoisoned)?;
+
+        // only batches of the same schema and time slot can be merged
+        if matches!(shard.batches.first(), Some(first) if first.schema() != record.schema() || shard.slot != slot)
+        {
+            let (slot, batches) = shard.take();
+            self.write(slot, batches)?;
+        }
+
+        shard.push(record, slot);
+
+        if shard.rows >= self.max_rows {
+            let (slot, batches) = shard.take();
+            drop(shard);
+            self.write(slot, batches)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the buffered events to the log file, only those buffered
+    /// for longer than `max_age` when it is given
+    pub fn flush(&self, max_age: Option<Duration>) -> Result<(), StreamWriterError> {
+        for shard in &self.shards {
+            let (slot, batches) = {
+                let mut shard = shard.lock().map_err(|_| StreamWriterError::MutexPoisoned)?;
+                match (max_age, shard.since) {
+                    (Some(max_age), Some(since)) if since.elapsed() < max_age => continue,
+                    _ => shard.take(),
+                }
+            };
+            self.write(slot, batches)?;
+        }
+
+        Ok(())
+    }
+
+    /// Flush the buffered events and the log file to disk
+    pub fn sync(&self) -> Result<(), StreamWriterError> {
+        self.flush(None)?;
+
+        let mut writer = self
+            .writer
+            .lock()
+            .map_err(|_| StreamWriterError::MutexPoisoned)?;
+        if let Some(ref mut writer) = *writer {
+            writer.sync()?;
+        }
+
+        Ok(())
+    }
+
+    /// Flush the buffered events and close the log file,
+    /// the next write starts a new file
+    pub fn finish(&self) -> Result<(), StreamWriterError> {
+        self.flush(None)?;
+
+        let writer = self
+            .writer
+            .lock()
+            .map_err(|_| StreamWriterError::MutexPoisoned)?
+            .take();
+        if let Some(writer) = writer {
+            writer.finish()?;
+        }
+
+        Ok(())
+    }
+
+    /// Path of the log file which is being written to
+    pub fn open_file(&self) -> Result<Option<PathBuf>, StreamWriterError> {
+        let writer = self
+            .writer
+            .lock()
+            .map_err(|_| StreamWriterError::MutexPoisoned)?;
+
+        Ok(writer.as_ref().map(|writer| writer.path().to_path_buf()))
+    }
+
+    fn write(&self, slot: String, batches: Vec<RecordBatch>) -> Result<(), StreamWriterError> {
+        let Some(first) = batches.first() else {
+            return Ok(());
+        };
+        // merged before taking the lock of the log file
+        let record = RecordBatch::concat(&first.schema(), &batches)?;
+
+        let mut writer = self
+            .writer
+            .lock()
+            .map_err(|_| StreamWriterError::MutexPoisoned)?;
+
+        // a file only has events of the time slot in its name, this is what
+        // keeps the prefixes a query lists complete. A file has a single schema,
+        // a widened schema starts a new one.
+        if matches!(*writer, Some(ref writer) if writer.slot() != slot || writer.schema() != &record.schema())
+        {
+            writer.take().expect("writer is open").finish()?;
+        }
+
+        match *writer {
+            Some(ref mut writer) => writer.write(&record)?,
+            None => {
+                writer.replace(init_new_stream_writer_file(
+                    &self.dir, slot, &record, self.wal,
+                )?);
+            }
+        }
+
+        // closed right away, so that it is uploaded with the next sync
+        if matches!(*writer, Some(ref writer) if writer.is_full()) {
+            writer.take().expect("writer is open").finish()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::ipc::reader::StreamReader;
+    use datafusion::arrow::record_batch::RecordBatch;
+    use rstest::*;
+
+    use super::StreamBuffer;
+    use crate::event::wal::WalOptions;
+    use crate::storage::{StorageDir, LEGACY_DATA_GRANULARITY};
+    use crate::utils;
+
+    #[fixture]
+    fn dir() -> PathBuf {
+        std::env::temp_dir().join(format!("parseable-{}", utils::uuid::gen().simple()))
+    }
+
+    fn buffer(dir: &PathBuf, max_rows: usize) -> StreamBuffer {
+        let wal = WalOptions {
+            max_rows: None,
+            max_bytes: None,
+            fsync: false,
+        };
+        StreamBuffer::new(
+            StorageDir::with_data_path("test", dir.clone()),
+            LEGACY_DATA_GRANULARITY,
+            max_rows,
+            wal,
+        )
+    }
+
+    // a buffer with a single shard, every append goes to it
+    fn single_shard(dir: &PathBuf, max_rows: usize) -> StreamBuffer {
+        let mut buffer = buffer(dir, max_rows);
+        buffer.shards.truncate(1);
+        buffer
+    }
+
+    fn int_record() -> RecordBatch {
+        let schema = Schema::new(vec![Field::new("a", DataType::Int64, true)]);
+        RecordBatch::try_new(Arc::new(schema), vec![Arc::new(Int64Array::from(vec![1]))]).unwrap()
+    }
+
+    fn string_record() -> RecordBatch {
+        let schema = Schema::new(vec![Field::new("a", DataType::Utf8, true)]);
+        RecordBatch::try_new(
+            Arc::new(schema),
+            vec![Arc::new(StringArray::from(vec!["a"]))],
+        )
+        .unwrap()
+    }
+
+    fn buffered_rows(buffer: &StreamBuffer) -> Vec<usize> {
+        buffer
+            .shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().rows)
+            .collect()
+    }
+
+    // rows in the finished log files of the directory, one entry per file
+    fn written_rows(dir: &PathBuf) -> Vec<usize> {
+        let mut rows: Vec<usize> = fs::read_dir(dir)
+            .unwrap()
+            .flatten()
+            .map(|file| {
+                StreamReader::try_new(File::open(file.path()).unwrap(), None)
+                    .unwrap()
+                    .map(|record| record.unwrap().num_rows())
+                    .sum()
+            })
+            .collect();
+        rows.sort_unstable();
+        rows
+    }
+
+    #[rstest]
+    fn appends_round_robin(dir: PathBuf) {
+        let buffer = buffer(&dir, 100);
+        let shards = buffer.shards.len();
+        for _ in 0..shards * 2 {
+            buffer.append(&int_record()).unwrap();
+        }
+
+        assert_eq!(buffered_rows(&buffer), vec![2; shards]);
+        assert!(buffer.open_file().unwrap().is_none());
+    }
+
+    #[rstest]
+    fn writes_out_at_row_limit(dir: PathBuf) {
+        let buffer = single_shard(&dir, 2);
+        buffer.append(&int_record()).unwrap();
+        assert!(buffer.open_file().unwrap().is_none());
+
+        buffer.append(&int_record()).unwrap();
+        assert_eq!(buffered_rows(&buffer), [0]);
+        assert!(buffer.open_file().unwrap().is_some());
+
+        buffer.finish().unwrap();
+        assert_eq!(written_rows(&dir), [2]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[rstest]
+    fn writes_out_on_schema_change(dir: PathBuf) {
+        let buffer = single_shard(&dir, 100);
+        buffer.append(&int_record()).unwrap();
+        buffer.append(&string_record()).unwrap();
+
+        // the batch of the earlier schema is written, the new one stays buffered
+        assert_eq!(buffered_rows(&buffer), [1]);
+        assert!(buffer.open_file().unwrap().is_some());
+
+        // a file has a single schema
+        buffer.finish().unwrap();
+        assert_eq!(written_rows(&dir), [1, 1]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[rstest]
+    fn writes_out_on_slot_change(dir: PathBuf) {
+        let buffer = single_shard(&dir, 100);
+        buffer
+            .append_in_slot(&int_record(), "minute=00.".to_owned())
+            .unwrap();
+        buffer
+            .append_in_slot(&int_record(), "minute=01.".to_owned())
+            .unwrap();
+        assert_eq!(buffered_rows(&buffer), [1]);
+
+        // each batch is in the file of the slot it arrived in
+        buffer.finish().unwrap();
+        let mut files: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .flatten()
+            .map(|file| file.file_name().into_string().unwrap())
+            .collect();
+        files.sort_unstable();
+        assert_eq!(files.len(), 2);
+        assert!(files[0].starts_with("minute=00."));
+        assert!(files[1].starts_with("minute=01."));
+        assert_eq!(written_rows(&dir), [1, 1]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[rstest]
+    fn flushes_by_age(dir: PathBuf) {
+        let buffer = single_shard(&dir, 100);
+        buffer.append(&int_record()).unwrap();
+
+        buffer.flush(Some(Duration::from_secs(60))).unwrap();
+        assert_eq!(buffered_rows(&buffer), [1]);
+        assert!(buffer.open_file().unwrap().is_none());
+
+        buffer.flush(Some(Duration::ZERO)).unwrap();
+        assert_eq!(buffered_rows(&buffer), [0]);
+        assert!(buffer.open_file().unwrap().is_some());
+
+        buffer.finish().unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[rstest]
+    fn finish_closes_file(dir: PathBuf) {
+        let buffer = buffer(&dir, 100);
+        buffer.append(&int_record()).unwrap();
+        buffer.append(&int_record()).unwrap();
+
+        buffer.finish().unwrap();
+        assert!(buffer.open_file().unwrap().is_none());
+        assert!(buffered_rows(&buffer).iter().all(|rows| *rows == 0));
+        // both batches are in the file, which is readable to its end
+        assert_eq!(written_rows(&dir), [2]);
+
+        // the next write starts a new file
+        buffer.append(&int_record()).unwrap();
+        buffer.finish().unwrap();
+        assert_eq!(written_rows(&dir), [1, 2]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
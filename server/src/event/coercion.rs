@@ -0,0 +1,455 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Number, Value};
+
+use super::error::CoercionError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Coercion {
+    /// Accept numbers sent as strings into numeric columns
+    #[serde(default)]
+    pub numeric_strings: bool,
+    /// Widen an integer column of an inferred schema to Float64 when
+    /// an event has a fractional value for it, instead of rejecting the event
+    #[serde(default = "default_widen_integers")]
+    pub widen_integers: bool,
+    /// Store numbers, booleans, arrays and objects sent for a string column of
+    /// a declared schema as their JSON text, instead of rejecting the event
+    #[serde(default)]
+    pub stringify: bool,
+}
+
+fn default_widen_integers() -> bool {
+    true
+}
+
+impl Default for Coercion {
+    fn default() -> Self {
+        Self {
+            numeric_strings: false,
+            widen_integers: default_widen_integers(),
+            stringify: false,
+        }
+    }
+}
+
+impl Coercion {
+    /// Integer columns of the schema which the event has fractional values for
+    pub fn columns_to_widen(&self, schema: &Schema, event: &Value) -> Vec<String> {
+        let Value::Object(event) = event else {
+            return Vec::new();
+        };
+        if !self.widen_integers {
+            return Vec::new();
+        }
+
+        schema
+            .fields()
+            .iter()
+            .filter(|field| field.data_type() == &DataType::Int64)
+            .filter(|field| match event.get(field.name()) {
+                Some(Value::Number(number)) => as_integer(number).is_none(),
+                Some(Value::String(value)) if self.numeric_strings => value
+                    .trim()
+                    .parse::<Number>()
+                    .map_or(false, |number| as_integer(&number).is_none()),
+                _ => false,
+            })
+            .map(|field| field.name().to_owned())
+            .collect()
+    }
+}
+
+/// Schema with the given integer columns widened to Float64
+pub fn widen(schema: &Schema, columns: &[String]) -> Schema {
+    let fields = schema
+        .fields()
+        .iter()
+        .map(|field| match field.data_type() {
+            DataType::Int64 if columns.contains(field.name()) => {
+                Field::new(field.name(), DataType::Float64, field.is_nullable())
+            }
+            _ => field.clone(),
+        })
+        .collect();
+
+    Schema::with_metadata(fields, schema.metadata().clone())
+}
+
+/// Coerce every value of an event to the type of its column in the given schema.
+/// Fails if the event has a field which is not in the schema, misses a non nullable
+/// field or has a value which can not be represented as the column's type.
+pub fn coerce_to_schema(
+    event: &mut Map<String, Value>,
+    schema: &Schema,
+    coercion: &Coercion,
+) -> Result<(), CoercionError> {
+    coerce_fields(event, schema.fields(), coercion)
+}
+
+/// Whether every value of an event already has the type of its column, so that
+/// coercing it would leave it as it is. This only reads the event.
+pub fn fits_schema(event: &Value, schema: &Schema) -> bool {
+    match event {
+        Value::Object(event) => fits_fields(event, schema.fields()),
+        _ => false,
+    }
+}
+
+fn fits_fields(event: &Map<String, Value>, fields: &[Field]) -> bool {
+    let known = event
+        .keys()
+        .all(|key| fields.iter().any(|field| field.name() == key));
+
+    known
+        && fields.iter().all(|field| match event.get(field.name()) {
+            Some(Value::Null) | None => field.is_nullable(),
+            Some(value) => fits_value(value, field.data_type()),
+        })
+}
+
+fn fits_value(value: &Value, data_type: &DataType) -> bool {
+    match (data_type, value) {
+        (DataType::Boolean, Value::Bool(_)) => true,
+        (DataType::Utf8 | DataType::LargeUtf8, Value::String(_)) => true,
+        (DataType::Int64, Value::Number(number)) => number.is_i64(),
+        (DataType::Float64, Value::Number(_)) => true,
+        (DataType::List(field) | DataType::LargeList(field), Value::Array(items)) => {
+            items.iter().all(|item| match item {
+                Value::Null => field.is_nullable(),
+                item => fits_value(item, field.data_type()),
+            })
+        }
+        (DataType::Struct(fields), Value::Object(map)) => fits_fields(map, fields),
+        // anything else is left to the coercion
+        _ => false,
+    }
+}
+
+fn coerce_fields(
+    event: &mut Map<String, Value>,
+    fields: &[Field],
+    coercion: &Coercion,
+) -> Result<(), CoercionError> {
+    if let Some(key) = event
+        .keys()
+        .find(|key| !fields.iter().any(|field| field.name() == *key))
+    {
+        return Err(CoercionError::UnknownField(key.to_owned()));
+    }
+
+    for field in fields {
+        let value = match event.get_mut(field.name()) {
+            Some(Value::Null) | None if field.is_nullable() => continue,
+            Some(Value::Null) | None => {
+                return Err(CoercionError::MissingField(field.name().to_owned()))
+            }
+            Some(value) => value,
+        };
+
+        if let Some(coerced) = coerce_value(value, field.data_type(), coercion) {
+            *value = coerced;
+        } else {
+            return Err(CoercionError::Incompatible {
+                field: field.name().to_owned(),
+                data_type: field.data_type().clone(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn coerce_value(value: &Value, data_type: &DataType, coercion: &Coercion) -> Option<Value> {
+    if let Value::String(value) = value {
+        if coercion.numeric_strings && is_numeric(data_type) {
+            let number = value.trim().parse::<Number>().ok()?;
+            return coerce_value(&Value::Number(number), data_type, coercion);
+        }
+    }
+
+    match (data_type, value) {
+        (DataType::Boolean, Value::Bool(_)) => Some(value.clone()),
+        (DataType::Utf8 | DataType::LargeUtf8, Value::String(_)) => Some(value.clone()),
+        // any other type is a conflict, unless the stream opts in to store it as json text
+        (DataType::Utf8 | DataType::LargeUtf8, _) if coercion.stringify => {
+            Some(Value::String(value.to_string()))
+        }
+        (DataType::Float16 | DataType::Float32 | DataType::Float64, Value::Number(_)) => {
+            Some(value.clone())
+        }
+        (
+            DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::Int64,
+            Value::Number(number),
+        ) => {
+            let number = as_integer(number)?;
+            integer_in_range(number, data_type).then(|| Value::from(number))
+        }
+        (
+            DataType::UInt8 | DataType::UInt16 | DataType::UInt32 | DataType::UInt64,
+            Value::Number(number),
+        ) => {
+            let number = number
+                .as_u64()
+                .or_else(|| as_integer(number).and_then(|number| u64::try_from(number).ok()))?;
+            unsigned_in_range(number, data_type).then(|| Value::from(number))
+        }
+        (DataType::List(field) | DataType::LargeList(field), Value::Array(items)) => items
+            .iter()
+            .map(|item| match item {
+                Value::Null if field.is_nullable() => Some(Value::Null),
+                item => coerce_value(item, field.data_type(), coercion),
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(Value::Array),
+        // keys of a map are free form, only the values are coerced
+        (DataType::Map(entries, _), Value::Object(map)) => {
+            let DataType::Struct(fields) = entries.data_type() else {
+                return None;
+            };
+            let value_type = fields.get(1)?.data_type();
+            map.iter()
+                .map(|(key, value)| match value {
+                    Value::Null => Some((key.to_owned(), Value::Null)),
+                    value => coerce_value(value, value_type, coercion).map(|v| (key.to_owned(), v)),
+                })
+                .collect::<Option<Map<_, _>>>()
+                .map(Value::Object)
+        }
+        (DataType::Struct(fields), Value::Object(map)) => {
+            let mut map = map.clone();
+            coerce_fields(&mut map, fields, coercion).ok()?;
+            Some(Value::Object(map))
+        }
+        _ => None,
+    }
+}
+
+fn is_numeric(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Float16
+            | DataType::Float32
+            | DataType::Float64
+    )
+}
+
+// floats without a fractional part are accepted as integers
+fn as_integer(number: &Number) -> Option<i64> {
+    number.as_i64().or_else(|| {
+        let number = number.as_f64()?;
+        (number.fract() == 0.0 && number >= i64::MIN as f64 && number <= i64::MAX as f64)
+            .then_some(number as i64)
+    })
+}
+
+fn integer_in_range(number: i64, data_type: &DataType) -> bool {
+    match data_type {
+        DataType::Int8 => i8::try_from(number).is_ok(),
+        DataType::Int16 => i16::try_from(number).is_ok(),
+        DataType::Int32 => i32::try_from(number).is_ok(),
+        _ => true,
+    }
+}
+
+fn unsigned_in_range(number: u64, data_type: &DataType) -> bool {
+    match data_type {
+        DataType::UInt8 => u8::try_from(number).is_ok(),
+        DataType::UInt16 => u16::try_from(number).is_ok(),
+        DataType::UInt32 => u32::try_from(number).is_ok(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use rstest::*;
+    use serde_json::{json, Value};
+
+    use super::{coerce_to_schema, fits_schema, widen, Coercion};
+
+    #[fixture]
+    fn schema() -> Schema {
+        Schema::new(vec![
+            Field::new("latency", DataType::Float64, true),
+            Field::new("status", DataType::Int16, true),
+            Field::new("message", DataType::Utf8, false),
+        ])
+    }
+
+    #[rstest]
+    #[case::int_to_float(json!({"latency": 3, "message": "ok"}), json!({"latency": 3, "message": "ok"}))]
+    #[case::whole_float_to_int(json!({"status": 200.0, "message": "ok"}), json!({"status": 200, "message": "ok"}))]
+    #[case::null_for_nullable(json!({"status": null, "message": "ok"}), json!({"status": null, "message": "ok"}))]
+    fn coerces(schema: Schema, #[case] event: Value, #[case] expected: Value) {
+        let mut event = event.as_object().unwrap().clone();
+        coerce_to_schema(&mut event, &schema, &Coercion::default()).unwrap();
+        assert_eq!(Value::Object(event), expected);
+    }
+
+    #[rstest]
+    #[case::unknown_field(json!({"message": "ok", "extra": 1}))]
+    #[case::missing_required_field(json!({"status": 200}))]
+    #[case::fraction_to_int(json!({"status": 2.5, "message": "ok"}))]
+    #[case::out_of_range(json!({"status": 70000, "message": "ok"}))]
+    #[case::string_to_float(json!({"latency": "fast", "message": "ok"}))]
+    #[case::number_to_string(json!({"message": 42}))]
+    #[case::bool_to_string(json!({"message": true}))]
+    #[case::object_to_string(json!({"message": {"a": 1}}))]
+    fn rejects(schema: Schema, #[case] event: Value) {
+        let mut event = event.as_object().unwrap().clone();
+        assert!(coerce_to_schema(&mut event, &schema, &Coercion::default()).is_err());
+    }
+
+    // an event which fits is left as it is by the coercion
+    #[rstest]
+    #[case::fits(json!({"latency": 3.5, "message": "ok"}), true)]
+    #[case::fewer_fields(json!({"message": "ok", "status": null}), true)]
+    #[case::narrow_int(json!({"status": 200, "message": "ok"}), false)]
+    #[case::number_to_string(json!({"message": 42}), false)]
+    #[case::missing_required_field(json!({"latency": 3.5}), false)]
+    #[case::unknown_field(json!({"message": "ok", "extra": 1}), false)]
+    fn fits(schema: Schema, #[case] event: Value, #[case] fits: bool) {
+        assert_eq!(fits_schema(&event, &schema), fits);
+        if fits {
+            let mut coerced = event.as_object().unwrap().clone();
+            coerce_to_schema(&mut coerced, &schema, &Coercion::default()).unwrap();
+            assert_eq!(Value::Object(coerced), event);
+        }
+    }
+
+    #[rstest]
+    fn numeric_strings_when_configured(schema: Schema) {
+        let coercion = Coercion {
+            numeric_strings: true,
+            ..Coercion::default()
+        };
+        let mut event = json!({"latency": "3.5", "status": " 200 ", "message": "ok"})
+            .as_object()
+            .unwrap()
+            .clone();
+        coerce_to_schema(&mut event, &schema, &coercion).unwrap();
+        assert_eq!(
+            Value::Object(event),
+            json!({"latency": 3.5, "status": 200, "message": "ok"})
+        );
+
+        let mut event = json!({"status": "2xx", "message": "ok"})
+            .as_object()
+            .unwrap()
+            .clone();
+        assert!(coerce_to_schema(&mut event, &schema, &coercion).is_err());
+    }
+
+    #[rstest]
+    #[case::number(json!({"message": 42}), json!({"message": "42"}))]
+    #[case::bool(json!({"message": true}), json!({"message": "true"}))]
+    #[case::object(json!({"message": {"a": 1}}), json!({"message": r#"{"a":1}"#}))]
+    fn stringify_when_configured(schema: Schema, #[case] event: Value, #[case] expected: Value) {
+        let coercion = Coercion {
+            stringify: true,
+            ..Coercion::default()
+        };
+        let mut event = event.as_object().unwrap().clone();
+        coerce_to_schema(&mut event, &schema, &coercion).unwrap();
+        assert_eq!(Value::Object(event), expected);
+    }
+
+    #[rstest]
+    fn nested_values() {
+        let schema = Schema::new(vec![
+            Field::new(
+                "tags",
+                DataType::List(Box::new(Field::new("item", DataType::Utf8, true))),
+                true,
+            ),
+            Field::new(
+                "user",
+                DataType::Struct(vec![
+                    Field::new("id", DataType::Int64, false),
+                    Field::new("name", DataType::Utf8, true),
+                ]),
+                true,
+            ),
+        ]);
+
+        let mut event = json!({"tags": ["a", null], "user": {"id": 7.0}})
+            .as_object()
+            .unwrap()
+            .clone();
+        coerce_to_schema(&mut event, &schema, &Coercion::default()).unwrap();
+        assert_eq!(
+            Value::Object(event),
+            json!({"tags": ["a", null], "user": {"id": 7}})
+        );
+
+        let mut event = json!({"tags": ["a", 1]}).as_object().unwrap().clone();
+        assert!(coerce_to_schema(&mut event, &schema, &Coercion::default()).is_err());
+
+        let mut event = json!({"user": {"name": "x"}}).as_object().unwrap().clone();
+        assert!(coerce_to_schema(&mut event, &schema, &Coercion::default()).is_err());
+    }
+
+    #[rstest]
+    #[case::fraction(json!({"latency": 2.5, "status": 200}), vec!["latency"])]
+    #[case::whole_numbers(json!({"latency": 3.0, "status": 200}), vec![])]
+    #[case::not_a_number(json!({"latency": "slow", "message": 1.5}), vec![])]
+    fn integer_columns_to_widen(#[case] event: Value, #[case] expected: Vec<&str>) {
+        let schema = Schema::new(vec![
+            Field::new("latency", DataType::Int64, true),
+            Field::new("status", DataType::Int64, true),
+            Field::new("message", DataType::Utf8, true),
+        ]);
+        assert_eq!(
+            Coercion::default().columns_to_widen(&schema, &event),
+            expected
+        );
+
+        let coercion = Coercion {
+            widen_integers: false,
+            ..Coercion::default()
+        };
+        assert!(coercion.columns_to_widen(&schema, &event).is_empty());
+    }
+
+    #[rstest]
+    fn widen_integers() {
+        let schema = Schema::new(vec![
+            Field::new("latency", DataType::Int64, true),
+            Field::new("status", DataType::Int64, true),
+            Field::new("message", DataType::Utf8, true),
+        ]);
+        let widened = widen(&schema, &["latency".to_string()]);
+        assert_eq!(widened.field(0).data_type(), &DataType::Float64);
+        assert_eq!(widened.field(1).data_type(), &DataType::Int64);
+        assert_eq!(widened.field(2).data_type(), &DataType::Utf8);
+    }
+}
@@ -0,0 +1,96 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::fmt::Display;
+
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::error::EventError;
+use super::Event;
+
+// Columns of a dead-letter stream
+pub const RAW_COLUMN: &str = "p_raw";
+pub const ERROR_COLUMN: &str = "p_error";
+pub const SOURCE_STREAM_COLUMN: &str = "p_source_stream";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetter {
+    /// Stream which receives the events rejected by the source stream
+    pub stream: String,
+}
+
+impl DeadLetter {
+    /// Every dead-letter stream has this schema, so that rejected
+    /// events of any shape can be stored in it
+    pub fn schema() -> Schema {
+        Schema::new(vec![
+            Field::new(RAW_COLUMN, DataType::Utf8, false),
+            Field::new(ERROR_COLUMN, DataType::Utf8, false),
+            Field::new(SOURCE_STREAM_COLUMN, DataType::Utf8, false),
+        ])
+    }
+
+    /// Store the raw payload rejected by `source_stream` along with the reason
+    pub async fn store(
+        &self,
+        source_stream: &str,
+        raw: &Value,
+        error: &impl Display,
+    ) -> Result<(), EventError> {
+        let event = Event {
+            body: Self::record(source_stream, raw, error),
+            stream_name: self.stream.clone(),
+        };
+
+        event.process().await
+    }
+
+    fn record(source_stream: &str, raw: &Value, error: &impl Display) -> Value {
+        json!({
+            RAW_COLUMN: raw.to_string(),
+            ERROR_COLUMN: error.to_string(),
+            SOURCE_STREAM_COLUMN: source_stream,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use serde_json::json;
+
+    use super::DeadLetter;
+
+    #[rstest]
+    fn record_keeps_raw_payload() {
+        let raw = json!({"status": "2xx", "nested": {"a": 1}});
+        let record = DeadLetter::record("app", &raw, &"schema mismatch");
+
+        assert_eq!(
+            record,
+            json!({
+                "p_raw": r#"{"nested":{"a":1},"status":"2xx"}"#,
+                "p_error": "schema mismatch",
+                "p_source_stream": "app",
+            })
+        );
+    }
+}
@@ -0,0 +1,277 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Dedup {
+    /// Event field holding the idempotency key. When a request carries the
+    /// idempotency key header, that takes precedence over this field.
+    pub field: Option<String>,
+    /// Duration for which a key is remembered
+    #[serde(default = "default_window")]
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+    /// Maximum number of keys remembered at any time
+    #[serde(default = "default_capacity")]
+    pub capacity: usize,
+    #[serde(skip)]
+    index: Mutex<DedupIndex>,
+}
+
+fn default_window() -> Duration {
+    Duration::from_secs(10 * 60)
+}
+
+fn default_capacity() -> usize {
+    100_000
+}
+
+impl Dedup {
+    /// Key for the event at `position` of a request. Returns None if this event can not be deduplicated.
+    pub fn key(&self, header_key: Option<&str>, position: usize, event: &Value) -> Option<String> {
+        if let Some(header_key) = header_key {
+            return Some(format!("{}/{}", header_key, position));
+        }
+
+        match event.get(self.field.as_ref()?)? {
+            Value::Null => None,
+            Value::String(key) => Some(key.to_owned()),
+            key => Some(key.to_string()),
+        }
+    }
+
+    pub fn is_duplicate(&self, key: &str) -> bool {
+        let mut index = self.index.lock().unwrap();
+        index.evict(Instant::now(), self.window);
+        index.contains(key) || index.is_pending(key)
+    }
+
+    /// Reserve the key for an event about to be written. Returns None if the key
+    /// was seen before or another event with this key is being written.
+    pub fn reserve<'a>(&'a self, key: &'a str) -> Option<Reservation<'a>> {
+        let mut index = self.index.lock().unwrap();
+        index.evict(Instant::now(), self.window);
+        if index.contains(key) || !index.pending.insert(DedupIndex::hash(key)) {
+            return None;
+        }
+
+        Some(Reservation {
+            dedup: self,
+            key,
+            committed: false,
+        })
+    }
+}
+
+/// Key of an event being written. The key is only marked as seen once the event
+/// is written, so that a failed event can still be retried. Dropping the
+/// reservation without a commit releases the key.
+pub struct Reservation<'a> {
+    dedup: &'a Dedup,
+    key: &'a str,
+    committed: bool,
+}
+
+impl Reservation<'_> {
+    pub fn commit(mut self) {
+        let mut index = self.dedup.index.lock().unwrap();
+        index.pending.remove(&DedupIndex::hash(self.key));
+        index.insert(self.key, Instant::now(), self.dedup.capacity);
+        self.committed = true;
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            let mut index = self.dedup.index.lock().unwrap();
+            index.pending.remove(&DedupIndex::hash(self.key));
+        }
+    }
+}
+
+// A copy of the setting remembers no keys yet
+impl Clone for Dedup {
+    fn clone(&self) -> Self {
+        Self {
+            field: self.field.clone(),
+            window: self.window,
+            capacity: self.capacity,
+            index: Mutex::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct DedupIndex {
+    seen: HashMap<u64, Instant>,
+    order: VecDeque<(u64, Instant)>,
+    // keys of events which are being written
+    pending: HashSet<u64>,
+}
+
+impl DedupIndex {
+    fn hash(key: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.seen.contains_key(&Self::hash(key))
+    }
+
+    fn is_pending(&self, key: &str) -> bool {
+        self.pending.contains(&Self::hash(key))
+    }
+
+    fn insert(&mut self, key: &str, now: Instant, capacity: usize) {
+        while self.order.len() >= capacity {
+            self.pop_oldest();
+        }
+
+        let hash = Self::hash(key);
+        self.seen.insert(hash, now);
+        self.order.push_back((hash, now));
+    }
+
+    // drop keys which are older than the window
+    fn evict(&mut self, now: Instant, window: Duration) {
+        while let Some((_, seen_at)) = self.order.front() {
+            if now.duration_since(*seen_at) < window {
+                break;
+            }
+            self.pop_oldest();
+        }
+    }
+
+    fn pop_oldest(&mut self) {
+        if let Some((hash, seen_at)) = self.order.pop_front() {
+            // the key may have been seen again later, keep that entry
+            if self.seen.get(&hash) == Some(&seen_at) {
+                self.seen.remove(&hash);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use rstest::*;
+    use serde_json::json;
+
+    use super::{Dedup, DedupIndex};
+
+    #[fixture]
+    fn dedup() -> Dedup {
+        serde_json::from_value(json!({ "field": "id", "window": "1m" })).unwrap()
+    }
+
+    #[rstest]
+    fn key_from_header_takes_precedence(dedup: Dedup) {
+        let event = json!({ "id": "abc" });
+        assert_eq!(
+            dedup.key(Some("req1"), 2, &event),
+            Some("req1/2".to_string())
+        );
+        assert_eq!(dedup.key(None, 2, &event), Some("abc".to_string()));
+        assert_eq!(
+            dedup.key(None, 2, &json!({ "id": 7 })),
+            Some("7".to_string())
+        );
+        assert_eq!(dedup.key(None, 2, &json!({ "other": 7 })), None);
+    }
+
+    #[rstest]
+    fn repeated_key_is_duplicate(dedup: Dedup) {
+        assert!(!dedup.is_duplicate("abc"));
+        dedup.reserve("abc").unwrap().commit();
+        assert!(dedup.is_duplicate("abc"));
+        assert!(dedup.reserve("abc").is_none());
+        assert!(!dedup.is_duplicate("xyz"));
+    }
+
+    #[rstest]
+    fn released_key_can_be_retried(dedup: Dedup) {
+        let reservation = dedup.reserve("abc").unwrap();
+        assert!(dedup.reserve("abc").is_none());
+        drop(reservation);
+        assert!(!dedup.is_duplicate("abc"));
+        assert!(dedup.reserve("abc").is_some());
+    }
+
+    #[rstest]
+    fn concurrent_events_reserve_a_key_once(dedup: Dedup) {
+        let barrier = Barrier::new(8);
+        let reserved = AtomicUsize::new(0);
+
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    barrier.wait();
+                    if let Some(reservation) = dedup.reserve("abc") {
+                        reserved.fetch_add(1, Ordering::SeqCst);
+                        // hold the key while the other events try to reserve it
+                        thread::sleep(Duration::from_millis(50));
+                        reservation.commit();
+                    }
+                });
+            }
+        });
+
+        assert_eq!(reserved.load(Ordering::SeqCst), 1);
+        assert!(dedup.is_duplicate("abc"));
+    }
+
+    #[rstest]
+    fn keys_expire_after_window() {
+        let mut index = DedupIndex::default();
+        let start = Instant::now();
+        index.insert("abc", start, 10);
+        index.evict(start + Duration::from_secs(30), Duration::from_secs(60));
+        assert!(index.contains("abc"));
+        index.evict(start + Duration::from_secs(61), Duration::from_secs(60));
+        assert!(!index.contains("abc"));
+    }
+
+    #[rstest]
+    fn index_is_bounded() {
+        let mut index = DedupIndex::default();
+        let now = Instant::now();
+        for key in ["a", "b", "c"] {
+            index.insert(key, now, 2);
+        }
+        assert!(!index.contains("a"));
+        assert!(index.contains("b"));
+        assert!(index.contains("c"));
+    }
+}
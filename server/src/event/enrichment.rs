@@ -0,0 +1,173 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::net::IpAddr;
+
+use actix_web::http::header;
+use actix_web::HttpRequest;
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::option::CONFIG;
+use crate::utils;
+
+pub const SRC_IP_COLUMN: &str = "p_src_ip";
+pub const USER_AGENT_COLUMN: &str = "p_user_agent";
+pub const NODE_COLUMN: &str = "p_node";
+
+const FORWARDED_FOR: &str = "x-forwarded-for";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Enrichment {
+    /// Address of the client which sent the event
+    #[serde(default)]
+    pub src_ip: bool,
+    /// User agent of the client which sent the event
+    #[serde(default)]
+    pub user_agent: bool,
+    /// Hostname of the server which ingested the event
+    #[serde(default)]
+    pub node: bool,
+}
+
+impl Enrichment {
+    /// Columns added to the events of the stream
+    pub fn columns(&self) -> Vec<&'static str> {
+        [
+            (self.src_ip, SRC_IP_COLUMN),
+            (self.user_agent, USER_AGENT_COLUMN),
+            (self.node, NODE_COLUMN),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, column)| enabled.then_some(column))
+        .collect()
+    }
+
+    /// Add the enabled columns to the schema inferred from the first event,
+    /// these are missing from it when the first request had no such information
+    pub fn with_columns(&self, schema: Schema) -> Schema {
+        let mut fields = schema.fields().clone();
+        for column in self.columns() {
+            if schema.column_with_name(column).is_none() {
+                fields.push(Field::new(column, DataType::Utf8, true));
+            }
+        }
+        Schema::new(fields)
+    }
+
+    /// Fields added to every event of the request.
+    /// A field is null if the request does not have this information.
+    pub fn fields(&self, req: &HttpRequest) -> Map<String, Value> {
+        let mut fields = Map::new();
+
+        if self.src_ip {
+            let forwarded_for = req
+                .headers()
+                .get(FORWARDED_FOR)
+                .and_then(|value| value.to_str().ok());
+            let ip = client_ip(
+                req.peer_addr().map(|addr| addr.ip()),
+                forwarded_for,
+                &CONFIG.parseable.trusted_proxies,
+            );
+            fields.insert(SRC_IP_COLUMN.to_string(), to_value(ip));
+        }
+
+        if self.user_agent {
+            let user_agent = req
+                .headers()
+                .get(header::USER_AGENT)
+                .and_then(|value| value.to_str().ok());
+            fields.insert(USER_AGENT_COLUMN.to_string(), to_value(user_agent));
+        }
+
+        if self.node {
+            fields.insert(NODE_COLUMN.to_string(), to_value(utils::hostname()));
+        }
+
+        fields
+    }
+}
+
+fn to_value(value: Option<impl ToString>) -> Value {
+    value.map_or(Value::Null, |value| Value::String(value.to_string()))
+}
+
+// X-Forwarded-For is only honored when the request comes from a trusted proxy.
+// Each proxy appends the address it received the request from, so the client
+// is the right most address which is not a trusted proxy.
+fn client_ip(
+    peer: Option<IpAddr>,
+    forwarded_for: Option<&str>,
+    trusted_proxies: &[IpAddr],
+) -> Option<IpAddr> {
+    let peer = peer?;
+    if !trusted_proxies.contains(&peer) {
+        return Some(peer);
+    }
+
+    let Some(forwarded_for) = forwarded_for else {
+        return Some(peer);
+    };
+
+    let mut client = peer;
+    for addr in forwarded_for.rsplit(',') {
+        let Ok(addr) = addr.trim().parse::<IpAddr>() else {
+            break;
+        };
+        client = addr;
+        if !trusted_proxies.contains(&addr) {
+            break;
+        }
+    }
+
+    Some(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use rstest::*;
+
+    use super::client_ip;
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[rstest]
+    #[case::untrusted_peer("10.0.0.9", Some("1.1.1.1"), "10.0.0.9")]
+    #[case::trusted_peer("10.0.0.1", Some("1.1.1.1"), "1.1.1.1")]
+    #[case::chain_of_proxies("10.0.0.1", Some("1.1.1.1, 2.2.2.2, 10.0.0.2"), "2.2.2.2")]
+    #[case::without_header("10.0.0.1", None, "10.0.0.1")]
+    #[case::invalid_address("10.0.0.1", Some("1.1.1.1, unknown"), "10.0.0.1")]
+    fn client_address(
+        #[case] peer: &str,
+        #[case] forwarded_for: Option<&str>,
+        #[case] expected: &str,
+    ) {
+        let trusted = [ip("10.0.0.1"), ip("10.0.0.2")];
+        assert_eq!(
+            client_ip(Some(ip(peer)), forwarded_for, &trusted),
+            Some(ip(expected))
+        );
+    }
+}
@@ -0,0 +1,117 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+pub const PATH_SEPARATOR: char = '.';
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Explode {
+    /// Path of the array to explode, with nested keys separated by `.`
+    pub path: String,
+}
+
+impl Explode {
+    /// Split an envelope into one event per element of the array at `path`.
+    /// Every event inherits the envelope fields, fields of the element take
+    /// precedence over them. An event without the array is returned as is.
+    pub fn apply(&self, event: Value) -> Vec<Value> {
+        let mut envelope = event;
+        let keys: Vec<&str> = self.path.split(PATH_SEPARATOR).collect();
+
+        let Some(Value::Array(elements)) = take_path(&mut envelope, &keys) else {
+            return vec![envelope];
+        };
+
+        let Value::Object(envelope) = envelope else {
+            unreachable!("only an object can have a path to the array")
+        };
+
+        let key = keys[keys.len() - 1];
+        elements
+            .into_iter()
+            .map(|element| {
+                let mut event = envelope.clone();
+                match element {
+                    Value::Object(fields) => event.extend(fields),
+                    // scalars take the place of the array
+                    element => {
+                        event.insert(key.to_owned(), element);
+                    }
+                }
+                Value::Object(event)
+            })
+            .collect()
+    }
+}
+
+// Remove the value at the path only if it is an array
+fn take_path(value: &mut Value, keys: &[&str]) -> Option<Value> {
+    let (last, parents) = keys.split_last()?;
+
+    let mut map: &mut Map<String, Value> = value.as_object_mut()?;
+    for key in parents {
+        map = map.get_mut(*key)?.as_object_mut()?;
+    }
+
+    if !matches!(map.get(*last), Some(Value::Array(_))) {
+        return None;
+    }
+
+    map.remove(*last)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use serde_json::{json, Value};
+
+    use super::Explode;
+
+    #[rstest]
+    #[case::top_level(
+        "records",
+        json!({"batch_id": 1, "records": [{"a": 1}, {"a": 2, "batch_id": 9}]}),
+        vec![json!({"batch_id": 1, "a": 1}), json!({"batch_id": 9, "a": 2})]
+    )]
+    #[case::nested(
+        "data.records",
+        json!({"batch_id": 1, "data": {"source": "x", "records": [{"a": 1}]}}),
+        vec![json!({"batch_id": 1, "data": {"source": "x"}, "a": 1})]
+    )]
+    #[case::scalars(
+        "records",
+        json!({"batch_id": 1, "records": [1, 2]}),
+        vec![json!({"batch_id": 1, "records": 1}), json!({"batch_id": 1, "records": 2})]
+    )]
+    #[case::empty_array("records", json!({"batch_id": 1, "records": []}), vec![])]
+    #[case::missing_path("records", json!({"batch_id": 1}), vec![json!({"batch_id": 1})])]
+    #[case::not_an_array(
+        "records",
+        json!({"batch_id": 1, "records": {"a": 1}}),
+        vec![json!({"batch_id": 1, "records": {"a": 1}})]
+    )]
+    fn explode(#[case] path: &str, #[case] event: Value, #[case] expected: Vec<Value>) {
+        let explode = Explode {
+            path: path.to_string(),
+        };
+        assert_eq!(explode.apply(event), expected);
+    }
+}
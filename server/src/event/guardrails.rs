@@ -0,0 +1,180 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+// Column which holds the fields folded out of an event, as a json object
+pub const OVERFLOW_COLUMN: &str = "p_overflow";
+// Fields added by the server are never folded
+const RESERVED_PREFIX: &str = "p_";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Guardrails {
+    /// Maximum number of columns in the stream, including the overflow column
+    pub max_columns: Option<usize>,
+    /// Maximum length of a column name
+    pub max_key_length: Option<usize>,
+    /// Maximum nesting depth of a field, a top level scalar has depth 1
+    pub max_depth: Option<usize>,
+}
+
+impl Guardrails {
+    /// Fold the fields nested deeper than the limit.
+    /// This is checked before the event is flattened.
+    pub fn fold_deep(&self, event: &mut Map<String, Value>, overflow: &mut Map<String, Value>) {
+        let Some(max_depth) = self.max_depth else {
+            return;
+        };
+
+        let keys: Vec<String> = event
+            .iter()
+            .filter(|(key, value)| !is_reserved(key) && 1 + depth(value) > max_depth)
+            .map(|(key, _)| key.to_owned())
+            .collect();
+
+        fold(event, keys, overflow);
+    }
+
+    /// Fold the fields with a long name, and the fields which would not fit in the
+    /// column limit. Once the schema of a stream is set it can not grow, so any field
+    /// outside of it is folded as well.
+    pub fn fold_columns(
+        &self,
+        event: &mut Map<String, Value>,
+        schema: Option<&Schema>,
+        overflow: &mut Map<String, Value>,
+    ) {
+        // one column is kept for the overflow
+        let mut available = self.max_columns.map(|max| max.saturating_sub(1));
+
+        let mut keys = Vec::new();
+        for key in event.keys() {
+            if is_reserved(key) {
+                continue;
+            }
+
+            let too_long = matches!(self.max_key_length, Some(max) if key.chars().count() > max);
+            let fits = match (schema, available.as_mut()) {
+                (Some(schema), _) => schema.column_with_name(key).is_some(),
+                (None, Some(0)) => false,
+                (None, Some(available)) => {
+                    *available -= 1;
+                    true
+                }
+                (None, None) => true,
+            };
+
+            if too_long || !fits {
+                keys.push(key.to_owned());
+            }
+        }
+
+        fold(event, keys, overflow);
+    }
+
+    /// Add the overflow column to the schema inferred from the first event
+    pub fn with_overflow_column(schema: Schema) -> Schema {
+        if schema.column_with_name(OVERFLOW_COLUMN).is_some() {
+            return schema;
+        }
+
+        let mut fields = schema.fields().clone();
+        fields.push(Field::new(OVERFLOW_COLUMN, DataType::Utf8, true));
+        Schema::new(fields)
+    }
+}
+
+fn fold(event: &mut Map<String, Value>, keys: Vec<String>, overflow: &mut Map<String, Value>) {
+    for key in keys {
+        if let Some(value) = event.remove(&key) {
+            overflow.insert(key, value);
+        }
+    }
+}
+
+fn is_reserved(key: &str) -> bool {
+    key.starts_with(RESERVED_PREFIX)
+}
+
+fn depth(value: &Value) -> usize {
+    match value {
+        Value::Array(array) => 1 + array.iter().map(depth).max().unwrap_or(0),
+        Value::Object(map) => 1 + map.values().map(depth).max().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use rstest::*;
+    use serde_json::{json, Map, Value};
+
+    use super::Guardrails;
+
+    fn object(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[rstest]
+    fn folds_deep_fields() {
+        let guardrails = Guardrails {
+            max_depth: Some(2),
+            ..Guardrails::default()
+        };
+        let mut event = object(json!({"a": 1, "b": {"c": 1}, "d": {"e": {"f": 1}}}));
+        let mut overflow = Map::new();
+        guardrails.fold_deep(&mut event, &mut overflow);
+
+        assert_eq!(Value::Object(event), json!({"a": 1, "b": {"c": 1}}));
+        assert_eq!(Value::Object(overflow), json!({"d": {"e": {"f": 1}}}));
+    }
+
+    #[rstest]
+    fn folds_columns_beyond_limit() {
+        let guardrails = Guardrails {
+            max_columns: Some(3),
+            max_key_length: Some(5),
+            ..Guardrails::default()
+        };
+        let mut event = object(json!({"a": 1, "b": 2, "c": 3, "p_tags": "", "very_long": 4}));
+        let mut overflow = Map::new();
+        guardrails.fold_columns(&mut event, None, &mut overflow);
+
+        assert_eq!(Value::Object(event), json!({"a": 1, "b": 2, "p_tags": ""}));
+        assert_eq!(Value::Object(overflow), json!({"c": 3, "very_long": 4}));
+    }
+
+    #[rstest]
+    fn folds_columns_outside_schema() {
+        let guardrails = Guardrails {
+            max_columns: Some(10),
+            ..Guardrails::default()
+        };
+        let schema = Schema::new(vec![Field::new("a", DataType::Int64, true)]);
+        let mut event = object(json!({"a": 1, "b": 2}));
+        let mut overflow = Map::new();
+        guardrails.fold_columns(&mut event, Some(&schema), &mut overflow);
+
+        assert_eq!(Value::Object(event), json!({"a": 1}));
+        assert_eq!(Value::Object(overflow), json!({"b": 2}));
+    }
+}
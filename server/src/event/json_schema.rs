@@ -0,0 +1,126 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::fmt;
+
+use jsonschema::JSONSchema;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+use super::error::EventError;
+
+/// JSON Schema every event of a stream has to match, before it is shaped in any way.
+/// Serializes as the schema document itself.
+pub struct JsonSchema {
+    schema: Value,
+    compiled: JSONSchema,
+}
+
+impl JsonSchema {
+    pub fn new(schema: Value) -> Result<Self, String> {
+        let compiled = JSONSchema::compile(&schema).map_err(|e| e.to_string())?;
+        Ok(Self { schema, compiled })
+    }
+
+    /// Check the event against the schema, the error lists every violation
+    pub fn validate(&self, event: &Value) -> Result<(), EventError> {
+        if let Err(errors) = self.compiled.validate(event) {
+            let violations = errors
+                .map(|error| match error.instance_path.to_string() {
+                    path if path.is_empty() => error.to_string(),
+                    path => format!("{}: {}", path, error),
+                })
+                .collect();
+            return Err(EventError::JsonSchema(violations));
+        }
+
+        Ok(())
+    }
+}
+
+impl Clone for JsonSchema {
+    fn clone(&self) -> Self {
+        Self::new(self.schema.clone()).expect("schema compiled before")
+    }
+}
+
+impl fmt::Debug for JsonSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JsonSchema")
+            .field("schema", &self.schema)
+            .finish()
+    }
+}
+
+impl Serialize for JsonSchema {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.schema.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for JsonSchema {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let schema = Value::deserialize(deserializer)?;
+        Self::new(schema).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use serde_json::{json, Value};
+
+    use super::JsonSchema;
+    use crate::event::error::EventError;
+
+    fn contract() -> JsonSchema {
+        JsonSchema::new(json!({
+            "type": "object",
+            "required": ["level", "service", "message"],
+            "properties": {
+                "level": {"type": "string"},
+                "service": {"type": "string"},
+                "message": {"type": "string"}
+            }
+        }))
+        .unwrap()
+    }
+
+    #[rstest]
+    fn accepts_matching_event() {
+        let event = json!({"level": "info", "service": "api", "message": "ok", "extra": 1});
+        assert!(contract().validate(&event).is_ok());
+    }
+
+    #[rstest]
+    #[case::missing_field(json!({"level": "info", "service": "api"}), 1)]
+    #[case::wrong_types(json!({"level": 1, "service": true, "message": "ok"}), 2)]
+    #[case::not_an_object(json!([1, 2]), 1)]
+    fn lists_every_violation(#[case] event: Value, #[case] count: usize) {
+        match contract().validate(&event) {
+            Err(EventError::JsonSchema(violations)) => assert_eq!(violations.len(), count),
+            other => panic!("expected violations, got {:?}", other),
+        }
+    }
+
+    #[rstest]
+    fn rejects_invalid_schema() {
+        assert!(JsonSchema::new(json!({"type": "not-a-type"})).is_err());
+    }
+}
@@ -0,0 +1,195 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::handlers::event::{METADATA_KEY, TAGS_KEY};
+use crate::utils::header_parsing::{join_labels, ParseHeaderError};
+
+pub const SEPARATOR: char = '^';
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LabelFormat {
+    /// `key=value` pairs joined with `^` in a single string column
+    #[default]
+    Joined,
+    /// A single Map<Utf8, Utf8> column
+    Map,
+    /// One string column per key, named `p_tags_<key>` or `p_metadata_<key>`.
+    /// A key which is new to the stream adds its column to the schema.
+    Columns,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Labels {
+    #[serde(default)]
+    pub format: LabelFormat,
+}
+
+impl Labels {
+    /// Fields added to every event of a request for the labels of `column`
+    pub fn fields(
+        &self,
+        column: &str,
+        labels: Vec<(String, String)>,
+    ) -> Result<Map<String, Value>, ParseHeaderError> {
+        let fields = match self.format {
+            LabelFormat::Joined => {
+                let joined = join_labels(&labels, SEPARATOR)?;
+                Map::from_iter([(column.to_owned(), Value::String(joined))])
+            }
+            LabelFormat::Map => {
+                let map = labels
+                    .into_iter()
+                    .map(|(key, value)| (key, Value::String(value)))
+                    .collect();
+                Map::from_iter([(column.to_owned(), Value::Object(map))])
+            }
+            LabelFormat::Columns => labels
+                .into_iter()
+                .map(|(key, value)| {
+                    // header names can have `-`, which is not usable in an unquoted column name
+                    let key = format!("{}_{}", column, key.replace('-', "_"));
+                    (key, Value::String(value))
+                })
+                .collect(),
+        };
+
+        Ok(fields)
+    }
+
+    /// Use map columns for labels in the schema inferred from the first event
+    pub fn with_map_columns(&self, schema: Schema) -> Schema {
+        if self.format != LabelFormat::Map {
+            return schema;
+        }
+
+        let fields = schema
+            .fields()
+            .iter()
+            .map(|field| match field.name().as_str() {
+                TAGS_KEY | METADATA_KEY => Field::new(field.name(), map_type(), true),
+                _ => field.clone(),
+            })
+            .collect();
+
+        Schema::new(fields)
+    }
+
+    /// Label columns of an event which are not in the schema yet. Events of a stream
+    /// can have different label keys, each of these is a column of its own.
+    pub fn new_columns(&self, schema: &Schema, event: &Value) -> Vec<String> {
+        let (LabelFormat::Columns, Value::Object(event)) = (self.format, event) else {
+            return Vec::new();
+        };
+
+        event
+            .keys()
+            .filter(|key| is_label_column(key) && schema.field_with_name(key).is_err())
+            .cloned()
+            .collect()
+    }
+}
+
+fn is_label_column(key: &str) -> bool {
+    [TAGS_KEY, METADATA_KEY].iter().any(|column| {
+        key.strip_prefix(column)
+            .map_or(false, |key| key.starts_with('_'))
+    })
+}
+
+/// Schema with string columns added for the given labels
+pub fn with_columns(schema: &Schema, columns: &[String]) -> Schema {
+    let mut fields = schema.fields().clone();
+    for column in columns {
+        if schema.field_with_name(column).is_err() {
+            fields.push(Field::new(column, DataType::Utf8, true));
+        }
+    }
+
+    Schema::with_metadata(fields, schema.metadata().clone())
+}
+
+pub fn map_type() -> DataType {
+    let entries = DataType::Struct(vec![
+        Field::new("keys", DataType::Utf8, false),
+        Field::new("values", DataType::Utf8, true),
+    ]);
+    DataType::Map(Box::new(Field::new("entries", entries, false)), false)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use serde_json::{json, Value};
+
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+
+    use super::{with_columns, LabelFormat, Labels};
+
+    fn labels() -> Vec<(String, String)> {
+        vec![
+            ("env".to_string(), "prod".to_string()),
+            ("team-name".to_string(), "core".to_string()),
+        ]
+    }
+
+    #[rstest]
+    #[case::joined(LabelFormat::Joined, json!({"p_tags": "env=prod^team-name=core"}))]
+    #[case::map(LabelFormat::Map, json!({"p_tags": {"env": "prod", "team-name": "core"}}))]
+    #[case::columns(LabelFormat::Columns, json!({"p_tags_env": "prod", "p_tags_team_name": "core"}))]
+    fn fields(#[case] format: LabelFormat, #[case] expected: Value) {
+        let fields = Labels { format }.fields("p_tags", labels()).unwrap();
+        assert_eq!(Value::Object(fields), expected);
+    }
+
+    #[rstest]
+    fn joined_rejects_separator() {
+        let labels = vec![("env".to_string(), "a^b".to_string())];
+        assert!(Labels::default().fields("p_tags", labels.clone()).is_err());
+
+        let labels = Labels {
+            format: LabelFormat::Map,
+        }
+        .fields("p_tags", labels);
+        assert!(labels.is_ok());
+    }
+
+    #[rstest]
+    #[case::columns(LabelFormat::Columns, &["p_tags_team"])]
+    #[case::joined(LabelFormat::Joined, &[])]
+    fn new_columns(#[case] format: LabelFormat, #[case] expected: &[&str]) {
+        let schema = Schema::new(vec![
+            Field::new("host", DataType::Utf8, true),
+            Field::new("p_tags_env", DataType::Utf8, true),
+        ]);
+        let event =
+            json!({"host": "a", "status": 200, "p_tags_env": "prod", "p_tags_team": "core"});
+
+        let columns = Labels { format }.new_columns(&schema, &event);
+        assert_eq!(columns, expected);
+
+        let schema = with_columns(&schema, &columns);
+        assert_eq!(schema.fields().len(), 2 + expected.len());
+        assert!(Labels { format }.new_columns(&schema, &event).is_empty());
+    }
+}
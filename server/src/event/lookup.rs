@@ -0,0 +1,148 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::lookup::{LookupTable, LOOKUP_TABLES};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LookupJoin {
+    /// Name of the lookup table
+    pub table: String,
+    /// Field of the event to match, as it is named after flattening
+    pub field: String,
+    /// Column of the lookup table the field is matched against
+    pub key: String,
+    /// Columns of the lookup table added to the event, all but the key if empty
+    #[serde(default)]
+    pub columns: Vec<String>,
+}
+
+impl LookupJoin {
+    /// Fill in the columns to add from the table when none are configured,
+    /// so that the columns of a stream do not change when the table is replaced
+    pub fn resolve_columns(&mut self, table: &LookupTable) {
+        if self.columns.is_empty() {
+            self.columns = table
+                .columns()
+                .iter()
+                .filter(|column| **column != self.key)
+                .cloned()
+                .collect();
+        }
+    }
+
+    /// Add the looked up columns to the event. A column is null if the field is missing
+    /// or has no match in the table, fields already in the event are left as is.
+    pub fn apply(&self, event: &mut Map<String, Value>) {
+        let table = LOOKUP_TABLES.get(&self.table);
+        let value = event.get(&self.field).and_then(|value| match value {
+            Value::String(value) => Some(value.to_owned()),
+            Value::Number(_) | Value::Bool(_) => Some(value.to_string()),
+            _ => None,
+        });
+
+        for column in &self.columns {
+            if event.contains_key(column) {
+                continue;
+            }
+
+            let looked_up = match (&table, &value) {
+                (Some(table), Some(value)) => table
+                    .get(&self.key, value, column)
+                    .map(|value| Value::String(value.to_owned())),
+                _ => None,
+            };
+            event.insert(column.to_owned(), looked_up.unwrap_or(Value::Null));
+        }
+    }
+}
+
+/// Columns added to the events of a stream by its lookups
+pub fn columns(lookups: &[LookupJoin]) -> impl Iterator<Item = &String> {
+    lookups.iter().flat_map(|lookup| lookup.columns.iter())
+}
+
+/// Add the looked up columns to the schema inferred from the first event,
+/// these are missing from it when the first event had no match
+pub fn with_columns(lookups: &[LookupJoin], schema: Schema) -> Schema {
+    let mut fields = schema.fields().clone();
+    for column in columns(lookups) {
+        if !fields.iter().any(|field| field.name() == column) {
+            fields.push(Field::new(column, DataType::Utf8, true));
+        }
+    }
+    Schema::new(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use serde_json::{json, Map, Value};
+
+    use super::LookupJoin;
+    use crate::lookup::{LookupTable, LOOKUP_TABLES};
+
+    fn join(table: &str) -> LookupJoin {
+        let services = "service,team,env\napi,core,prod\n";
+        let services = LookupTable::from_csv(services.as_bytes(), String::new()).unwrap();
+        LOOKUP_TABLES.insert(table.to_string(), services);
+
+        let mut join = LookupJoin {
+            table: table.to_string(),
+            field: "service".to_string(),
+            key: "service".to_string(),
+            columns: Vec::new(),
+        };
+        join.resolve_columns(&LOOKUP_TABLES.get(table).unwrap());
+        join
+    }
+
+    fn object(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[rstest]
+    #[case::matched(json!({"service": "api"}), json!({"service": "api", "team": "core", "env": "prod"}))]
+    #[case::not_matched(json!({"service": "db"}), json!({"service": "db", "team": null, "env": null}))]
+    #[case::missing_field(json!({"a": 1}), json!({"a": 1, "team": null, "env": null}))]
+    #[case::keeps_event_fields(
+        json!({"service": "api", "env": "dev"}),
+        json!({"service": "api", "team": "core", "env": "dev"})
+    )]
+    fn apply(#[case] event: Value, #[case] expected: Value) {
+        let mut event = object(event);
+        join("test_apply_services").apply(&mut event);
+        assert_eq!(Value::Object(event), expected);
+    }
+
+    #[rstest]
+    fn missing_table_adds_nulls() {
+        let mut join = join("test_missing_services");
+        join.table = "test_unknown_services".to_string();
+        let mut event = object(json!({"service": "api"}));
+        join.apply(&mut event);
+        assert_eq!(
+            Value::Object(event),
+            json!({"service": "api", "team": null, "env": null})
+        );
+    }
+}
@@ -0,0 +1,710 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ *
+ */
+use actix_web::rt::spawn;
+use datafusion::arrow::datatypes::Schema;
+use datafusion::arrow::error::ArrowError;
+use datafusion::arrow::json::reader::{infer_json_schema_from_iterator, Decoder, DecoderOptions};
+use datafusion::arrow::record_batch::RecordBatch;
+use lazy_static::lazy_static;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::iter;
+use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use crate::alerts;
+use crate::metadata;
+use crate::metadata::LOCK_EXPECT;
+use crate::option::CONFIG;
+use crate::s3;
+use crate::storage::{self, ObjectStorage, StorageDir};
+
+use self::buffer::StreamBuffer;
+use self::coercion::{coerce_to_schema, Coercion};
+use self::error::{EventError, StreamWriterError};
+use self::guardrails::Guardrails;
+use self::wal::{WalOptions, WalWriter};
+
+pub mod buffer;
+pub mod coercion;
+pub mod dead_letter;
+pub mod dedup;
+pub mod enrichment;
+pub mod explode;
+pub mod guardrails;
+pub mod json_schema;
+pub mod labels;
+pub mod lookup;
+pub mod nesting;
+pub mod sampling;
+pub mod wal;
+
+lazy_static! {
+    #[derive(Default)]
+    pub static ref STREAM_WRITERS: RwLock<HashMap<String, Arc<StreamBuffer>>> = RwLock::new(HashMap::new());
+
+    // serializes the uploads of widened schemas
+    static ref SCHEMA_UPLOAD: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+impl STREAM_WRITERS {
+    // append to the buffer of a stream, the map is only locked to find the buffer
+    fn append_to_local(stream: &str, record: &RecordBatch) -> Result<(), StreamWriterError> {
+        let buffer = STREAM_WRITERS::buffer(stream)?;
+        buffer.append(record)
+    }
+
+    // buffer of a stream, it is created if the stream does not have one yet. Creating it
+    // reads the stream metadata, so this is not called while holding its write lock
+    fn buffer(stream: &str) -> Result<Arc<StreamBuffer>, StreamWriterError> {
+        let hashmap_guard = STREAM_WRITERS
+            .read()
+            .map_err(|_| StreamWriterError::RwPoisoned)?;
+
+        if let Some(buffer) = hashmap_guard.get(stream) {
+            return Ok(Arc::clone(buffer));
+        }
+
+        // this requires mutable borrow of the map so we drop this read lock and wait for write lock
+        drop(hashmap_guard);
+        let mut hashmap_guard = STREAM_WRITERS
+            .write()
+            .map_err(|_| StreamWriterError::RwPoisoned)?;
+
+        let buffer = hashmap_guard.entry(stream.to_string()).or_insert_with(|| {
+            let data_granularity = metadata::STREAM_INFO
+                .data_granularity(stream)
+                .unwrap_or(storage::LEGACY_DATA_GRANULARITY);
+            Arc::new(StreamBuffer::new(
+                StorageDir::new(stream),
+                data_granularity,
+                CONFIG.parseable.buffer_max_rows,
+                WalOptions::from_config(),
+            ))
+        });
+
+        Ok(Arc::clone(buffer))
+    }
+
+    fn buffers() -> Result<Vec<(String, Arc<StreamBuffer>)>, StreamWriterError> {
+        let hashmap_guard = STREAM_WRITERS
+            .read()
+            .map_err(|_| StreamWriterError::RwPoisoned)?;
+
+        Ok(hashmap_guard
+            .iter()
+            .map(|(stream, buffer)| (stream.clone(), Arc::clone(buffer)))
+            .collect())
+    }
+
+    // Deleting a logstream requires that metadata is deleted first
+    pub fn delete_entry(stream: &str) -> Result<(), StreamWriterError> {
+        let mut hashmap_guard = STREAM_WRITERS
+            .write()
+            .map_err(|_| StreamWriterError::RwPoisoned)?;
+
+        hashmap_guard.remove(stream);
+
+        Ok(())
+    }
+
+    pub fn unset_all() -> Result<(), StreamWriterError> {
+        for (stream, buffer) in STREAM_WRITERS::buffers()? {
+            if let Err(e) = buffer.finish() {
+                log::warn!(
+                    "failed to finish local log file of stream {}. {}",
+                    stream,
+                    e
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    // write the events buffered for longer than the flush interval
+    pub fn flush_all() -> Result<(), StreamWriterError> {
+        let max_age = Duration::from_secs(CONFIG.parseable.buffer_flush_interval);
+        for (_, buffer) in STREAM_WRITERS::buffers()? {
+            buffer.flush(Some(max_age))?;
+        }
+
+        Ok(())
+    }
+
+    // flush the buffered events and the open log file of a stream to disk
+    pub fn sync(stream: &str) -> Result<(), StreamWriterError> {
+        let buffer = STREAM_WRITERS
+            .read()
+            .map_err(|_| StreamWriterError::RwPoisoned)?
+            .get(stream)
+            .cloned();
+
+        if let Some(buffer) = buffer {
+            buffer.sync()?;
+        }
+
+        Ok(())
+    }
+
+    // the log file of a stream which is still being written to
+    pub fn open_file(stream: &str) -> Result<Option<PathBuf>, StreamWriterError> {
+        let buffer = STREAM_WRITERS
+            .read()
+            .map_err(|_| StreamWriterError::RwPoisoned)?
+            .get(stream)
+            .cloned();
+
+        match buffer {
+            Some(buffer) => buffer.open_file(),
+            None => Ok(None),
+        }
+    }
+
+    pub fn sync_all() -> Result<(), StreamWriterError> {
+        for (_, buffer) in STREAM_WRITERS::buffers()? {
+            buffer.sync()?;
+        }
+
+        Ok(())
+    }
+}
+
+fn init_new_stream_writer_file(
+    dir: &StorageDir,
+    slot: String,
+    record: &RecordBatch,
+    options: WalOptions,
+) -> Result<WalWriter, StreamWriterError> {
+    let path = dir.path_for_slot(&slot);
+
+    std::fs::create_dir_all(&dir.data_path)?;
+
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+    WalWriter::try_new(file, path, slot, record, options)
+}
+
+#[derive(Clone)]
+pub struct Event {
+    pub body: Value,
+    pub stream_name: String,
+}
+
+// Events holds the schema related to a each event for a single log stream
+
+impl Event {
+    // The body is only ever decoded from the parsed json, schema inference, the record
+    // batch and alert evaluation all work on the value or on the batch decoded from it.
+    pub async fn process(self) -> Result<(), EventError> {
+        let stream_name = self.stream_name.clone();
+        let size = encoded_len(&self.body);
+        let stream_schema = metadata::STREAM_INFO.schema(&stream_name)?;
+
+        let record = if let Some(existing_schema) = stream_schema {
+            let existing_schema = match self.columns_to_widen(&existing_schema)? {
+                Some(columns) => Self::widen_schema::<s3::S3>(&stream_name, &columns).await?,
+                None => existing_schema,
+            };
+            let existing_schema = match self.label_columns_to_add(&existing_schema)? {
+                Some(columns) => Self::add_label_columns::<s3::S3>(&stream_name, &columns).await?,
+                None => existing_schema,
+            };
+            let body = self.conform(&existing_schema)?;
+            let record = decode(body, existing_schema)?;
+            Self::process_event(&stream_name, &record)?;
+            record
+        } else {
+            // if stream schema is none then it is first event,
+            // process first event and store schema in obect store
+            let inferred_schema = self.first_event_schema()?;
+            let record = decode(self.body, inferred_schema.clone())?;
+            Self::process_first_event::<s3::S3>(&stream_name, &record, inferred_schema)?;
+            record
+        };
+
+        metadata::STREAM_INFO.update_stats(&stream_name, size)?;
+
+        // evaluated by the alert workers, the request does not wait for it
+        if metadata::STREAM_INFO.has_alerts(&stream_name)? {
+            alerts::queue::enqueue(&stream_name, record);
+        }
+
+        Ok(())
+    }
+
+    /// Check that the event can be written to a stream with `schema`, without writing it.
+    /// When `schema` is None the event would be the first one of the stream, the schema
+    /// it sets up is returned so that later events of a batch are checked against it.
+    pub fn validate(&self, schema: Option<&Schema>) -> Result<Option<Schema>, EventError> {
+        let Some(schema) = schema else {
+            return self.first_event_schema().map(Some);
+        };
+
+        // later events are checked against the widened schema and its new label columns
+        let mut changed = self
+            .columns_to_widen(schema)?
+            .map(|columns| coercion::widen(schema, &columns));
+        if let Some(columns) = self.label_columns_to_add(changed.as_ref().unwrap_or(schema))? {
+            changed = Some(labels::with_columns(
+                changed.as_ref().unwrap_or(schema),
+                &columns,
+            ));
+        }
+
+        // only an event which has to be coerced is copied for the check
+        let schema = changed.as_ref().unwrap_or(schema);
+        if !coercion::fits_schema(&self.body, schema) {
+            self.clone().conform(schema)?;
+        }
+        Ok(changed)
+    }
+
+    // Integer columns of an inferred schema which this event has fractional values for
+    fn columns_to_widen(&self, schema: &Schema) -> Result<Option<Vec<String>>, EventError> {
+        if metadata::STREAM_INFO.is_static_schema(&self.stream_name)? {
+            return Ok(None);
+        }
+
+        let coercion = metadata::STREAM_INFO.coercion(&self.stream_name)?;
+        let columns = coercion.columns_to_widen(schema, &self.body);
+        Ok((!columns.is_empty()).then_some(columns))
+    }
+
+    // Widen the columns in the stream schema and store it. Files written before keep
+    // the integer type of the column, these are cast to the stream schema on query.
+    async fn widen_schema<S: ObjectStorage>(
+        stream_name: &str,
+        columns: &[String],
+    ) -> Result<Schema, EventError> {
+        let schema = metadata::STREAM_INFO.widen_columns(stream_name, columns)?;
+        log::info!(
+            "widened columns {:?} of logstream {} to Float64",
+            columns,
+            stream_name
+        );
+
+        Self::store_schema::<S>(stream_name).await?;
+        Ok(schema)
+    }
+
+    // Label columns of an inferred schema which are new with this event
+    fn label_columns_to_add(&self, schema: &Schema) -> Result<Option<Vec<String>>, EventError> {
+        if metadata::STREAM_INFO.is_static_schema(&self.stream_name)? {
+            return Ok(None);
+        }
+
+        let labels = metadata::STREAM_INFO.labels(&self.stream_name)?;
+        let columns = labels.new_columns(schema, &self.body);
+        Ok((!columns.is_empty()).then_some(columns))
+    }
+
+    // Add the label columns to the stream schema and store it. Files written
+    // before lack these columns, these are read as nulls on query.
+    async fn add_label_columns<S: ObjectStorage>(
+        stream_name: &str,
+        columns: &[String],
+    ) -> Result<Schema, EventError> {
+        let schema = metadata::STREAM_INFO.add_label_columns(stream_name, columns)?;
+        log::info!(
+            "added label columns {:?} to logstream {}",
+            columns,
+            stream_name
+        );
+
+        Self::store_schema::<S>(stream_name).await?;
+        Ok(schema)
+    }
+
+    async fn store_schema<S: ObjectStorage>(stream_name: &str) -> Result<(), EventError> {
+        // the schema is read under the lock, so the last upload has every change to it
+        let _guard = SCHEMA_UPLOAD.lock().await;
+        if let Some(latest) = metadata::STREAM_INFO.schema(stream_name)? {
+            S::new().put_schema(stream_name.to_owned(), &latest).await?;
+        }
+
+        Ok(())
+    }
+
+    // Body of the event with values matching the types of the stream schema. Most events
+    // already match it, these are checked in place without inferring their schema.
+    fn conform(self, schema: &Schema) -> Result<Value, EventError> {
+        if coercion::fits_schema(&self.body, schema) {
+            return Ok(self.body);
+        }
+
+        let mut coercion = metadata::STREAM_INFO.coercion(&self.stream_name)?;
+        if metadata::STREAM_INFO.is_static_schema(&self.stream_name)? {
+            // values are coerced to the declared schema instead of being inferred
+            coerce_body(self.body, schema, &coercion)
+        } else {
+            // a string column inferred from earlier events is no reason to
+            // store values of other types as json text
+            coercion.stringify = false;
+            self.coerce_inferred_body(schema, &coercion)
+        }
+    }
+
+    // Schema of the stream when this is its first event, this has all the
+    // columns which the stream config adds to events even if this one lacks them
+    fn first_event_schema(&self) -> Result<Schema, EventError> {
+        let coercion = metadata::STREAM_INFO.coercion(&self.stream_name)?;
+        let mut inferred_schema = self.infer_schema()?;
+        if metadata::STREAM_INFO
+            .guardrails(&self.stream_name)?
+            .is_some()
+        {
+            inferred_schema = Guardrails::with_overflow_column(inferred_schema);
+        }
+        let labels = metadata::STREAM_INFO.labels(&self.stream_name)?;
+        let inferred_schema = labels.with_map_columns(inferred_schema);
+        let enrichment = metadata::STREAM_INFO.enrichment(&self.stream_name)?;
+        let inferred_schema = enrichment.with_columns(inferred_schema);
+        let lookups = metadata::STREAM_INFO.lookups(&self.stream_name)?;
+        Ok(lookup::with_columns(&lookups, inferred_schema))
+    }
+
+    // This is called when the first event of a log stream is received. The first event is
+    // special because we parse this event to generate the schema for the log stream. This
+    // schema is then enforced on rest of the events sent to this log stream.
+    fn process_first_event<S: ObjectStorage>(
+        stream_name: &str,
+        record: &RecordBatch,
+        schema: Schema,
+    ) -> Result<(), EventError> {
+        if !Self::write_first_event(stream_name, record, schema.clone())? {
+            return Ok(());
+        }
+
+        log::info!(
+            "setting schema on objectstore for logstream {}",
+            stream_name
+        );
+        let storage = S::new();
+
+        let stream_name = stream_name.to_owned();
+        spawn(async move {
+            if let Err(e) = storage.put_schema(stream_name.clone(), &schema).await {
+                // If this call has failed then currently there is no right way to make local state consistent
+                // this needs a fix after more constraints are safety guarentee is provided by localwriter and s3_sync.
+                // Reasoning -
+                // - After dropping lock many events may process through
+                // - Processed events may sync before metadata deletion
+                log::error!(
+                    "Parseable failed to upload schema to objectstore due to error {}",
+                    e
+                );
+                log::error!("Please manually delete this logstream and create a new one.");
+                metadata::STREAM_INFO.delete_stream(&stream_name);
+            }
+        });
+
+        Ok(())
+    }
+
+    // Write the first event and set the schema of the stream, returns whether this
+    // set the schema. Another first event may have set it in the meantime.
+    fn write_first_event(
+        stream_name: &str,
+        record: &RecordBatch,
+        schema: Schema,
+    ) -> Result<bool, EventError> {
+        // the buffer is set up before taking the lock, setting it up reads the stream metadata
+        let buffer = STREAM_WRITERS::buffer(stream_name)?;
+
+        // note for functions _schema_with_map and _set_schema_with_map,
+        // these are to be called while holding a write lock specifically.
+        // this guarantees two things
+        // - no other metadata operation can happen inbetween
+        // - map always have an entry for this stream
+
+        let mut stream_metadata = metadata::STREAM_INFO.write().expect(LOCK_EXPECT);
+        // if the metadata is not none after acquiring lock
+        // then some other thread has already completed this function.
+        if _schema_with_map(stream_name, &stream_metadata).is_some() {
+            // drop the lock
+            drop(stream_metadata);
+            // Try to post event usual way
+            log::info!("first event is redirected to process_event");
+            Self::process_event(stream_name, record)?;
+            return Ok(false);
+        }
+
+        // stream metadata is still none,
+        // this means this execution should be considered as first event.
+
+        // Store record batch on local cache
+        log::info!("creating local writer for this first event");
+        buffer.append(record)?;
+
+        log::info!("schema is set in memory map for logstream {}", stream_name);
+        _set_schema_with_map(stream_name, schema, &mut stream_metadata);
+
+        Ok(true)
+    }
+
+    // event process all events after the 1st event. Concatenates record batches
+    // and puts them in memory store for each event.
+    fn process_event(stream_name: &str, record: &RecordBatch) -> Result<(), EventError> {
+        STREAM_WRITERS::append_to_local(stream_name, record)?;
+        Ok(())
+    }
+
+    // Schema of the first event of a stream, later events are only checked against it
+    fn infer_schema(&self) -> Result<Schema, ArrowError> {
+        infer_json_schema_from_iterator(iter::once(Ok(self.body.clone())))
+    }
+
+    // an event with different types is coerced to the schema of this stream
+    fn coerce_inferred_body(
+        self,
+        schema: &Schema,
+        coercion: &Coercion,
+    ) -> Result<Value, EventError> {
+        match coerce_body(self.body, schema, coercion) {
+            Err(EventError::Coercion(e)) => {
+                let reason = format!("{} ({})", self.stream_name, e);
+                Err(EventError::SchemaMismatch(reason))
+            }
+            body => body,
+        }
+    }
+}
+
+fn coerce_body(mut body: Value, schema: &Schema, coercion: &Coercion) -> Result<Value, EventError> {
+    if let Value::Object(ref mut map) = body {
+        coerce_to_schema(map, schema, coercion)?;
+    }
+    Ok(body)
+}
+
+// Arrays of the record batch are built straight from the parsed json
+fn decode(body: Value, arrow_schema: Schema) -> Result<RecordBatch, EventError> {
+    let decoder = Decoder::new(Arc::new(arrow_schema), DecoderOptions::new());
+    decoder
+        .next_batch(&mut iter::once(Ok(body)))?
+        .ok_or(EventError::MissingRecord)
+}
+
+// Size of the event as json, counted without serializing it to a buffer
+fn encoded_len(body: &Value) -> u64 {
+    struct Counter(u64);
+
+    impl Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 += buf.len() as u64;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    serde_json::to_writer(&mut counter, body).expect("writing to a counter can not fail");
+    counter.0
+}
+
+//  Special functions which reads from metadata map while holding the lock
+#[inline]
+pub fn _schema_with_map(
+    stream_name: &str,
+    map: &impl Deref<Target = HashMap<String, metadata::LogStreamMetadata>>,
+) -> Option<Schema> {
+    map.get(stream_name)
+        .expect("map has entry for this stream name")
+        .schema
+        .to_owned()
+}
+
+#[inline]
+//  Special functions which writes to metadata map while holding the lock
+pub fn _set_schema_with_map(
+    stream_name: &str,
+    schema: Schema,
+    map: &mut impl DerefMut<Target = HashMap<String, metadata::LogStreamMetadata>>,
+) {
+    map.get_mut(stream_name)
+        .expect("map has entry for this stream name")
+        .schema
+        .replace(schema);
+}
+
+pub mod error {
+    use crate::metadata::error::stream_info::MetadataError;
+    use crate::storage::ObjectStorageError;
+    use datafusion::arrow::datatypes::DataType;
+    use datafusion::arrow::error::ArrowError;
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum EventError {
+        #[error("Missing Record from event body")]
+        MissingRecord,
+        #[error("Stream Writer Failed: {0}")]
+        StreamWriter(#[from] StreamWriterError),
+        #[error("Metadata Error: {0}")]
+        Metadata(#[from] MetadataError),
+        #[error("Stream Writer Failed: {0}")]
+        Arrow(#[from] ArrowError),
+        #[error("Schema Mismatch: {0}")]
+        SchemaMismatch(String),
+        #[error("Storage Error: {0}")]
+        ObjectStorage(#[from] ObjectStorageError),
+        #[error("Event does not match the schema of this stream: {0}")]
+        Coercion(#[from] CoercionError),
+        #[error("Event does not match the json schema of this stream: {}", .0.join("; "))]
+        JsonSchema(Vec<String>),
+        #[error("Event is written but could not be flushed to disk in time")]
+        NotDurable,
+        #[error("Serde Json Error: {0}")]
+        Serde(#[from] serde_json::Error),
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum CoercionError {
+        #[error("field {0} is not present in the schema")]
+        UnknownField(String),
+        #[error("required field {0} is missing")]
+        MissingField(String),
+        #[error("value of field {field} can not be represented as {data_type:?}")]
+        Incompatible { field: String, data_type: DataType },
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum StreamWriterError {
+        #[error("Arrow writer failed: {0}")]
+        Writer(#[from] ArrowError),
+        #[error("Io Error when creating new file: {0}")]
+        Io(#[from] std::io::Error),
+        #[error("RwLock was poisoned")]
+        RwPoisoned,
+        #[error("Mutex was poisoned")]
+        MutexPoisoned,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::Duration;
+
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use rstest::*;
+    use serde_json::{json, Value};
+
+    use super::buffer::StreamBuffer;
+    use super::wal::WalOptions;
+    use super::{
+        decode, encoded_len, infer_json_schema_from_iterator, iter, Event, STREAM_WRITERS,
+    };
+    use crate::alerts::Alerts;
+    use crate::metadata::STREAM_INFO;
+    use crate::storage::{StorageDir, LEGACY_DATA_GRANULARITY};
+    use crate::utils;
+
+    #[fixture]
+    pub fn body() -> Value {
+        json!({
+            "host": "192.168.1.10",
+            "method": "GET",
+            "path": "/api/v1/query",
+            "status": 200,
+            "latency": 12.5,
+            "user_agent": "Mozilla/5.0 (X11; Linux x86_64)",
+        })
+    }
+
+    #[rstest]
+    fn decodes_parsed_body(body: Value) {
+        let schema = infer_json_schema_from_iterator(iter::once(Ok(body.clone()))).unwrap();
+        let record = decode(body, schema).unwrap();
+
+        assert_eq!(record.num_rows(), 1);
+        let status = record.column(record.schema().index_of("status").unwrap());
+        let status = status.as_any().downcast_ref::<Int64Array>().unwrap();
+        assert_eq!(status.value(0), 200);
+        let method = record.column(record.schema().index_of("method").unwrap());
+        let method = method.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(method.value(0), "GET");
+    }
+
+    #[rstest]
+    fn encoded_len_matches_serialized(body: Value) {
+        assert_eq!(encoded_len(&body), body.to_string().len() as u64);
+    }
+
+    // With a buffer of a single row the first event is written out right away, while
+    // the lock of the stream metadata is held. The write must not wait on that lock.
+    #[rstest]
+    #[serial_test::serial]
+    fn first_event_written_out(body: Value) {
+        let stream_name = "first_event_written_out";
+        let data_path =
+            std::env::temp_dir().join(format!("parseable-{}", utils::uuid::gen().simple()));
+        STREAM_INFO.add_stream(
+            stream_name.to_owned(),
+            None,
+            Alerts::default(),
+            LEGACY_DATA_GRANULARITY,
+        );
+        let wal = WalOptions {
+            max_rows: None,
+            max_bytes: None,
+            fsync: false,
+        };
+        let buffer = StreamBuffer::new(
+            StorageDir::with_data_path(stream_name, data_path.clone()),
+            LEGACY_DATA_GRANULARITY,
+            1,
+            wal,
+        );
+        STREAM_WRITERS
+            .write()
+            .unwrap()
+            .insert(stream_name.to_owned(), Arc::new(buffer));
+
+        let schema = infer_json_schema_from_iterator(iter::once(Ok(body.clone()))).unwrap();
+        let record = decode(body, schema.clone()).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let written =
+                Event::write_first_event(stream_name, &record, schema).map_err(|e| e.to_string());
+            let _ = sender.send(written);
+        });
+
+        let written = receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("first event is written without waiting on the stream metadata");
+        assert_eq!(written, Ok(true));
+        assert!(STREAM_INFO.schema(stream_name).unwrap().is_some());
+        let file = STREAM_WRITERS::open_file(stream_name).unwrap().unwrap();
+        assert!(file.starts_with(&data_path));
+
+        STREAM_WRITERS::delete_entry(stream_name).unwrap();
+        STREAM_INFO.delete_stream(stream_name);
+        let _ = std::fs::remove_dir_all(data_path);
+    }
+}
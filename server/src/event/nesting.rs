@@ -0,0 +1,122 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::utils::{flatten_json_body, FlattenError};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum NestingMode {
+    /// Nested fields become top level columns joined with `_`,
+    /// array elements become one column per index
+    #[default]
+    Flatten,
+    /// Arrays and objects are kept as Arrow List and Struct columns
+    Nested,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Nesting {
+    #[serde(default)]
+    pub mode: NestingMode,
+    /// In nested mode, arrays and objects deeper than this are stored as JSON strings
+    #[serde(default = "default_max_depth")]
+    pub max_depth: usize,
+}
+
+fn default_max_depth() -> usize {
+    4
+}
+
+impl Default for Nesting {
+    fn default() -> Self {
+        Self {
+            mode: NestingMode::default(),
+            max_depth: default_max_depth(),
+        }
+    }
+}
+
+impl Nesting {
+    /// Shape the event body into the columns of the stream
+    pub fn apply(&self, body: &Value) -> Result<Value, FlattenError> {
+        match self.mode {
+            NestingMode::Flatten => flatten_json_body(body),
+            NestingMode::Nested => {
+                let mut body = body.clone();
+                if let Value::Object(ref mut map) = body {
+                    for value in map.values_mut() {
+                        limit_depth(value, 1, self.max_depth);
+                    }
+                }
+                Ok(body)
+            }
+        }
+    }
+}
+
+// Top level fields of an event are at depth 1
+fn limit_depth(value: &mut Value, depth: usize, max_depth: usize) {
+    match value {
+        Value::Array(_) | Value::Object(_) if depth >= max_depth => {
+            *value = Value::String(value.to_string());
+        }
+        Value::Array(array) => array
+            .iter_mut()
+            .for_each(|value| limit_depth(value, depth + 1, max_depth)),
+        // an empty object would infer a struct without fields, which can not be stored
+        Value::Object(map) if map.is_empty() => *value = Value::Null,
+        Value::Object(map) => map
+            .values_mut()
+            .for_each(|value| limit_depth(value, depth + 1, max_depth)),
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use serde_json::{json, Value};
+
+    use super::{Nesting, NestingMode};
+
+    #[rstest]
+    #[case::within_depth(3, json!({"a": {"b": [1, 2]}}), json!({"a": {"b": [1, 2]}}))]
+    #[case::beyond_depth(2, json!({"a": {"b": [1, 2]}}), json!({"a": {"b": "[1,2]"}}))]
+    #[case::top_level_only(1, json!({"a": {"b": 1}, "c": 1}), json!({"a": r#"{"b":1}"#, "c": 1}))]
+    #[case::empty_object(4, json!({"a": {}}), json!({"a": null}))]
+    fn nested_mode(#[case] max_depth: usize, #[case] body: Value, #[case] expected: Value) {
+        let nesting = Nesting {
+            mode: NestingMode::Nested,
+            max_depth,
+        };
+        assert_eq!(nesting.apply(&body).unwrap(), expected);
+    }
+
+    #[rstest]
+    fn flatten_mode_is_default() {
+        let nesting: Nesting = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(
+            nesting.apply(&json!({"a": {"b": 1}})).unwrap(),
+            json!({"a_b": 1})
+        );
+    }
+}
@@ -0,0 +1,141 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::alerts::rule::base::ops::StringOperator;
+
+// Column which holds the rate at which an event was kept.
+// Counts can be re-scaled in queries by dividing with this value.
+pub const SAMPLE_RATE_COLUMN: &str = "p_sample_rate";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sampling {
+    /// Rules are evaluated in order and the first matching rule decides the rate
+    #[serde(default)]
+    pub rules: Vec<SamplingRule>,
+    /// Rate for events which do not match any rule, between 0 and 1
+    #[serde(default = "one")]
+    pub rate: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SamplingRule {
+    pub column: String,
+    #[serde(default = "exact")]
+    pub operator: StringOperator,
+    pub value: String,
+    #[serde(default = "one")]
+    pub rate: f64,
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+fn exact() -> StringOperator {
+    StringOperator::Exact
+}
+
+impl Sampling {
+    /// Returns the rate this event was sampled at, or None if the event should be dropped
+    pub fn sample(&self, event: &Value) -> Option<f64> {
+        let rate = self.rate_for(event);
+
+        if rate >= 1.0 || (rate > 0.0 && rand::thread_rng().gen_bool(rate)) {
+            Some(rate)
+        } else {
+            None
+        }
+    }
+
+    fn rate_for(&self, event: &Value) -> f64 {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(event))
+            .map_or(self.rate, |rule| rule.rate)
+    }
+}
+
+impl SamplingRule {
+    fn matches(&self, event: &Value) -> bool {
+        let value = match event.get(&self.column) {
+            Some(Value::String(value)) => value.to_owned(),
+            Some(Value::Null) | None => return false,
+            Some(value) => value.to_string(),
+        };
+
+        match self.operator {
+            StringOperator::Exact => value.eq(&self.value),
+            StringOperator::NotExact => !value.eq(&self.value),
+            StringOperator::Contains => value.contains(&self.value),
+            StringOperator::NotContains => !value.contains(&self.value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use serde_json::{json, Value};
+
+    use super::Sampling;
+
+    #[fixture]
+    fn sampling() -> Sampling {
+        serde_json::from_value(json!({
+            "rules": [
+                { "column": "level", "value": "error" },
+                { "column": "level", "value": "debug", "rate": 0.0 }
+            ],
+            "rate": 0.5
+        }))
+        .unwrap()
+    }
+
+    #[rstest]
+    #[case::matching_rule(json!({"level": "error"}), 1.0)]
+    #[case::matching_second_rule(json!({"level": "debug"}), 0.0)]
+    #[case::no_matching_rule(json!({"level": "info"}), 0.5)]
+    #[case::missing_column(json!({"message": "error"}), 0.5)]
+    fn rate_for_event(sampling: Sampling, #[case] event: Value, #[case] rate: f64) {
+        assert_eq!(sampling.rate_for(&event), rate);
+    }
+
+    #[rstest]
+    fn keeps_and_drops(sampling: Sampling) {
+        assert_eq!(sampling.sample(&json!({"level": "error"})), Some(1.0));
+        assert_eq!(sampling.sample(&json!({"level": "debug"})), None);
+    }
+
+    #[rstest]
+    fn numeric_values_are_compared_as_strings() {
+        let sampling: Sampling = serde_json::from_value(json!({
+            "rules": [{ "column": "status", "value": "500" }],
+            "rate": 0.0
+        }))
+        .unwrap();
+
+        assert_eq!(sampling.sample(&json!({"status": 500})), Some(1.0));
+        assert_eq!(sampling.sample(&json!({"status": 200})), None);
+    }
+}
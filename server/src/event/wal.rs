@@ -0,0 +1,227 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::ipc::writer::StreamWriter;
+use datafusion::arrow::record_batch::RecordBatch;
+use lazy_static::lazy_static;
+use tokio::sync::watch;
+
+use crate::option::{AckMode, FsyncPolicy, CONFIG};
+
+use super::error::{EventError, StreamWriterError};
+use super::STREAM_WRITERS;
+
+// Interval syncs started so far. A sync only covers the writes which
+// completed before it started, that is what durable requests wait for.
+static SYNCS_STARTED: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    // Interval syncs completed so far
+    static ref SYNCS_COMPLETED: (watch::Sender<u64>, watch::Receiver<u64>) = watch::channel(0);
+}
+
+/// Limits of a local log file, and whether it is flushed to disk once finished
+#[derive(Debug, Clone, Copy)]
+pub struct WalOptions {
+    pub max_rows: Option<usize>,
+    pub max_bytes: Option<u64>,
+    pub fsync: bool,
+}
+
+impl WalOptions {
+    pub fn from_config() -> Self {
+        Self {
+            max_rows: CONFIG.parseable.rotate_max_rows,
+            max_bytes: CONFIG.parseable.rotate_max_bytes,
+            fsync: CONFIG.parseable.wal_fsync != FsyncPolicy::None,
+        }
+    }
+}
+
+/// Local write-ahead log file of a stream. Events are appended as an Arrow IPC stream,
+/// which is converted to parquet once the file is finished.
+pub struct WalWriter {
+    writer: StreamWriter<File>,
+    // second handle of the same file, the stream writer does not expose its own
+    file: File,
+    path: PathBuf,
+    // time slot of the events in the file
+    slot: String,
+    schema: SchemaRef,
+    rows: usize,
+    // batches were written since the last sync
+    dirty: bool,
+    options: WalOptions,
+}
+
+impl WalWriter {
+    pub fn try_new(
+        file: File,
+        path: PathBuf,
+        slot: String,
+        record: &RecordBatch,
+        options: WalOptions,
+    ) -> Result<Self, StreamWriterError> {
+        let handle = file.try_clone()?;
+        let writer = StreamWriter::try_new(file, &record.schema())
+            .expect("File and RecordBatch both are checked");
+
+        let mut wal = Self {
+            writer,
+            file: handle,
+            path,
+            slot,
+            schema: record.schema(),
+            rows: 0,
+            dirty: false,
+            options,
+        };
+        wal.write(record)?;
+
+        Ok(wal)
+    }
+
+    pub fn write(&mut self, record: &RecordBatch) -> Result<(), StreamWriterError> {
+        // the stream writer flushes its buffer after every batch,
+        // so the batch is in the file once this returns
+        self.writer.write(record)?;
+        self.rows += record.num_rows();
+        self.dirty = true;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn slot(&self) -> &str {
+        &self.slot
+    }
+
+    pub fn schema(&self) -> &SchemaRef {
+        &self.schema
+    }
+
+    /// The file has reached the row or size limit of a local log file
+    pub fn is_full(&self) -> bool {
+        let over_rows = self
+            .options
+            .max_rows
+            .map_or(false, |max_rows| self.rows >= max_rows);
+        let over_bytes = self.options.max_bytes.map_or(false, |max_bytes| {
+            self.file
+                .metadata()
+                .map_or(false, |metadata| metadata.len() >= max_bytes)
+        });
+
+        over_rows || over_bytes
+    }
+
+    /// Flush the batches written so far to disk
+    pub fn sync(&mut self) -> Result<(), StreamWriterError> {
+        if self.dirty {
+            self.file.sync_data()?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// End the IPC stream, the file is flushed to disk unless fsync is disabled
+    pub fn finish(self) -> Result<(), StreamWriterError> {
+        let Self {
+            mut writer,
+            file,
+            options,
+            ..
+        } = self;
+
+        writer.finish()?;
+        // dropping the writer flushes the end of stream marker out of its buffer
+        drop(writer);
+
+        if options.fsync {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+/// Write out the buffered events and flush every open log file to disk,
+/// run periodically with the interval fsync policy
+pub fn sync_interval() -> Result<(), StreamWriterError> {
+    let generation = SYNCS_STARTED.fetch_add(1, Ordering::SeqCst) + 1;
+    STREAM_WRITERS::sync_all()?;
+    // only published on success, waiters are released by the next successful sync
+    let _ = SYNCS_COMPLETED.0.send(generation);
+    Ok(())
+}
+
+/// Make the events a request wrote to `stream_name` durable, as required by
+/// the fsync policy and ack mode. This is called before the request is answered.
+pub async fn commit(stream_name: &str) -> Result<(), EventError> {
+    match (CONFIG.parseable.wal_fsync, CONFIG.parseable.ack_mode) {
+        (FsyncPolicy::Request, _) => STREAM_WRITERS::sync(stream_name)?,
+        (FsyncPolicy::Interval, AckMode::Durable) => {
+            // a few missed intervals means the disk is failing, do not hold the request forever
+            let timeout = Duration::from_secs(10 * CONFIG.parseable.wal_fsync_interval);
+            wait_for_sync(timeout).await?
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+// Wait for an interval sync which starts after the writes of this request
+async fn wait_for_sync(timeout: Duration) -> Result<(), EventError> {
+    let target = SYNCS_STARTED.load(Ordering::SeqCst) + 1;
+    let mut completed = SYNCS_COMPLETED.1.clone();
+
+    let wait = async move {
+        while *completed.borrow() < target {
+            if completed.changed().await.is_err() {
+                break;
+            }
+        }
+    };
+
+    tokio::time::timeout(timeout, wait)
+        .await
+        .map_err(|_| EventError::NotDurable)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::event::error::EventError;
+
+    use super::wait_for_sync;
+
+    // no interval sync runs, the request is answered with an error instead of waiting on
+    #[tokio::test]
+    async fn wait_for_sync_times_out() {
+        let waited = wait_for_sync(Duration::from_millis(50)).await;
+        assert!(matches!(waited, Err(EventError::NotDurable)));
+    }
+}
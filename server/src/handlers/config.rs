@@ -0,0 +1,576 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use datafusion::arrow::datatypes::Schema;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::event::coercion::Coercion;
+use crate::event::dead_letter::DeadLetter;
+use crate::event::dedup::Dedup;
+use crate::event::enrichment::Enrichment;
+use crate::event::explode::Explode;
+use crate::event::guardrails::{Guardrails, OVERFLOW_COLUMN};
+use crate::event::json_schema::JsonSchema;
+use crate::event::labels::Labels;
+use crate::event::lookup::{self, LookupJoin};
+use crate::event::nesting::Nesting;
+use crate::event::sampling::{Sampling, SAMPLE_RATE_COLUMN};
+use crate::lookup::LOOKUP_TABLES;
+use crate::metadata::error::stream_info::MetadataError;
+use crate::metadata::STREAM_INFO;
+use crate::option::CONFIG;
+use crate::parquet::ParquetConfig;
+use crate::retention::Retention;
+use crate::s3::S3;
+use crate::storage::{ObjectStorage, ObjectStoreFormat};
+use crate::{response, validator};
+
+use self::error::ConfigError;
+
+/// A setting of a log stream, kept in memory and in the `.parseable.json` of the stream.
+/// Every setting is read and written through `/logstream/{logstream}/<setting>`.
+pub trait StreamConfig: Serialize + DeserializeOwned + Clone + Send + Sync + 'static {
+    /// Name of the setting in responses
+    const NAME: &'static str;
+
+    /// Check the setting against the stream it is set for.
+    fn prepare(&mut self, _stream_name: &str, _schema: Option<&Schema>) -> Result<(), ConfigError> {
+        Ok(())
+    }
+
+    fn get(stream_name: &str) -> Result<Option<Self>, MetadataError>;
+
+    fn set(self, stream_name: &str) -> Result<(), MetadataError>;
+
+    fn store(self, format: &mut ObjectStoreFormat);
+}
+
+pub async fn put_config<C: StreamConfig>(req: HttpRequest, body: web::Json<Value>) -> HttpResponse {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    let config: C = match serde_json::from_value(body.into_inner()) {
+        Ok(config) => config,
+        Err(e) => {
+            return response::ServerResponse {
+                msg: format!(
+                    "failed to set {} for log stream {} due to err: {}",
+                    C::NAME,
+                    stream_name,
+                    e
+                ),
+                code: StatusCode::BAD_REQUEST,
+            }
+            .to_http()
+        }
+    };
+
+    set_config(&stream_name, config).await
+}
+
+pub async fn get_config<C: StreamConfig>(req: HttpRequest) -> HttpResponse {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    match C::get(&stream_name) {
+        Ok(Some(config)) => response::ServerResponse {
+            msg: serde_json::to_string(&config).expect("settings can serialize to valid json"),
+            code: StatusCode::OK,
+        }
+        .to_http(),
+        Ok(None) => response::ServerResponse {
+            msg: format!("{} not set for log stream {}", C::NAME, stream_name),
+            code: StatusCode::BAD_REQUEST,
+        }
+        .to_http(),
+        Err(e) => response::ServerResponse {
+            msg: format!("could not get {} due to error: {}", C::NAME, e),
+            code: StatusCode::BAD_REQUEST,
+        }
+        .to_http(),
+    }
+}
+
+pub async fn put_dead_letter(req: HttpRequest, body: web::Json<Value>) -> HttpResponse {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+
+    let dead_letter: DeadLetter = match serde_json::from_value(body.into_inner()) {
+        Ok(dead_letter) => dead_letter,
+        Err(e) => {
+            return response::ServerResponse {
+                msg: format!(
+                    "failed to set {} for log stream {} due to err: {}",
+                    DeadLetter::NAME,
+                    stream_name,
+                    e
+                ),
+                code: StatusCode::BAD_REQUEST,
+            }
+            .to_http()
+        }
+    };
+
+    if let Err(e) = validator::dead_letter(&stream_name, &dead_letter) {
+        return response::ServerResponse {
+            msg: format!(
+                "failed to set {} for log stream {} due to err: {}",
+                DeadLetter::NAME,
+                stream_name,
+                e
+            ),
+            code: StatusCode::BAD_REQUEST,
+        }
+        .to_http();
+    }
+
+    if STREAM_INFO.schema(&stream_name).is_err() {
+        return response::ServerResponse {
+            msg: "log stream is not found".to_string(),
+            code: StatusCode::BAD_REQUEST,
+        }
+        .to_http();
+    }
+
+    // The dead-letter stream is created with its fixed schema if it doesn't exist yet.
+    // An existing stream can only be used if it was created as a dead-letter stream.
+    match STREAM_INFO.schema(&dead_letter.stream) {
+        Ok(Some(schema)) if schema.fields() == DeadLetter::schema().fields() => (),
+        Ok(_) => {
+            return response::ServerResponse {
+                msg: format!(
+                    "log stream {} already exists and is not a dead-letter stream",
+                    dead_letter.stream
+                ),
+                code: StatusCode::BAD_REQUEST,
+            }
+            .to_http()
+        }
+        Err(_) => {
+            let schema = DeadLetter::schema();
+            if let Err(e) = S3::new()
+                .create_stream(&dead_letter.stream, Some(&schema))
+                .await
+            {
+                return response::ServerResponse {
+                    msg: format!(
+                        "failed to create dead-letter stream {} due to err: {}",
+                        dead_letter.stream, e
+                    ),
+                    code: StatusCode::INTERNAL_SERVER_ERROR,
+                }
+                .to_http();
+            }
+            STREAM_INFO.add_static_stream(
+                dead_letter.stream.clone(),
+                schema,
+                CONFIG.parseable.data_granularity,
+            );
+        }
+    }
+
+    set_config(&stream_name, dead_letter).await
+}
+
+async fn set_config<C: StreamConfig>(stream_name: &str, mut config: C) -> HttpResponse {
+    let schema = match STREAM_INFO.schema(stream_name) {
+        Ok(schema) => schema,
+        Err(_) => {
+            return response::ServerResponse {
+                msg: "log stream is not found".to_string(),
+                code: StatusCode::BAD_REQUEST,
+            }
+            .to_http()
+        }
+    };
+
+    if let Err(e) = config.prepare(stream_name, schema.as_ref()) {
+        let msg = match e {
+            ConfigError::Invalid(e) => format!(
+                "failed to set {} for log stream {} due to err: {}",
+                C::NAME,
+                stream_name,
+                e
+            ),
+            e => e.to_string(),
+        };
+        return response::ServerResponse {
+            msg,
+            code: StatusCode::BAD_REQUEST,
+        }
+        .to_http();
+    }
+
+    let stored = config.clone();
+    if let Err(e) = S3::new()
+        .update_stream_metadata(stream_name, move |format| stored.store(format))
+        .await
+    {
+        return response::ServerResponse {
+            msg: format!(
+                "failed to set {} for log stream {} due to err: {}",
+                C::NAME,
+                stream_name,
+                e
+            ),
+            code: StatusCode::INTERNAL_SERVER_ERROR,
+        }
+        .to_http();
+    }
+
+    if let Err(e) = config.set(stream_name) {
+        return response::ServerResponse {
+            msg: format!(
+                "failed to set {} for log stream {} due to err: {}",
+                C::NAME,
+                stream_name,
+                e
+            ),
+            code: StatusCode::INTERNAL_SERVER_ERROR,
+        }
+        .to_http();
+    }
+
+    response::ServerResponse {
+        msg: format!("set {} for log stream {}", C::NAME, stream_name),
+        code: StatusCode::OK,
+    }
+    .to_http()
+}
+
+// Settings which add columns to events can only be set before the first event
+// sets the schema, unless the schema of the stream already has those columns.
+fn require_columns<'a>(
+    stream_name: &str,
+    schema: Option<&Schema>,
+    columns: impl IntoIterator<Item = &'a str>,
+    name: &'static str,
+) -> Result<(), ConfigError> {
+    let Some(schema) = schema else {
+        return Ok(());
+    };
+
+    match columns
+        .into_iter()
+        .find(|column| schema.column_with_name(column).is_none())
+    {
+        Some(column) => Err(ConfigError::MissingColumn(
+            stream_name.to_owned(),
+            column.to_owned(),
+            name,
+        )),
+        None => Ok(()),
+    }
+}
+
+impl StreamConfig for Sampling {
+    const NAME: &'static str = "sampling";
+
+    fn prepare(&mut self, stream_name: &str, schema: Option<&Schema>) -> Result<(), ConfigError> {
+        validator::sampling(self).map_err(|e| ConfigError::Invalid(e.to_string()))?;
+        require_columns(stream_name, schema, [SAMPLE_RATE_COLUMN], Self::NAME)
+    }
+
+    fn get(stream_name: &str) -> Result<Option<Self>, MetadataError> {
+        STREAM_INFO.sampling(stream_name)
+    }
+
+    fn set(self, stream_name: &str) -> Result<(), MetadataError> {
+        STREAM_INFO.set_sampling(stream_name, self)
+    }
+
+    fn store(self, format: &mut ObjectStoreFormat) {
+        format.sampling = Some(self);
+    }
+}
+
+impl StreamConfig for Dedup {
+    const NAME: &'static str = "dedup";
+
+    fn prepare(&mut self, _: &str, _: Option<&Schema>) -> Result<(), ConfigError> {
+        validator::dedup(self).map_err(|e| ConfigError::Invalid(e.to_string()))
+    }
+
+    fn get(stream_name: &str) -> Result<Option<Self>, MetadataError> {
+        Ok(STREAM_INFO
+            .dedup(stream_name)?
+            .map(|dedup| (*dedup).clone()))
+    }
+
+    fn set(self, stream_name: &str) -> Result<(), MetadataError> {
+        STREAM_INFO.set_dedup(stream_name, self)
+    }
+
+    fn store(self, format: &mut ObjectStoreFormat) {
+        format.dedup = Some(self);
+    }
+}
+
+impl StreamConfig for Coercion {
+    const NAME: &'static str = "coercion";
+
+    fn get(stream_name: &str) -> Result<Option<Self>, MetadataError> {
+        STREAM_INFO.coercion(stream_name).map(Some)
+    }
+
+    fn set(self, stream_name: &str) -> Result<(), MetadataError> {
+        STREAM_INFO.set_coercion(stream_name, self)
+    }
+
+    fn store(self, format: &mut ObjectStoreFormat) {
+        format.coercion = self;
+    }
+}
+
+impl StreamConfig for JsonSchema {
+    const NAME: &'static str = "json schema";
+
+    fn get(stream_name: &str) -> Result<Option<Self>, MetadataError> {
+        Ok(STREAM_INFO
+            .json_schema(stream_name)?
+            .map(|json_schema| (*json_schema).clone()))
+    }
+
+    fn set(self, stream_name: &str) -> Result<(), MetadataError> {
+        STREAM_INFO.set_json_schema(stream_name, self)
+    }
+
+    fn store(self, format: &mut ObjectStoreFormat) {
+        format.json_schema = Some(self);
+    }
+}
+
+impl StreamConfig for Nesting {
+    const NAME: &'static str = "nesting";
+
+    // The nesting mode decides the columns of an inferred schema,
+    // so it can not change once the first event has set the schema.
+    fn prepare(&mut self, stream_name: &str, schema: Option<&Schema>) -> Result<(), ConfigError> {
+        validator::nesting(self).map_err(|e| ConfigError::Invalid(e.to_string()))?;
+
+        if schema.is_some()
+            && !STREAM_INFO
+                .is_static_schema(stream_name)
+                .unwrap_or_default()
+        {
+            return Err(ConfigError::Initialized(stream_name.to_owned(), Self::NAME));
+        }
+
+        Ok(())
+    }
+
+    fn get(stream_name: &str) -> Result<Option<Self>, MetadataError> {
+        STREAM_INFO.nesting(stream_name).map(Some)
+    }
+
+    fn set(self, stream_name: &str) -> Result<(), MetadataError> {
+        STREAM_INFO.set_nesting(stream_name, self)
+    }
+
+    fn store(self, format: &mut ObjectStoreFormat) {
+        format.nesting = self;
+    }
+}
+
+impl StreamConfig for Explode {
+    const NAME: &'static str = "explode";
+
+    fn prepare(&mut self, _: &str, _: Option<&Schema>) -> Result<(), ConfigError> {
+        validator::explode(self).map_err(|e| ConfigError::Invalid(e.to_string()))
+    }
+
+    fn get(stream_name: &str) -> Result<Option<Self>, MetadataError> {
+        STREAM_INFO.explode(stream_name)
+    }
+
+    fn set(self, stream_name: &str) -> Result<(), MetadataError> {
+        STREAM_INFO.set_explode(stream_name, self)
+    }
+
+    fn store(self, format: &mut ObjectStoreFormat) {
+        format.explode = Some(self);
+    }
+}
+
+impl StreamConfig for Guardrails {
+    const NAME: &'static str = "guardrails";
+
+    fn prepare(&mut self, stream_name: &str, schema: Option<&Schema>) -> Result<(), ConfigError> {
+        validator::guardrails(self).map_err(|e| ConfigError::Invalid(e.to_string()))?;
+        require_columns(stream_name, schema, [OVERFLOW_COLUMN], Self::NAME)
+    }
+
+    fn get(stream_name: &str) -> Result<Option<Self>, MetadataError> {
+        STREAM_INFO.guardrails(stream_name)
+    }
+
+    fn set(self, stream_name: &str) -> Result<(), MetadataError> {
+        STREAM_INFO.set_guardrails(stream_name, self)
+    }
+
+    fn store(self, format: &mut ObjectStoreFormat) {
+        format.guardrails = Some(self);
+    }
+}
+
+impl StreamConfig for Labels {
+    const NAME: &'static str = "labels";
+
+    // The format decides the type and the names of the label columns,
+    // so it can not change once the schema of the stream is set.
+    fn prepare(&mut self, stream_name: &str, schema: Option<&Schema>) -> Result<(), ConfigError> {
+        match schema {
+            Some(_) => Err(ConfigError::Initialized(stream_name.to_owned(), Self::NAME)),
+            None => Ok(()),
+        }
+    }
+
+    fn get(stream_name: &str) -> Result<Option<Self>, MetadataError> {
+        STREAM_INFO.labels(stream_name).map(Some)
+    }
+
+    fn set(self, stream_name: &str) -> Result<(), MetadataError> {
+        STREAM_INFO.set_labels(stream_name, self)
+    }
+
+    fn store(self, format: &mut ObjectStoreFormat) {
+        format.labels = self;
+    }
+}
+
+impl StreamConfig for Enrichment {
+    const NAME: &'static str = "enrichment";
+
+    fn prepare(&mut self, stream_name: &str, schema: Option<&Schema>) -> Result<(), ConfigError> {
+        require_columns(stream_name, schema, self.columns(), Self::NAME)
+    }
+
+    fn get(stream_name: &str) -> Result<Option<Self>, MetadataError> {
+        STREAM_INFO.enrichment(stream_name).map(Some)
+    }
+
+    fn set(self, stream_name: &str) -> Result<(), MetadataError> {
+        STREAM_INFO.set_enrichment(stream_name, self)
+    }
+
+    fn store(self, format: &mut ObjectStoreFormat) {
+        format.enrichment = self;
+    }
+}
+
+impl StreamConfig for Vec<LookupJoin> {
+    const NAME: &'static str = "lookups";
+
+    fn prepare(&mut self, stream_name: &str, schema: Option<&Schema>) -> Result<(), ConfigError> {
+        for lookup in self.iter_mut() {
+            if let Some(table) = LOOKUP_TABLES.get(&lookup.table) {
+                lookup.resolve_columns(&table);
+            }
+        }
+
+        validator::lookups(self).map_err(|e| ConfigError::Invalid(e.to_string()))?;
+        require_columns(
+            stream_name,
+            schema,
+            lookup::columns(self).map(String::as_str),
+            Self::NAME,
+        )
+    }
+
+    fn get(stream_name: &str) -> Result<Option<Self>, MetadataError> {
+        STREAM_INFO.lookups(stream_name).map(Some)
+    }
+
+    fn set(self, stream_name: &str) -> Result<(), MetadataError> {
+        STREAM_INFO.set_lookups(stream_name, self)
+    }
+
+    fn store(self, format: &mut ObjectStoreFormat) {
+        format.lookups = self;
+    }
+}
+
+impl StreamConfig for ParquetConfig {
+    const NAME: &'static str = "parquet settings";
+
+    fn prepare(&mut self, _: &str, _: Option<&Schema>) -> Result<(), ConfigError> {
+        validator::parquet(self).map_err(|e| ConfigError::Invalid(e.to_string()))
+    }
+
+    fn get(stream_name: &str) -> Result<Option<Self>, MetadataError> {
+        STREAM_INFO.parquet(stream_name).map(Some)
+    }
+
+    fn set(self, stream_name: &str) -> Result<(), MetadataError> {
+        STREAM_INFO.set_parquet(stream_name, self)
+    }
+
+    fn store(self, format: &mut ObjectStoreFormat) {
+        format.parquet = self;
+    }
+}
+
+impl StreamConfig for Retention {
+    const NAME: &'static str = "retention";
+
+    fn prepare(&mut self, _: &str, _: Option<&Schema>) -> Result<(), ConfigError> {
+        validator::retention(self).map_err(|e| ConfigError::Invalid(e.to_string()))
+    }
+
+    fn get(stream_name: &str) -> Result<Option<Self>, MetadataError> {
+        STREAM_INFO.retention(stream_name)
+    }
+
+    fn set(self, stream_name: &str) -> Result<(), MetadataError> {
+        STREAM_INFO.set_retention(stream_name, self)
+    }
+
+    fn store(self, format: &mut ObjectStoreFormat) {
+        format.retention = Some(self);
+    }
+}
+
+// checked along with its target stream by put_dead_letter
+impl StreamConfig for DeadLetter {
+    const NAME: &'static str = "dead-letter stream";
+
+    fn get(stream_name: &str) -> Result<Option<Self>, MetadataError> {
+        STREAM_INFO.dead_letter(stream_name)
+    }
+
+    fn set(self, stream_name: &str) -> Result<(), MetadataError> {
+        STREAM_INFO.set_dead_letter(stream_name, self)
+    }
+
+    fn store(self, format: &mut ObjectStoreFormat) {
+        format.dead_letter = Some(self);
+    }
+}
+
+pub mod error {
+    #[derive(Debug, thiserror::Error)]
+    pub enum ConfigError {
+        #[error("{0}")]
+        Invalid(String),
+        #[error("log stream {0} is already initialized without {1} column, {2} can only be set before posting the first event")]
+        MissingColumn(String, String, &'static str),
+        #[error("log stream {0} is already initialized, {1} can only be set before posting the first event")]
+        Initialized(String, &'static str),
+    }
+}
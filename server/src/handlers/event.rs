@@ -16,23 +16,102 @@
  *
  */
 
-use std::collections::HashMap;
+use std::sync::Arc;
 
+use actix_web::http::StatusCode;
 use actix_web::{web, HttpRequest, HttpResponse};
-use serde_json::Value;
+use serde::Serialize;
+use serde_json::{Map, Value};
 
 use crate::event;
+use crate::event::dead_letter::DeadLetter;
+use crate::event::dedup::Dedup;
+use crate::event::explode::Explode;
+use crate::event::guardrails::{Guardrails, OVERFLOW_COLUMN};
+use crate::event::json_schema::JsonSchema;
+use crate::event::lookup::LookupJoin;
+use crate::event::nesting::Nesting;
+use crate::event::sampling::{Sampling, SAMPLE_RATE_COLUMN};
+use crate::event::wal;
+use crate::metadata::STREAM_INFO;
+use crate::option::CONFIG;
 use crate::query::Query;
 use crate::response::QueryResponse;
 use crate::s3::S3;
-use crate::utils::header_parsing::collect_labelled_headers;
-use crate::utils::{self, flatten_json_body, merge};
+use crate::staging;
+use crate::utils::header_parsing::{collect_labels, ParseHeaderError};
+use crate::utils::merge;
 
 use self::error::{PostError, QueryError};
 
+pub const TAGS_KEY: &str = "p_tags";
+pub const METADATA_KEY: &str = "p_metadata";
 const PREFIX_TAGS: &str = "x-p-tag-";
 const PREFIX_META: &str = "x-p-meta-";
-const SEPARATOR: char = '^';
+const IDEMPOTENCY_KEY: &str = "x-p-idempotency-key";
+const ATOMIC_KEY: &str = "x-p-atomic";
+
+#[derive(Debug, Default, Serialize)]
+struct PostResponse {
+    accepted: usize,
+    deduplicated: usize,
+    dead_lettered: usize,
+    // events of an array body which could not be ingested
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<ItemError>,
+}
+
+#[derive(Debug, Serialize)]
+struct ItemError {
+    // position of the event in the array, after it is exploded
+    index: usize,
+    error: String,
+    // the event can succeed if it is sent again
+    retryable: bool,
+}
+
+impl PostResponse {
+    fn count(&mut self, ingested: Ingested) {
+        match ingested {
+            Ingested::Accepted => self.accepted += 1,
+            Ingested::Deduplicated => self.deduplicated += 1,
+            Ingested::Dropped => (),
+        }
+    }
+
+    fn push_error(&mut self, index: usize, error: &PostError) {
+        self.errors.push(ItemError {
+            index,
+            error: error.to_string(),
+            retryable: !error.is_rejection(),
+        })
+    }
+
+    fn to_http(&self) -> HttpResponse {
+        let status = if self.errors.is_empty() {
+            StatusCode::OK
+        } else {
+            StatusCode::MULTI_STATUS
+        };
+        HttpResponse::build(status).json(self)
+    }
+}
+
+enum Ingested {
+    Accepted,
+    Deduplicated,
+    Dropped,
+}
+
+// An event which went through every step before the write
+enum Prepared {
+    Ready {
+        event: event::Event,
+        dedup_key: Option<String>,
+    },
+    Deduplicated,
+    Dropped,
+}
 
 pub async fn query(_req: HttpRequest, json: web::Json<Value>) -> Result<HttpResponse, QueryError> {
     let json = json.into_inner();
@@ -52,54 +131,355 @@ pub async fn post_event(
     req: HttpRequest,
     body: web::Json<serde_json::Value>,
 ) -> Result<HttpResponse, PostError> {
+    if staging::under_pressure() {
+        return Err(PostError::DiskPressure);
+    }
+
     let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let dead_letter = STREAM_INFO.dead_letter(&stream_name)?;
 
-    let tags = HashMap::from([(
-        "p_tags".to_string(),
-        collect_labelled_headers(&req, PREFIX_TAGS, SEPARATOR)?,
-    )]);
-
-    let metadata = HashMap::from([(
-        "p_metadata".to_string(),
-        collect_labelled_headers(&req, PREFIX_META, SEPARATOR)?,
-    )]);
-
-    if let Some(array) = body.as_array() {
-        for body in array {
-            let body = merge(body.clone(), metadata.clone());
-            let body = merge(body, tags.clone());
-            let body = flatten_json_body(web::Json(body)).unwrap();
-
-            let event = event::Event {
-                body,
-                stream_name: stream_name.clone(),
-            };
+    let explode = STREAM_INFO.explode(&stream_name)?;
+    let (bodies, is_batch) = split_body(body.into_inner(), explode.as_ref());
+    let atomic = is_batch && is_atomic(&req)?;
 
-            event.process().await?;
+    let mut response = PostResponse::default();
+
+    let context = match IngestContext::new(&req, stream_name.clone()) {
+        Ok(context) => context,
+        // none of the events can be ingested if the headers are invalid
+        Err(error) if error.is_rejection() => {
+            reject(dead_letter.as_ref(), &stream_name, &bodies, error).await?;
+            response.dead_lettered = bodies.len();
+            commit(&stream_name, dead_letter.as_ref(), &response).await?;
+            return Ok(HttpResponse::Ok().json(response));
         }
-    } else {
-        let body = merge(body.clone(), metadata);
-        let body = merge(body, tags);
+        Err(error) => return Err(error),
+    };
 
-        let event = event::Event {
-            body: utils::flatten_json_body(web::Json(body)).unwrap(),
+    if atomic {
+        return ingest_atomic(&context, &bodies).await;
+    }
+
+    // a failed event does not stop the rest of an array,
+    // its error is listed in the response instead
+    for (position, body) in bodies.into_iter().enumerate() {
+        let error = match context.ingest(position, &body).await {
+            Ok(ingested) => {
+                response.count(ingested);
+                continue;
+            }
+            Err(error) if error.is_rejection() => {
+                let bodies = std::slice::from_ref(&body);
+                match reject(dead_letter.as_ref(), &stream_name, bodies, error).await {
+                    Ok(()) => {
+                        response.dead_lettered += 1;
+                        continue;
+                    }
+                    Err(error) => error,
+                }
+            }
+            Err(error) => error,
+        };
+
+        if !is_batch {
+            return Err(error);
+        }
+        response.push_error(position, &error);
+    }
+
+    commit(&stream_name, dead_letter.as_ref(), &response).await?;
+
+    Ok(response.to_http())
+}
+
+// Events of a body, with envelopes split into their records before anything else.
+// An envelope of many records is a batch just like an array body, each of its
+// records has its own result and the atomic mode applies to it.
+fn split_body(body: Value, explode: Option<&Explode>) -> (Vec<Value>, bool) {
+    let (bodies, is_array) = match body {
+        Value::Array(array) => (array, true),
+        body => (vec![body], false),
+    };
+
+    let bodies: Vec<Value> = match explode {
+        Some(explode) => bodies
+            .into_iter()
+            .flat_map(|body| explode.apply(body))
+            .collect(),
+        None => bodies,
+    };
+    let is_batch = is_array || bodies.len() > 1;
+
+    (bodies, is_batch)
+}
+
+// Every event of the batch is prepared and checked against the schema of the stream
+// before any of them is written, so that a single invalid event rejects the whole batch.
+// Rejected events are not dead-lettered in this mode.
+async fn ingest_atomic(
+    context: &IngestContext,
+    bodies: &[Value],
+) -> Result<HttpResponse, PostError> {
+    let mut response = PostResponse::default();
+    let mut schema = STREAM_INFO.schema(&context.stream_name)?;
+
+    let mut batch = Vec::with_capacity(bodies.len());
+    for (position, body) in bodies.iter().enumerate() {
+        let prepared = context.prepare(position, body).and_then(|prepared| {
+            if let Prepared::Ready { ref event, .. } = prepared {
+                if let Some(first_schema) = event.validate(schema.as_ref())? {
+                    schema = Some(first_schema);
+                }
+            }
+            Ok(prepared)
+        });
+
+        match prepared {
+            Ok(prepared) => batch.push((position, prepared)),
+            Err(error) => response.push_error(position, &error),
+        }
+    }
+
+    if !response.errors.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(response));
+    }
+
+    for (position, prepared) in batch {
+        match context.write(prepared).await {
+            Ok(ingested) => response.count(ingested),
+            Err(error) => response.push_error(position, &error),
+        }
+    }
+
+    commit(&context.stream_name, None, &response).await?;
+
+    Ok(response.to_http())
+}
+
+// The response is sent only once the written events are as durable as the
+// fsync policy and ack mode require, including those sent to the dead-letter stream
+async fn commit(
+    stream_name: &str,
+    dead_letter: Option<&DeadLetter>,
+    response: &PostResponse,
+) -> Result<(), PostError> {
+    if response.accepted > 0 {
+        wal::commit(stream_name).await?;
+    }
+    if let (Some(dead_letter), true) = (dead_letter, response.dead_lettered > 0) {
+        wal::commit(&dead_letter.stream).await?;
+    }
+
+    Ok(())
+}
+
+fn is_atomic(req: &HttpRequest) -> Result<bool, ParseHeaderError> {
+    let Some(value) = req.headers().get(ATOMIC_KEY) else {
+        return Ok(false);
+    };
+
+    value
+        .to_str()
+        .ok()
+        .and_then(|value| value.parse::<bool>().ok())
+        .ok_or(ParseHeaderError::InvalidValue)
+}
+
+// Store rejected events in the dead-letter stream if the source stream has one,
+// otherwise the rejection is returned to the client
+async fn reject(
+    dead_letter: Option<&DeadLetter>,
+    stream_name: &str,
+    bodies: &[Value],
+    error: PostError,
+) -> Result<(), PostError> {
+    let Some(dead_letter) = dead_letter else {
+        return Err(error);
+    };
+
+    for body in bodies {
+        if let Err(e) = dead_letter.store(stream_name, body, &error).await {
+            log::error!(
+                "failed to store rejected event of log stream {} in dead-letter stream {} due to err: {}",
+                stream_name,
+                dead_letter.stream,
+                e
+            );
+            return Err(error);
+        }
+    }
+
+    Ok(())
+}
+
+// Request level state shared by all the events in a request
+struct IngestContext {
+    stream_name: String,
+    // tag and metadata fields of the request
+    labels: Map<String, Value>,
+    // client context fields of the request
+    context: Map<String, Value>,
+    idempotency_key: Option<String>,
+    json_schema: Option<Arc<JsonSchema>>,
+    nesting: Nesting,
+    guardrails: Option<Guardrails>,
+    lookups: Vec<LookupJoin>,
+    sampling: Option<Sampling>,
+    dedup: Option<Arc<Dedup>>,
+}
+
+impl IngestContext {
+    fn new(req: &HttpRequest, stream_name: String) -> Result<Self, PostError> {
+        let max_labels = CONFIG.parseable.max_labels;
+        let format = STREAM_INFO.labels(&stream_name)?;
+
+        let mut labels =
+            format.fields(METADATA_KEY, collect_labels(req, PREFIX_META, max_labels)?)?;
+        labels.extend(format.fields(TAGS_KEY, collect_labels(req, PREFIX_TAGS, max_labels)?)?);
+
+        let context = STREAM_INFO.enrichment(&stream_name)?.fields(req);
+
+        let idempotency_key = req
+            .headers()
+            .get(IDEMPOTENCY_KEY)
+            .map(|value| {
+                value
+                    .to_str()
+                    .map(ToString::to_string)
+                    .map_err(|_| ParseHeaderError::InvalidValue)
+            })
+            .transpose()?;
+
+        let json_schema = STREAM_INFO.json_schema(&stream_name)?;
+        let nesting = STREAM_INFO.nesting(&stream_name)?;
+        let guardrails = STREAM_INFO.guardrails(&stream_name)?;
+        let lookups = STREAM_INFO.lookups(&stream_name)?;
+        let sampling = STREAM_INFO.sampling(&stream_name)?;
+        let dedup = STREAM_INFO.dedup(&stream_name)?;
+
+        Ok(Self {
             stream_name,
+            labels,
+            context,
+            idempotency_key,
+            json_schema,
+            nesting,
+            guardrails,
+            lookups,
+            sampling,
+            dedup,
+        })
+    }
+
+    async fn ingest(&self, position: usize, body: &Value) -> Result<Ingested, PostError> {
+        let prepared = self.prepare(position, body)?;
+        self.write(prepared).await
+    }
+
+    fn prepare(&self, position: usize, body: &Value) -> Result<Prepared, PostError> {
+        // the contract applies to the event as it was sent, before schema inference
+        if let Some(ref json_schema) = self.json_schema {
+            json_schema.validate(body)?;
+        }
+
+        let mut body = body.clone();
+
+        // fields over the limits of the stream are folded into a single column
+        let mut overflow = Map::new();
+        if let (Some(guardrails), Value::Object(map)) = (&self.guardrails, &mut body) {
+            guardrails.fold_deep(map, &mut overflow);
+        }
+
+        let mut body = self.nesting.apply(&body)?;
+
+        if let (Some(guardrails), Value::Object(map)) = (&self.guardrails, &mut body) {
+            let schema = STREAM_INFO.schema(&self.stream_name)?;
+            guardrails.fold_columns(map, schema.as_ref(), &mut overflow);
+            if !overflow.is_empty() {
+                let overflow = Value::Object(overflow).to_string();
+                map.insert(OVERFLOW_COLUMN.to_string(), Value::String(overflow));
+            }
+        }
+
+        // labels are added after shaping, so that these are never flattened or folded
+        let mut body = merge(body, &self.labels);
+        // client context always replaces the fields sent with the event
+        if let Value::Object(ref mut map) = body {
+            map.extend(self.context.clone());
+            // joined against the shaped event, so that the field is found by its flattened name
+            for lookup in &self.lookups {
+                lookup.apply(map);
+            }
+        }
+
+        let dedup_key = self
+            .dedup
+            .as_ref()
+            .and_then(|dedup| dedup.key(self.idempotency_key.as_deref(), position, &body));
+
+        if let (Some(dedup), Some(key)) = (&self.dedup, &dedup_key) {
+            if dedup.is_duplicate(key) {
+                return Ok(Prepared::Deduplicated);
+            }
+        }
+
+        if let Some(ref sampling) = self.sampling {
+            // drop the event here if it is not sampled,
+            // otherwise record the rate it was sampled at
+            let Some(rate) = sampling.sample(&body) else {
+                return Ok(Prepared::Dropped);
+            };
+            if let Value::Object(ref mut map) = body {
+                map.insert(SAMPLE_RATE_COLUMN.to_string(), Value::from(rate));
+            }
+        }
+
+        let event = event::Event {
+            body,
+            stream_name: self.stream_name.clone(),
         };
 
-        event.process().await?;
+        Ok(Prepared::Ready { event, dedup_key })
     }
 
-    Ok(HttpResponse::Ok().finish())
+    async fn write(&self, prepared: Prepared) -> Result<Ingested, PostError> {
+        let (event, dedup_key) = match prepared {
+            Prepared::Ready { event, dedup_key } => (event, dedup_key),
+            Prepared::Deduplicated => return Ok(Ingested::Deduplicated),
+            Prepared::Dropped => return Ok(Ingested::Dropped),
+        };
+
+        // an earlier event of the same batch or of a concurrent request can have
+        // the same key, only the one which reserves the key is written
+        let reservation = match (&self.dedup, &dedup_key) {
+            (Some(dedup), Some(key)) => match dedup.reserve(key) {
+                Some(reservation) => Some(reservation),
+                None => return Ok(Ingested::Deduplicated),
+            },
+            _ => None,
+        };
+
+        event.process().await?;
+
+        if let Some(reservation) = reservation {
+            reservation.commit();
+        }
+
+        Ok(Ingested::Accepted)
+    }
 }
 
 pub mod error {
-    use actix_web::http::header::ContentType;
+    use actix_web::http::header::{self, ContentType};
     use http::StatusCode;
 
+    use crate::option::CONFIG;
+
     use crate::{
         event::error::EventError,
+        metadata::error::stream_info::MetadataError,
         query::error::{ExecuteError, ParseError},
-        utils::header_parsing::ParseHeaderError,
+        utils::{header_parsing::ParseHeaderError, FlattenError},
     };
 
     #[derive(Debug, thiserror::Error)]
@@ -131,20 +511,222 @@ pub mod error {
         Header(#[from] ParseHeaderError),
         #[error("Event Error: {0}")]
         Event(#[from] EventError),
+        #[error("Metadata Error: {0}")]
+        Metadata(#[from] MetadataError),
+        #[error("Flatten Error: {0}")]
+        Flatten(#[from] FlattenError),
+        #[error("Local storage is almost full, retry once it is uploaded to object storage")]
+        DiskPressure,
+    }
+
+    impl PostError {
+        /// Errors caused by the event itself, which can not succeed on a retry
+        pub fn is_rejection(&self) -> bool {
+            matches!(
+                self,
+                PostError::Header(_)
+                    | PostError::Flatten(_)
+                    | PostError::Event(
+                        EventError::Coercion(_)
+                            | EventError::SchemaMismatch(_)
+                            | EventError::JsonSchema(_)
+                    )
+            )
+        }
     }
 
     impl actix_web::ResponseError for PostError {
         fn status_code(&self) -> http::StatusCode {
             match self {
                 PostError::Header(_) => StatusCode::BAD_REQUEST,
+                PostError::Event(
+                    EventError::Coercion(_)
+                    | EventError::SchemaMismatch(_)
+                    | EventError::JsonSchema(_),
+                ) => StatusCode::BAD_REQUEST,
                 PostError::Event(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                PostError::Metadata(_) => StatusCode::BAD_REQUEST,
+                PostError::Flatten(_) => StatusCode::BAD_REQUEST,
+                PostError::DiskPressure => StatusCode::SERVICE_UNAVAILABLE,
             }
         }
 
         fn error_response(&self) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
-            actix_web::HttpResponse::build(self.status_code())
-                .insert_header(ContentType::plaintext())
-                .body(self.to_string())
+            let mut response = actix_web::HttpResponse::build(self.status_code());
+            response.insert_header(ContentType::plaintext());
+            if let PostError::DiskPressure = self {
+                // local storage drains with the next upload
+                let retry_after = CONFIG.parseable.upload_interval.to_string();
+                response.insert_header((header::RETRY_AFTER, retry_after));
+            }
+            response.body(self.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::body::to_bytes;
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+    use actix_web::HttpResponse;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use rstest::*;
+    use serde_json::{json, Map, Value};
+
+    use super::error::PostError;
+    use super::{
+        ingest_atomic, is_atomic, split_body, IngestContext, Ingested, PostResponse, ATOMIC_KEY,
+    };
+    use crate::event::explode::Explode;
+    use crate::event::nesting::Nesting;
+    use crate::event::STREAM_WRITERS;
+    use crate::metadata::STREAM_INFO;
+    use crate::storage::LEGACY_DATA_GRANULARITY;
+    use crate::utils::header_parsing::ParseHeaderError;
+
+    fn context(stream_name: &str) -> IngestContext {
+        IngestContext {
+            stream_name: stream_name.to_owned(),
+            labels: Map::new(),
+            context: Map::new(),
+            idempotency_key: None,
+            json_schema: None,
+            nesting: Nesting::default(),
+            guardrails: None,
+            lookups: Vec::new(),
+            sampling: None,
+            dedup: None,
+        }
+    }
+
+    async fn body(response: HttpResponse) -> Value {
+        let bytes = to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn multi_status_lists_failed_items() {
+        let mut response = PostResponse::default();
+        response.count(Ingested::Accepted);
+        response.push_error(1, &PostError::Header(ParseHeaderError::InvalidValue));
+        response.count(Ingested::Deduplicated);
+        response.push_error(3, &PostError::DiskPressure);
+        response.count(Ingested::Dropped);
+
+        let response = response.to_http();
+        assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+
+        let body = body(response).await;
+        assert_eq!(body["accepted"], 1);
+        assert_eq!(body["deduplicated"], 1);
+        let errors: Vec<(u64, bool)> = body["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|error| {
+                (
+                    error["index"].as_u64().unwrap(),
+                    error["retryable"].as_bool().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(errors, [(1, false), (3, true)]);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn ok_without_failed_items() {
+        let mut response = PostResponse::default();
+        response.count(Ingested::Accepted);
+        response.count(Ingested::Accepted);
+
+        let response = response.to_http();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            body(response).await,
+            json!({"accepted": 2, "deduplicated": 0, "dead_lettered": 0})
+        );
+    }
+
+    // A single invalid event rejects the whole batch, none of the events is written
+    #[rstest]
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn atomic_batch_is_all_or_nothing() {
+        let stream_name = "atomic_batch_is_all_or_nothing";
+        let schema = Schema::new(vec![Field::new("a", DataType::Int64, true)]);
+        STREAM_INFO.add_static_stream(stream_name.to_owned(), schema, LEGACY_DATA_GRANULARITY);
+
+        let bodies = [json!({"a": 1}), json!({"b": 2}), json!({"a": 3})];
+        let response = ingest_atomic(&context(stream_name), &bodies).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = body(response).await;
+        assert_eq!(body["accepted"], 0);
+        let errors = body["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0]["index"], 1);
+        assert_eq!(errors[0]["retryable"], false);
+        assert!(!STREAM_WRITERS.read().unwrap().contains_key(stream_name));
+
+        STREAM_INFO.delete_stream(stream_name);
+    }
+
+    #[rstest]
+    #[case(json!({"a": 1}), false)]
+    #[case(json!([{"a": 1}]), true)]
+    #[case(json!({"records": [{"a": 1}]}), false)]
+    #[case(json!({"records": [{"a": 1}, {"a": 2}]}), true)]
+    fn exploded_envelope_is_batch(#[case] body: Value, #[case] is_batch: bool) {
+        let explode = Explode {
+            path: "records".to_owned(),
+        };
+        assert_eq!(split_body(body, Some(&explode)).1, is_batch);
+    }
+
+    // The records of an envelope are a batch, a failed record is reported on its own
+    // and with the atomic mode none of the records is written
+    #[rstest]
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn exploded_envelope_with_failed_record() {
+        let stream_name = "exploded_envelope_with_failed_record";
+        let schema = Schema::new(vec![Field::new("a", DataType::Int64, true)]);
+        STREAM_INFO.add_static_stream(stream_name.to_owned(), schema, LEGACY_DATA_GRANULARITY);
+
+        let explode = Explode {
+            path: "records".to_owned(),
+        };
+        let envelope = json!({"records": [{"a": 1}, {"b": 2}, {"a": 3}]});
+        let (bodies, is_batch) = split_body(envelope, Some(&explode));
+        assert!(is_batch);
+        assert_eq!(bodies.len(), 3);
+
+        let response = ingest_atomic(&context(stream_name), &bodies).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = body(response).await;
+        assert_eq!(body["accepted"], 0);
+        let errors = body["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0]["index"], 1);
+        assert!(!STREAM_WRITERS.read().unwrap().contains_key(stream_name));
+
+        STREAM_INFO.delete_stream(stream_name);
+    }
+
+    #[rstest]
+    #[case(None, Some(false))]
+    #[case(Some("true"), Some(true))]
+    #[case(Some("false"), Some(false))]
+    #[case(Some("yes"), None)]
+    fn atomic_header(#[case] value: Option<&str>, #[case] expected: Option<bool>) {
+        let mut req = TestRequest::default();
+        if let Some(value) = value {
+            req = req.insert_header((ATOMIC_KEY, value));
         }
+        assert_eq!(is_atomic(&req.to_http_request()).ok(), expected);
     }
 }
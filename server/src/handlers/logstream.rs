@@ -21,9 +21,12 @@ use std::fs;
 use actix_web::http::StatusCode;
 use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use chrono::Utc;
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
 use serde_json::Value;
 
 use crate::alerts::Alerts;
+use crate::handlers::event::{METADATA_KEY, TAGS_KEY};
+use crate::option::CONFIG;
 use crate::s3::S3;
 use crate::storage::{ObjectStorage, StorageDir};
 use crate::{event, response};
@@ -164,7 +167,7 @@ pub async fn get_alert(req: HttpRequest) -> HttpResponse {
     .to_http()
 }
 
-pub async fn put(req: HttpRequest) -> HttpResponse {
+pub async fn put(req: HttpRequest, body: web::Bytes) -> HttpResponse {
     let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
 
     // fail to proceed if there is an error in log stream name validation
@@ -176,12 +179,37 @@ pub async fn put(req: HttpRequest) -> HttpResponse {
         .to_http();
     }
 
+    // An optional schema can be declared while creating the log stream
+    let schema = if body.is_empty() {
+        None
+    } else {
+        match serde_json::from_slice::<Schema>(&body)
+            .map_err(|e| e.to_string())
+            .and_then(|schema| {
+                validator::schema(&schema)
+                    .map(|_| with_default_fields(schema))
+                    .map_err(|e| e.to_string())
+            }) {
+            Ok(schema) => Some(schema),
+            Err(e) => {
+                return response::ServerResponse {
+                    msg: format!(
+                        "failed to create log stream {} due to invalid schema: {}",
+                        stream_name, e
+                    ),
+                    code: StatusCode::BAD_REQUEST,
+                }
+                .to_http()
+            }
+        }
+    };
+
     let s3 = S3::new();
 
     // Proceed to create log stream if it doesn't exist
     if s3.get_schema(&stream_name).await.is_err() {
         // Fail if unable to create log stream on object store backend
-        if let Err(e) = s3.create_stream(&stream_name).await {
+        if let Err(e) = s3.create_stream(&stream_name, schema.as_ref()).await {
             return response::ServerResponse {
                 msg: format!(
                     "failed to create log stream {} due to err: {}",
@@ -191,7 +219,20 @@ pub async fn put(req: HttpRequest) -> HttpResponse {
             }
             .to_http();
         }
-        metadata::STREAM_INFO.add_stream(stream_name.to_string(), None, Alerts::default());
+        let data_granularity = CONFIG.parseable.data_granularity;
+        match schema {
+            Some(schema) => metadata::STREAM_INFO.add_static_stream(
+                stream_name.clone(),
+                schema,
+                data_granularity,
+            ),
+            None => metadata::STREAM_INFO.add_stream(
+                stream_name.clone(),
+                None,
+                Alerts::default(),
+                data_granularity,
+            ),
+        }
         return response::ServerResponse {
             msg: format!("created log stream {}", stream_name),
             code: StatusCode::OK,
@@ -199,6 +240,19 @@ pub async fn put(req: HttpRequest) -> HttpResponse {
         .to_http();
     }
 
+    // The schema of an existing stream is not replaced, events
+    // stored with the old schema would not match it
+    if schema.is_some() {
+        return response::ServerResponse {
+            msg: format!(
+                "log stream {} already exists, a schema can only be declared while creating a log stream",
+                stream_name
+            ),
+            code: StatusCode::CONFLICT,
+        }
+        .to_http();
+    }
+
     // Error if the log stream already exists
     response::ServerResponse {
         msg: format!(
@@ -210,6 +264,18 @@ pub async fn put(req: HttpRequest) -> HttpResponse {
     .to_http()
 }
 
+// Tags and metadata are added to every event by the server,
+// so these are a part of every declared schema as well.
+fn with_default_fields(schema: Schema) -> Schema {
+    let mut fields = schema.fields().clone();
+    for name in [TAGS_KEY, METADATA_KEY] {
+        if schema.column_with_name(name).is_none() {
+            fields.push(Field::new(name, DataType::Utf8, true));
+        }
+    }
+    Schema::new(fields)
+}
+
 pub async fn put_alert(req: HttpRequest, body: web::Json<serde_json::Value>) -> HttpResponse {
     let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
 
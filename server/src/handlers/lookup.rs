@@ -0,0 +1,72 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+
+use crate::lookup::{LookupTable, LOOKUP_TABLES};
+use crate::s3::S3;
+use crate::storage::ObjectStorage;
+use crate::{response, validator};
+
+pub async fn put(req: HttpRequest, body: web::Bytes) -> HttpResponse {
+    let name: String = req.match_info().get("lookup").unwrap().parse().unwrap();
+
+    if let Err(e) = validator::lookup_table_name(&name) {
+        return response::ServerResponse {
+            msg: format!("failed to upload lookup table {} due to err: {}", name, e),
+            code: StatusCode::BAD_REQUEST,
+        }
+        .to_http();
+    }
+
+    // parse before storing, so that a broken file never replaces a working table
+    let mut table = match LookupTable::from_csv(&body, String::new()) {
+        Ok(table) => table,
+        Err(e) => {
+            return response::ServerResponse {
+                msg: format!("failed to upload lookup table {} due to err: {}", name, e),
+                code: StatusCode::BAD_REQUEST,
+            }
+            .to_http()
+        }
+    };
+
+    table.version = match S3::new().put_lookup_table(&name, body.to_vec()).await {
+        Ok(version) => version,
+        Err(e) => {
+            return response::ServerResponse {
+                msg: format!("failed to upload lookup table {} due to err: {}", name, e),
+                code: StatusCode::INTERNAL_SERVER_ERROR,
+            }
+            .to_http()
+        }
+    };
+
+    LOOKUP_TABLES.insert(name.clone(), table);
+
+    response::ServerResponse {
+        msg: format!("uploaded lookup table {}", name),
+        code: StatusCode::OK,
+    }
+    .to_http()
+}
+
+pub async fn list(_: HttpRequest) -> HttpResponse {
+    HttpResponse::Ok().json(LOOKUP_TABLES.list())
+}
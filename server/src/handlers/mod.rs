@@ -16,14 +16,18 @@
  *
  */
 
+pub mod config;
 pub mod event;
 pub mod logstream;
+pub mod lookup;
 
 use actix_web::http::StatusCode;
 use actix_web::HttpResponse;
 use sysinfo::{System, SystemExt};
 
+use crate::alerts;
 use crate::s3::S3;
+use crate::staging;
 use crate::storage::ObjectStorage;
 
 pub async fn liveness() -> HttpResponse {
@@ -37,9 +41,18 @@ pub async fn liveness() -> HttpResponse {
 }
 
 pub async fn readiness() -> HttpResponse {
+    // events are refused while the staging disk is too full
+    if staging::under_pressure() {
+        return HttpResponse::new(StatusCode::SERVICE_UNAVAILABLE);
+    }
+
     if let Ok(()) = S3::new().check().await {
         return HttpResponse::new(StatusCode::OK);
     }
 
     HttpResponse::new(StatusCode::SERVICE_UNAVAILABLE)
 }
+
+pub async fn alert_metrics() -> HttpResponse {
+    HttpResponse::Ok().json(alerts::queue::metrics())
+}
@@ -0,0 +1,212 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+use crate::metadata::LOCK_EXPECT;
+use crate::storage::ObjectStorage;
+
+use self::error::LookupError;
+
+/// A CSV table uploaded by the user, the first row of the file names the columns
+#[derive(Debug)]
+pub struct LookupTable {
+    /// Version of the object in object storage this table was read from
+    pub version: String,
+    columns: Vec<String>,
+    rows: Vec<Vec<String>>,
+    // row position of every value, per column. The first row wins for duplicate values.
+    index: Vec<HashMap<String, usize>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LookupTableInfo {
+    pub name: String,
+    pub version: String,
+    pub columns: Vec<String>,
+    pub rows: usize,
+}
+
+impl LookupTable {
+    pub fn from_csv(body: &[u8], version: String) -> Result<Self, LookupError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .from_reader(body);
+
+        let columns: Vec<String> = reader.headers()?.iter().map(str::to_owned).collect();
+        if columns.is_empty() || columns.iter().any(String::is_empty) {
+            return Err(LookupError::EmptyColumnName);
+        }
+
+        let mut names = HashSet::new();
+        if let Some(column) = columns.iter().find(|column| !names.insert(*column)) {
+            return Err(LookupError::DuplicateColumn(column.to_owned()));
+        }
+
+        let mut rows = Vec::new();
+        for record in reader.records() {
+            rows.push(record?.iter().map(str::to_owned).collect::<Vec<_>>());
+        }
+
+        let index = (0..columns.len())
+            .map(|column| {
+                let mut index = HashMap::new();
+                for (position, row) in rows.iter().enumerate() {
+                    index.entry(row[column].clone()).or_insert(position);
+                }
+                index
+            })
+            .collect();
+
+        Ok(Self {
+            version,
+            columns,
+            rows,
+            index,
+        })
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn has_column(&self, column: &str) -> bool {
+        self.position(column).is_some()
+    }
+
+    /// Value of `column` in the first row where `key` column is equal to `value`
+    pub fn get(&self, key: &str, value: &str, column: &str) -> Option<&str> {
+        let row = *self.index[self.position(key)?].get(value)?;
+        Some(&self.rows[row][self.position(column)?])
+    }
+
+    fn position(&self, column: &str) -> Option<usize> {
+        self.columns.iter().position(|name| name == column)
+    }
+}
+
+lazy_static! {
+    #[derive(Debug)]
+    pub static ref LOOKUP_TABLES: RwLock<HashMap<String, Arc<LookupTable>>> =
+        RwLock::new(HashMap::new());
+}
+
+#[allow(clippy::all)]
+impl LOOKUP_TABLES {
+    pub fn get(&self, name: &str) -> Option<Arc<LookupTable>> {
+        self.read().expect(LOCK_EXPECT).get(name).cloned()
+    }
+
+    pub fn insert(&self, name: String, table: LookupTable) {
+        self.write()
+            .expect(LOCK_EXPECT)
+            .insert(name, Arc::new(table));
+    }
+
+    pub fn list(&self) -> Vec<LookupTableInfo> {
+        let map = self.read().expect(LOCK_EXPECT);
+        let mut tables: Vec<_> = map
+            .iter()
+            .map(|(name, table)| LookupTableInfo {
+                name: name.to_owned(),
+                version: table.version.to_owned(),
+                columns: table.columns.to_owned(),
+                rows: table.rows.len(),
+            })
+            .collect();
+        tables.sort_by(|a, b| a.name.cmp(&b.name));
+        tables
+    }
+
+    /// Sync the tables with object storage. Only the tables which were replaced since
+    /// the last load are read again, so this is cheap enough to run periodically.
+    pub async fn load(&self, storage: &impl ObjectStorage) -> Result<(), LookupError> {
+        let objects = storage.list_lookup_tables().await?;
+
+        for object in &objects {
+            let current = self.get(&object.name);
+            if matches!(current, Some(table) if table.version == object.version) {
+                continue;
+            }
+
+            let body = storage.get_lookup_table(&object.name).await?;
+            match LookupTable::from_csv(&body, object.version.to_owned()) {
+                Ok(table) => self.insert(object.name.to_owned(), table),
+                // keep serving the last good version of the table
+                Err(e) => log::warn!("failed to load lookup table {}. {}", object.name, e),
+            }
+        }
+
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.retain(|name, _| objects.iter().any(|object| &object.name == name));
+
+        Ok(())
+    }
+}
+
+pub mod error {
+    use crate::storage::ObjectStorageError;
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum LookupError {
+        #[error("Invalid CSV: {0}")]
+        Csv(#[from] csv::Error),
+        #[error("Lookup table column names cannot be empty")]
+        EmptyColumnName,
+        #[error("Lookup table column {0} is declared more than once")]
+        DuplicateColumn(String),
+        #[error("Storage Error: {0}")]
+        Storage(#[from] ObjectStorageError),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use super::LookupTable;
+
+    const SERVICES: &str = "service,team,env\napi,core,prod\nweb,frontend,prod\napi,other,dev\n";
+
+    #[rstest]
+    #[case::first_row_wins("service", "api", "team", Some("core"))]
+    #[case::other_key_column("team", "frontend", "service", Some("web"))]
+    #[case::no_match("service", "db", "team", None)]
+    #[case::unknown_key("owner", "api", "team", None)]
+    #[case::unknown_column("service", "api", "owner", None)]
+    fn get(
+        #[case] key: &str,
+        #[case] value: &str,
+        #[case] column: &str,
+        #[case] expected: Option<&str>,
+    ) {
+        let table = LookupTable::from_csv(SERVICES.as_bytes(), String::new()).unwrap();
+        assert_eq!(table.get(key, value, column), expected);
+    }
+
+    #[rstest]
+    #[case::duplicate_column("service,service\napi,api\n")]
+    #[case::empty_column("service,\napi,core\n")]
+    #[case::uneven_rows("service,team\napi\n")]
+    fn rejects_invalid_csv(#[case] body: &str) {
+        assert!(LookupTable::from_csv(body.as_bytes(), String::new()).is_err());
+    }
+}
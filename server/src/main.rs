@@ -34,6 +34,7 @@ include!(concat!(env!("OUT_DIR"), "/generated.rs"));
 use std::fs::File;
 use std::io::BufReader;
 use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread::{self, JoinHandle};
 use std::time::Duration;
 use tokio::sync::oneshot;
@@ -41,24 +42,44 @@ use tokio::sync::oneshot::error::TryRecvError;
 
 mod alerts;
 mod banner;
+mod compaction;
 mod event;
 mod handlers;
+mod lookup;
+mod manifest;
 mod metadata;
 mod option;
+mod parquet;
 mod query;
 mod response;
+mod retention;
 mod s3;
+mod staging;
 mod stats;
 mod storage;
 mod utils;
 mod validator;
 
-use option::CONFIG;
+use event::coercion::Coercion;
+use event::dead_letter::DeadLetter;
+use event::dedup::Dedup;
+use event::enrichment::Enrichment;
+use event::explode::Explode;
+use event::guardrails::Guardrails;
+use event::json_schema::JsonSchema;
+use event::labels::Labels;
+use event::lookup::LookupJoin;
+use event::nesting::Nesting;
+use event::sampling::Sampling;
+use option::{FsyncPolicy, CONFIG};
+use parquet::ParquetConfig;
+use retention::Retention;
 use s3::S3;
 use storage::ObjectStorage;
 
 // Global configurations
 const MAX_EVENT_PAYLOAD_SIZE: usize = 1024000;
+const MAX_LOOKUP_TABLE_SIZE: usize = 10 * 1024 * 1024;
 const API_BASE_PATH: &str = "/api";
 const API_VERSION: &str = "v1";
 
@@ -72,6 +93,15 @@ async fn main() -> anyhow::Result<()> {
     if let Err(e) = metadata::STREAM_INFO.load(&storage).await {
         warn!("could not populate local metadata. {:?}", e);
     }
+    if let Err(e) = lookup::LOOKUP_TABLES.load(&storage).await {
+        warn!("could not load lookup tables. {:?}", e);
+    }
+    staging::check_monitored();
+    staging::check_disk_usage();
+    // must run before any event is written, a file left by a crash is never appended to
+    storage::recover_local_data();
+
+    alerts::queue::start_workers();
 
     let (localsync_handler, mut localsync_outbox, localsync_inbox) = run_local_sync();
     let (mut s3sync_handler, mut s3sync_outbox, mut s3sync_inbox) = s3_sync();
@@ -118,6 +148,19 @@ fn s3_sync() -> (JoinHandle<()>, oneshot::Receiver<()>, oneshot::Sender<()>) {
                             warn!("failed to sync local data with object store. {:?}", e);
                         }
                     });
+                // pick up lookup tables replaced through other servers or directly in the bucket
+                scheduler
+                    .every((CONFIG.parseable.upload_interval as u32).seconds())
+                    .run(|| async {
+                        if let Err(e) = lookup::LOOKUP_TABLES.load(&S3::new()).await {
+                            warn!("failed to reload lookup tables. {:?}", e);
+                        }
+                    });
+                // retention and compaction can take longer than the sync interval,
+                // these run next to the jobs above instead of holding them up
+                scheduler.every(1.hours()).run(|| async {
+                    actix_web::rt::spawn(storage_maintenance());
+                });
 
                 loop {
                     tokio::time::sleep(Duration::from_secs(1)).await;
@@ -142,6 +185,31 @@ fn s3_sync() -> (JoinHandle<()>, oneshot::Receiver<()>, oneshot::Sender<()>) {
     (handle, outbox_rx, inbox_tx)
 }
 
+// Retention and compaction both rewrite past days of the streams, so one runs after
+// the other and a run which outlasts the schedule is not overlapped
+async fn storage_maintenance() {
+    static RUNNING: AtomicBool = AtomicBool::new(false);
+
+    // cleared on drop, a run which panics does not stop later ones
+    struct Running;
+
+    impl Drop for Running {
+        fn drop(&mut self) {
+            RUNNING.store(false, Ordering::Release);
+        }
+    }
+
+    if RUNNING.swap(true, Ordering::AcqRel) {
+        log::warn!("previous retention and compaction run is still going, skipping this one");
+        return;
+    }
+    let _running = Running;
+
+    let storage = S3::new();
+    retention::run(&storage).await;
+    compaction::run(&storage).await;
+}
+
 fn run_local_sync() -> (JoinHandle<()>, oneshot::Receiver<()>, oneshot::Sender<()>) {
     let (outbox_tx, outbox_rx) = oneshot::channel::<()>();
     let (inbox_tx, inbox_rx) = oneshot::channel::<()>();
@@ -157,12 +225,31 @@ fn run_local_sync() -> (JoinHandle<()>, oneshot::Receiver<()>, oneshot::Sender<(
             let res = catch_unwind(move || {
                 let mut scheduler = Scheduler::new();
                 scheduler
-                    .every((storage::LOCAL_SYNC_INTERVAL as u32).seconds())
+                    .every((CONFIG.parseable.local_sync_interval as u32).seconds())
                     .run(move || {
                         if let Err(e) = crate::event::STREAM_WRITERS::unset_all() {
                             warn!("failed to sync local data. {:?}", e);
                         }
                     });
+                scheduler
+                    .every((CONFIG.parseable.buffer_flush_interval as u32).seconds())
+                    .run(move || {
+                        if let Err(e) = crate::event::STREAM_WRITERS::flush_all() {
+                            warn!("failed to write buffered events to local data. {:?}", e);
+                        }
+                    });
+                scheduler
+                    .every((staging::DISK_CHECK_INTERVAL as u32).seconds())
+                    .run(staging::check_disk_usage);
+                if CONFIG.parseable.wal_fsync == FsyncPolicy::Interval {
+                    scheduler
+                        .every((CONFIG.parseable.wal_fsync_interval as u32).seconds())
+                        .run(move || {
+                            if let Err(e) = crate::event::wal::sync_interval() {
+                                warn!("failed to flush local data to disk. {:?}", e);
+                            }
+                        });
+                }
 
                 loop {
                     thread::sleep(Duration::from_millis(50));
@@ -275,10 +362,111 @@ pub fn configure_routes(cfg: &mut web::ServiceConfig) {
                     // GET "/logstream/{logstream}/alert" ==> Get alert for given log stream
                     .route(web::get().to(handlers::logstream::get_alert)),
             )
+            .service(
+                web::resource(sampling_path("{logstream}"))
+                    // PUT "/logstream/{logstream}/sampling" ==> Set sampling rules for given log stream
+                    .route(web::put().to(handlers::config::put_config::<Sampling>))
+                    // GET "/logstream/{logstream}/sampling" ==> Get sampling rules for given log stream
+                    .route(web::get().to(handlers::config::get_config::<Sampling>)),
+            )
+            .service(
+                web::resource(dedup_path("{logstream}"))
+                    // PUT "/logstream/{logstream}/dedup" ==> Set dedup config for given log stream
+                    .route(web::put().to(handlers::config::put_config::<Dedup>))
+                    // GET "/logstream/{logstream}/dedup" ==> Get dedup config for given log stream
+                    .route(web::get().to(handlers::config::get_config::<Dedup>)),
+            )
+            .service(
+                web::resource(coercion_path("{logstream}"))
+                    // PUT "/logstream/{logstream}/coercion" ==> Set type coercion for given log stream
+                    .route(web::put().to(handlers::config::put_config::<Coercion>))
+                    // GET "/logstream/{logstream}/coercion" ==> Get type coercion for given log stream
+                    .route(web::get().to(handlers::config::get_config::<Coercion>)),
+            )
+            .service(
+                web::resource(json_schema_path("{logstream}"))
+                    // PUT "/logstream/{logstream}/jsonschema" ==> Set json schema of events for given log stream
+                    .route(web::put().to(handlers::config::put_config::<JsonSchema>))
+                    // GET "/logstream/{logstream}/jsonschema" ==> Get json schema of events for given log stream
+                    .route(web::get().to(handlers::config::get_config::<JsonSchema>)),
+            )
+            .service(
+                web::resource(nesting_path("{logstream}"))
+                    // PUT "/logstream/{logstream}/nesting" ==> Set nesting mode for given log stream
+                    .route(web::put().to(handlers::config::put_config::<Nesting>))
+                    // GET "/logstream/{logstream}/nesting" ==> Get nesting mode for given log stream
+                    .route(web::get().to(handlers::config::get_config::<Nesting>)),
+            )
+            .service(
+                web::resource(explode_path("{logstream}"))
+                    // PUT "/logstream/{logstream}/explode" ==> Set array path to explode for given log stream
+                    .route(web::put().to(handlers::config::put_config::<Explode>))
+                    // GET "/logstream/{logstream}/explode" ==> Get array path to explode for given log stream
+                    .route(web::get().to(handlers::config::get_config::<Explode>)),
+            )
+            .service(
+                web::resource(guardrails_path("{logstream}"))
+                    // PUT "/logstream/{logstream}/guardrails" ==> Set schema guardrails for given log stream
+                    .route(web::put().to(handlers::config::put_config::<Guardrails>))
+                    // GET "/logstream/{logstream}/guardrails" ==> Get schema guardrails for given log stream
+                    .route(web::get().to(handlers::config::get_config::<Guardrails>)),
+            )
+            .service(
+                web::resource(labels_path("{logstream}"))
+                    // PUT "/logstream/{logstream}/labels" ==> Set format of tags and metadata for given log stream
+                    .route(web::put().to(handlers::config::put_config::<Labels>))
+                    // GET "/logstream/{logstream}/labels" ==> Get format of tags and metadata for given log stream
+                    .route(web::get().to(handlers::config::get_config::<Labels>)),
+            )
+            .service(
+                web::resource(enrichment_path("{logstream}"))
+                    // PUT "/logstream/{logstream}/enrichment" ==> Set client context columns for given log stream
+                    .route(web::put().to(handlers::config::put_config::<Enrichment>))
+                    // GET "/logstream/{logstream}/enrichment" ==> Get client context columns for given log stream
+                    .route(web::get().to(handlers::config::get_config::<Enrichment>)),
+            )
+            .service(
+                web::resource(lookups_path("{logstream}"))
+                    // PUT "/logstream/{logstream}/lookup" ==> Set lookup table joins for given log stream
+                    .route(web::put().to(handlers::config::put_config::<Vec<LookupJoin>>))
+                    // GET "/logstream/{logstream}/lookup" ==> Get lookup table joins for given log stream
+                    .route(web::get().to(handlers::config::get_config::<Vec<LookupJoin>>)),
+            )
+            .service(
+                web::resource(parquet_path("{logstream}"))
+                    // PUT "/logstream/{logstream}/parquet" ==> Set parquet writer settings for given log stream
+                    .route(web::put().to(handlers::config::put_config::<ParquetConfig>))
+                    // GET "/logstream/{logstream}/parquet" ==> Get parquet writer settings for given log stream
+                    .route(web::get().to(handlers::config::get_config::<ParquetConfig>)),
+            )
+            .service(
+                web::resource(retention_path("{logstream}"))
+                    // PUT "/logstream/{logstream}/retention" ==> Set data retention for given log stream
+                    .route(web::put().to(handlers::config::put_config::<Retention>))
+                    // GET "/logstream/{logstream}/retention" ==> Get data retention for given log stream
+                    .route(web::get().to(handlers::config::get_config::<Retention>)),
+            )
+            .service(
+                web::resource(dead_letter_path("{logstream}"))
+                    // PUT "/logstream/{logstream}/deadletter" ==> Set dead-letter stream for given log stream
+                    .route(web::put().to(handlers::config::put_dead_letter))
+                    // GET "/logstream/{logstream}/deadletter" ==> Get dead-letter stream for given log stream
+                    .route(web::get().to(handlers::config::get_config::<DeadLetter>)),
+            )
             // GET "/logstream" ==> Get list of all Log Streams on the server
             .service(
                 web::resource(logstream_path("")).route(web::get().to(handlers::logstream::list)),
             )
+            .service(
+                // PUT "/lookup/{lookup}" ==> Upload or replace a CSV lookup table
+                web::resource(lookup_table_path("{lookup}"))
+                    .route(web::put().to(handlers::lookup::put))
+                    .app_data(web::PayloadConfig::default().limit(MAX_LOOKUP_TABLE_SIZE)),
+            )
+            // GET "/lookup" ==> Get list of all lookup tables on the server
+            .service(
+                web::resource(lookup_table_path("")).route(web::get().to(handlers::lookup::list)),
+            )
             .service(
                 // GET "/logstream/{logstream}/schema" ==> Get schema for given log stream
                 web::resource(schema_path("{logstream}"))
@@ -293,6 +481,10 @@ pub fn configure_routes(cfg: &mut web::ServiceConfig) {
             .service(web::resource(liveness_path()).route(web::get().to(handlers::liveness)))
             // GET "/readiness" ==> Readiness check as per https://kubernetes.io/docs/tasks/configure-pod-container/configure-liveness-readiness-startup-probes/#define-readiness-probes
             .service(web::resource(readiness_path()).route(web::get().to(handlers::readiness)))
+            // GET "/metrics/alerts" ==> Depth of the alert evaluation queue and evaluations dropped while it was full
+            .service(
+                web::resource(alert_metrics_path()).route(web::get().to(handlers::alert_metrics)),
+            )
             .wrap(HttpAuthentication::basic(validator)),
     )
     // GET "/" ==> Serve the static frontend directory
@@ -327,6 +519,14 @@ fn logstream_path(stream_name: &str) -> String {
     }
 }
 
+fn lookup_table_path(name: &str) -> String {
+    if name.is_empty() {
+        "/lookup".to_string()
+    } else {
+        format!("/lookup/{}", name)
+    }
+}
+
 fn readiness_path() -> String {
     "/readiness".to_string()
 }
@@ -335,6 +535,10 @@ fn liveness_path() -> String {
     "/liveness".to_string()
 }
 
+fn alert_metrics_path() -> String {
+    "/metrics/alerts".to_string()
+}
+
 fn query_path() -> String {
     "/query".to_string()
 }
@@ -343,6 +547,58 @@ fn alert_path(stream_name: &str) -> String {
     format!("{}/alert", logstream_path(stream_name))
 }
 
+fn sampling_path(stream_name: &str) -> String {
+    format!("{}/sampling", logstream_path(stream_name))
+}
+
+fn dedup_path(stream_name: &str) -> String {
+    format!("{}/dedup", logstream_path(stream_name))
+}
+
+fn coercion_path(stream_name: &str) -> String {
+    format!("{}/coercion", logstream_path(stream_name))
+}
+
+fn json_schema_path(stream_name: &str) -> String {
+    format!("{}/jsonschema", logstream_path(stream_name))
+}
+
+fn nesting_path(stream_name: &str) -> String {
+    format!("{}/nesting", logstream_path(stream_name))
+}
+
+fn explode_path(stream_name: &str) -> String {
+    format!("{}/explode", logstream_path(stream_name))
+}
+
+fn guardrails_path(stream_name: &str) -> String {
+    format!("{}/guardrails", logstream_path(stream_name))
+}
+
+fn labels_path(stream_name: &str) -> String {
+    format!("{}/labels", logstream_path(stream_name))
+}
+
+fn enrichment_path(stream_name: &str) -> String {
+    format!("{}/enrichment", logstream_path(stream_name))
+}
+
+fn lookups_path(stream_name: &str) -> String {
+    format!("{}/lookup", logstream_path(stream_name))
+}
+
+fn parquet_path(stream_name: &str) -> String {
+    format!("{}/parquet", logstream_path(stream_name))
+}
+
+fn retention_path(stream_name: &str) -> String {
+    format!("{}/retention", logstream_path(stream_name))
+}
+
+fn dead_letter_path(stream_name: &str) -> String {
+    format!("{}/deadletter", logstream_path(stream_name))
+}
+
 fn schema_path(stream_name: &str) -> String {
     format!("{}/schema", logstream_path(stream_name))
 }
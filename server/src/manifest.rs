@@ -0,0 +1,759 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use datafusion::arrow::datatypes::{DataType, Schema, SchemaRef};
+use datafusion::datasource::empty::EmptyTable;
+use datafusion::logical_expr::expr_visitor::{ExprVisitable, ExpressionVisitor, Recursion};
+use datafusion::logical_expr::{BinaryExpr, Expr, LogicalPlan, Operator};
+use datafusion::parquet::errors::ParquetError;
+use datafusion::parquet::file::reader::{FileReader, SerializedFileReader};
+use datafusion::parquet::file::statistics::Statistics;
+use datafusion::prelude::SessionContext;
+use datafusion::scalar::ScalarValue;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Mutex;
+
+use crate::compaction;
+use crate::storage::{DataObject, ObjectStorage, ObjectStorageError};
+use crate::utils;
+
+// manifests of the hours of a day read at a time
+const MANIFEST_READS: usize = 16;
+
+lazy_static::lazy_static! {
+    // uploads and compaction both change manifests, a change
+    // is a read and a write which must not interleave
+    static ref MANIFEST_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Data files of an hour of a stream, kept in `date=../hour=../.manifest.json` of the
+/// stream, so that an upload only rewrites the manifest of its hour. Objects of a whole
+/// day are kept in `date=../.manifest.json`, along with the files of the days which had
+/// a single manifest before. Queries over an hour with a manifest read only the files in it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(default)]
+    pub files: Vec<DataFile>,
+}
+
+/// Files of a day of a stream, from the manifest of the day and of its hours
+#[derive(Debug, Default)]
+pub struct DayFiles {
+    pub files: Vec<DataFile>,
+    /// Hours which have no manifest, their objects are not listed in one
+    pub unlisted: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DataFile {
+    pub key: String,
+    /// Unknown for files uploaded before the day had a manifest
+    pub rows: Option<u64>,
+    pub size: u64,
+    pub time_range: Option<TimeRange>,
+    /// Statistics of the columns the file has them for
+    #[serde(default)]
+    pub columns: BTreeMap<String, ColumnStats>,
+}
+
+/// Time slot of a file, from `start` up to `end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeRange {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ColumnStats {
+    /// None when every value of the column is null
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+    pub null_count: u64,
+}
+
+impl Manifest {
+    // a file uploaded again replaces its entry
+    fn add(&mut self, file: DataFile) {
+        self.files.retain(|entry| entry.key != file.key);
+        self.files.push(file);
+    }
+
+    // files of the hour `date=../hour=../`, or of the whole day for None
+    fn files_of(&self, hour: Option<&str>) -> impl Iterator<Item = &DataFile> {
+        self.files
+            .iter()
+            .filter(move |file| hour_of(&file.key) == hour)
+    }
+}
+
+/// Hour `date=../hour=../` a key or a prefix of a stream is in
+pub fn hour_of(key: &str) -> Option<&str> {
+    key.split_once('/')
+        .and_then(|(_, path)| compaction::periods_of(path).1)
+}
+
+/// Hours `date=../hour=../` of a day
+pub fn day_hours(day: &str) -> Vec<String> {
+    (0..24)
+        .map(|hour| day.to_owned() + &utils::hour_to_prefix(hour))
+        .collect()
+}
+
+/// Hours `date=../hour=../` of a day which overlap the time from `start` up to `end`
+pub fn hours_of(day: &str, start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<String> {
+    day_hours(day)
+        .into_iter()
+        .filter(|hour| {
+            TimeRange::from_path(hour).map_or(false, |range| range.start < end && start < range.end)
+        })
+        .collect()
+}
+
+impl DataFile {
+    /// Entry of a local parquet file uploaded to `key`, with the statistics in its footer
+    pub fn from_parquet(key: String, path: &Path) -> Result<Self, ParquetError> {
+        let size = fs::metadata(path)?.len();
+        let reader = SerializedFileReader::new(File::open(path)?)?;
+        let metadata = reader.metadata();
+
+        // statistics of a column are known only when every row group has them
+        let mut columns: BTreeMap<String, Option<ColumnStats>> = BTreeMap::new();
+        for row_group in metadata.row_groups() {
+            for column in row_group.columns() {
+                let name = column.column_path().string();
+                let stats = column
+                    .statistics()
+                    .and_then(|stats| ColumnStats::from_parquet(stats, column.num_values()));
+                let merged = match columns.remove(&name) {
+                    None => stats,
+                    Some(known) => known.zip(stats).map(|(known, stats)| known.merge(stats)),
+                };
+                columns.insert(name, merged);
+            }
+        }
+
+        let time_range = key
+            .split_once('/')
+            .and_then(|(_, path)| TimeRange::from_path(path));
+
+        Ok(Self {
+            key,
+            rows: Some(metadata.file_metadata().num_rows() as u64),
+            size,
+            time_range,
+            columns: columns
+                .into_iter()
+                .filter_map(|(column, stats)| Some((column, stats?)))
+                .collect(),
+        })
+    }
+
+    // Entry of an object without statistics
+    fn from_object(object: DataObject) -> Self {
+        let time_range = object
+            .key
+            .split_once('/')
+            .and_then(|(_, path)| TimeRange::from_path(path));
+
+        Self {
+            key: object.key,
+            rows: None,
+            size: object.size,
+            time_range,
+            columns: BTreeMap::new(),
+        }
+    }
+
+    /// Whether the file can hold rows from `start` up to `end`
+    pub fn overlaps(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> bool {
+        self.time_range
+            .map_or(true, |range| range.start < end && start < range.end)
+    }
+}
+
+impl From<DataFile> for DataObject {
+    fn from(file: DataFile) -> Self {
+        Self {
+            key: file.key,
+            size: file.size,
+        }
+    }
+}
+
+impl TimeRange {
+    /// Time slot of a prefix or key of a stream, e.g. `date=2022-10-15/hour=10/minute=05-09/`
+    /// is from 10:05 up to 10:10. An object of an hour or a day spans all of it.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let mut parts = path.split('/');
+        let date = parts.next()?.strip_prefix("date=")?;
+        let start = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .ok()?
+            .and_hms_opt(0, 0, 0)?;
+
+        let (start, length) = match parts.next().and_then(|hour| hour.strip_prefix("hour=")) {
+            None => (start, Duration::days(1)),
+            Some(hour) => {
+                let start = start + Duration::hours(hour.parse().ok()?);
+                match parts.next().and_then(|slot| slot.strip_prefix("minute=")) {
+                    None => (start, Duration::hours(1)),
+                    Some(slot) => {
+                        let (first, last) = slot.split_once('-').unwrap_or((slot, slot));
+                        let (first, last): (i64, i64) = (first.parse().ok()?, last.parse().ok()?);
+                        (
+                            start + Duration::minutes(first),
+                            Duration::minutes(last - first + 1),
+                        )
+                    }
+                }
+            }
+        };
+
+        Some(Self {
+            start: DateTime::from_utc(start, Utc),
+            end: DateTime::from_utc(start + length, Utc),
+        })
+    }
+}
+
+impl ColumnStats {
+    // None if the statistics do not tell the range of the values
+    fn from_parquet(stats: &Statistics, num_values: i64) -> Option<Self> {
+        let null_count = stats.null_count();
+        if !stats.has_min_max_set() {
+            // without min and max only a column of nulls is known
+            return (null_count as i64 == num_values).then_some(Self {
+                min: None,
+                max: None,
+                null_count,
+            });
+        }
+
+        let (min, max) = match stats {
+            Statistics::Boolean(stats) => (Value::from(*stats.min()), Value::from(*stats.max())),
+            Statistics::Int32(stats) => (Value::from(*stats.min()), Value::from(*stats.max())),
+            Statistics::Int64(stats) => (Value::from(*stats.min()), Value::from(*stats.max())),
+            Statistics::Float(stats) => (Value::from(*stats.min()), Value::from(*stats.max())),
+            Statistics::Double(stats) => (Value::from(*stats.min()), Value::from(*stats.max())),
+            Statistics::ByteArray(stats) => (
+                Value::from(stats.min().as_utf8().ok()?),
+                Value::from(stats.max().as_utf8().ok()?),
+            ),
+            Statistics::Int96(_) | Statistics::FixedLenByteArray(_) => return None,
+        };
+
+        // NaN has no json number, such a range is unknown
+        if min.is_null() || max.is_null() {
+            return None;
+        }
+
+        Some(Self {
+            min: Some(min),
+            max: Some(max),
+            null_count,
+        })
+    }
+
+    fn merge(self, other: Self) -> Self {
+        let pick = |a: Option<Value>, b: Option<Value>, keep: Ordering| match (a, b) {
+            (Some(a), Some(b)) => Some(if compare(&a, &b) == Some(keep) { a } else { b }),
+            (a, b) => a.or(b),
+        };
+
+        Self {
+            min: pick(self.min, other.min, Ordering::Less),
+            max: pick(self.max, other.max, Ordering::Greater),
+            null_count: self.null_count + other.null_count,
+        }
+    }
+}
+
+// Order of two values of the same type, integers are compared exactly
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => match (a.as_i64(), b.as_i64()) {
+            (Some(a), Some(b)) => Some(a.cmp(&b)),
+            _ => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        },
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+/// Comparisons of a column with a value in the WHERE clause of a query,
+/// files whose statistics rule one of these out are not read
+#[derive(Debug, Default)]
+pub struct Filter(Vec<Condition>);
+
+#[derive(Debug)]
+struct Condition {
+    column: String,
+    op: Operator,
+    value: Value,
+}
+
+impl Filter {
+    pub fn new(query: &str, stream_name: &str, schema: &SchemaRef) -> Self {
+        let ctx = SessionContext::new();
+        let table = EmptyTable::new(Arc::clone(schema));
+        if ctx.register_table(stream_name, Arc::new(table)).is_err() {
+            return Self::default();
+        }
+        let Ok(plan) = ctx.create_logical_plan(query) else {
+            return Self::default();
+        };
+
+        // a condition holds for all the rows read only when the stream is read once,
+        // subqueries of expressions read it as well
+        let mut filters = Vec::new();
+        if scans(&plan, &mut filters) != 1 {
+            return Self::default();
+        }
+
+        let mut conditions = Vec::new();
+        for predicate in filters {
+            collect_conditions(predicate, schema, &mut conditions);
+        }
+
+        Self(conditions)
+    }
+
+    /// Whether the file can hold rows which meet all the conditions
+    pub fn may_match(&self, file: &DataFile) -> bool {
+        self.0
+            .iter()
+            .all(|condition| condition.may_match(file.columns.get(&condition.column)))
+    }
+}
+
+// Number of table scans in the plan, collecting the filters applied right on a scan.
+// Scans of subqueries in expressions are counted, their filters are left out.
+fn scans<'a>(plan: &'a LogicalPlan, filters: &mut Vec<&'a Expr>) -> usize {
+    let subqueries = match subqueries(plan) {
+        Ok(subqueries) => subqueries,
+        // a plan which can not be walked is not pruned
+        Err(_) => return usize::MAX,
+    };
+    let in_subqueries = subqueries
+        .iter()
+        .map(|subquery| scans(subquery, &mut Vec::new()))
+        .fold(0, usize::saturating_add);
+
+    let in_plan = match plan {
+        LogicalPlan::TableScan(_) => 1,
+        LogicalPlan::Filter(filter) => {
+            if let LogicalPlan::TableScan(_) = filter.input.as_ref() {
+                filters.push(&filter.predicate);
+            }
+            scans(&filter.input, filters)
+        }
+        plan => plan
+            .inputs()
+            .into_iter()
+            .map(|input| scans(input, filters))
+            .fold(0, usize::saturating_add),
+    };
+
+    in_plan.saturating_add(in_subqueries)
+}
+
+// Plans of the subqueries in the expressions of a plan node
+fn subqueries(plan: &LogicalPlan) -> datafusion::error::Result<Vec<Arc<LogicalPlan>>> {
+    #[derive(Default)]
+    struct Subqueries(Vec<Arc<LogicalPlan>>);
+
+    impl ExpressionVisitor for Subqueries {
+        fn pre_visit(mut self, expr: &Expr) -> datafusion::error::Result<Recursion<Self>> {
+            match expr {
+                Expr::Exists { subquery, .. }
+                | Expr::InSubquery { subquery, .. }
+                | Expr::ScalarSubquery(subquery) => self.0.push(Arc::clone(&subquery.subquery)),
+                _ => (),
+            }
+            Ok(Recursion::Continue(self))
+        }
+    }
+
+    let mut visitor = Subqueries::default();
+    for expr in plan.expressions() {
+        visitor = expr.accept(visitor)?;
+    }
+    Ok(visitor.0)
+}
+
+fn collect_conditions(expr: &Expr, schema: &Schema, conditions: &mut Vec<Condition>) {
+    let Expr::BinaryExpr(BinaryExpr { left, op, right }) = expr else {
+        return;
+    };
+    if *op == Operator::And {
+        collect_conditions(left, schema, conditions);
+        collect_conditions(right, schema, conditions);
+        return;
+    }
+
+    let (column, op, value) = match (left.as_ref(), right.as_ref()) {
+        (Expr::Column(column), Expr::Literal(value)) => (column, *op, value),
+        (Expr::Literal(value), Expr::Column(column)) => match flip(*op) {
+            Some(op) => (column, op, value),
+            None => return,
+        },
+        _ => return,
+    };
+
+    let Ok(field) = schema.field_with_name(&column.name) else {
+        return;
+    };
+    if let Some(value) = literal(field.data_type(), value) {
+        conditions.push(Condition {
+            column: column.name.clone(),
+            op,
+            value,
+        });
+    }
+}
+
+// Operator with its sides swapped
+fn flip(op: Operator) -> Option<Operator> {
+    match op {
+        Operator::Eq | Operator::NotEq => Some(op),
+        Operator::Lt => Some(Operator::Gt),
+        Operator::LtEq => Some(Operator::GtEq),
+        Operator::Gt => Some(Operator::Lt),
+        Operator::GtEq => Some(Operator::LtEq),
+        _ => None,
+    }
+}
+
+// Value of a literal compared with a column, only for the column types whose
+// statistics are stored as the same values
+fn literal(data_type: &DataType, value: &ScalarValue) -> Option<Value> {
+    match (data_type, value) {
+        (
+            DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::Int64,
+            ScalarValue::Int64(Some(value)),
+        ) => Some(Value::from(*value)),
+        (DataType::Float32 | DataType::Float64, ScalarValue::Int64(Some(value))) => {
+            Some(Value::from(*value))
+        }
+        // NaN has no json number, it is null
+        (DataType::Float32 | DataType::Float64, ScalarValue::Float64(Some(value))) => {
+            Some(Value::from(*value)).filter(|value| !value.is_null())
+        }
+        (DataType::Utf8, ScalarValue::Utf8(Some(value))) => Some(Value::from(value.as_str())),
+        (DataType::Boolean, ScalarValue::Boolean(Some(value))) => Some(Value::from(*value)),
+        _ => None,
+    }
+}
+
+impl Condition {
+    fn may_match(&self, stats: Option<&ColumnStats>) -> bool {
+        let Some(stats) = stats else { return true };
+        // no comparison matches a null
+        let (Some(min), Some(max)) = (&stats.min, &stats.max) else {
+            return false;
+        };
+        let (Some(to_min), Some(to_max)) = (compare(&self.value, min), compare(&self.value, max))
+        else {
+            return true;
+        };
+
+        match self.op {
+            Operator::Eq => to_min != Ordering::Less && to_max != Ordering::Greater,
+            Operator::NotEq => !(to_min == Ordering::Equal && to_max == Ordering::Equal),
+            Operator::Lt => to_min == Ordering::Greater,
+            Operator::LtEq => to_min != Ordering::Less,
+            Operator::Gt => to_max == Ordering::Less,
+            Operator::GtEq => to_max != Ordering::Greater,
+            _ => true,
+        }
+    }
+}
+
+/// Files of a day with the given hours. None when neither the day nor any of
+/// these hours has a manifest, the objects of such a day are not listed in one.
+pub async fn day_files(
+    storage: &(impl ObjectStorage + ?Sized),
+    stream_name: &str,
+    day: &str,
+    hours: Vec<String>,
+) -> Result<Option<DayFiles>, ObjectStorageError> {
+    let day_manifest = storage.get_manifest(stream_name, day).await?;
+    let hour_manifests: Vec<Option<Manifest>> = stream::iter(
+        hours
+            .iter()
+            .map(|hour| storage.get_manifest(stream_name, hour)),
+    )
+    .buffered(MANIFEST_READS)
+    .try_collect()
+    .await?;
+
+    if day_manifest.is_none() && hour_manifests.iter().all(Option::is_none) {
+        return Ok(None);
+    }
+
+    let mut day_files = DayFiles::default();
+    if let Some(ref day_manifest) = day_manifest {
+        day_files.files.extend(day_manifest.files_of(None).cloned());
+    }
+    for (hour, manifest) in hours.into_iter().zip(hour_manifests) {
+        match (manifest, &day_manifest) {
+            (Some(manifest), _) => day_files.files.extend(manifest.files),
+            (None, Some(day_manifest)) => day_files
+                .files
+                .extend(day_manifest.files_of(Some(&hour)).cloned()),
+            (None, None) => day_files.unlisted.push(hour),
+        }
+    }
+
+    Ok(Some(day_files))
+}
+
+// Manifest of an hour, an hour of a day with a single manifest starts out with
+// the files of the hour in it
+async fn hour_manifest(
+    storage: &(impl ObjectStorage + ?Sized),
+    stream_name: &str,
+    hour: &str,
+) -> Result<Option<Manifest>, ObjectStorageError> {
+    if let Some(manifest) = storage.get_manifest(stream_name, hour).await? {
+        return Ok(Some(manifest));
+    }
+
+    let day = compaction::periods_of(hour).0;
+    Ok(storage
+        .get_manifest(stream_name, day)
+        .await?
+        .map(|day_manifest| Manifest {
+            files: day_manifest.files_of(Some(hour)).cloned().collect(),
+        }))
+}
+
+/// Add uploaded files to the manifest of their hour. The manifest of an hour with objects
+/// from before there were manifests starts out with these, without statistics.
+pub async fn add_files(
+    storage: &(impl ObjectStorage + ?Sized),
+    stream_name: &str,
+    hour: &str,
+    files: Vec<DataFile>,
+) -> Result<(), ObjectStorageError> {
+    let _lock = MANIFEST_LOCK.lock().await;
+
+    let mut manifest = match hour_manifest(storage, stream_name, hour).await? {
+        Some(manifest) => manifest,
+        None => {
+            let compaction = storage.get_compaction(stream_name).await?;
+            let files = storage
+                .list_objects(&format!("{}/{}", stream_name, hour))
+                .await?
+                .into_iter()
+                .filter(|object| {
+                    object.key.ends_with(".data.parquet")
+                        && compaction.is_read(stream_name, &object.key)
+                })
+                .map(DataFile::from_object)
+                .collect();
+            Manifest { files }
+        }
+    };
+
+    for file in files {
+        manifest.add(file);
+    }
+
+    storage.put_manifest(stream_name, hour, &manifest).await
+}
+
+/// Replace the files merged into a compacted one in the manifests of their period, an hour
+/// or a day. A period without any manifest is left as it is.
+pub async fn replace_files(
+    storage: &(impl ObjectStorage + ?Sized),
+    stream_name: &str,
+    period: &str,
+    removed: &[String],
+    added: DataFile,
+) -> Result<(), ObjectStorageError> {
+    let _lock = MANIFEST_LOCK.lock().await;
+
+    let (day, hour) = compaction::periods_of(period);
+    if let Some(hour) = hour {
+        let Some(mut manifest) = hour_manifest(storage, stream_name, hour).await? else {
+            return Ok(());
+        };
+        manifest.files.retain(|file| !removed.contains(&file.key));
+        manifest.add(added);
+        return storage.put_manifest(stream_name, hour, &manifest).await;
+    }
+
+    // the compacted object of a day is in the manifest of the day,
+    // the manifests of its hours are left without the merged files
+    let mut listed = false;
+    for hour in day_hours(day) {
+        if let Some(mut manifest) = storage.get_manifest(stream_name, &hour).await? {
+            manifest.files.retain(|file| !removed.contains(&file.key));
+            storage.put_manifest(stream_name, &hour, &manifest).await?;
+            listed = true;
+        }
+    }
+
+    let mut manifest = match storage.get_manifest(stream_name, day).await? {
+        Some(manifest) => manifest,
+        None if listed => Manifest::default(),
+        None => return Ok(()),
+    };
+    manifest.files.retain(|file| !removed.contains(&file.key));
+    manifest.add(added);
+
+    storage.put_manifest(stream_name, day, &manifest).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+    use rstest::*;
+    use serde_json::json;
+
+    use super::{hour_of, hours_of, DataFile, Filter, TimeRange};
+
+    #[fixture]
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("status", DataType::Int64, true),
+            Field::new("host", DataType::Utf8, true),
+        ]))
+    }
+
+    #[fixture]
+    fn file() -> DataFile {
+        serde_json::from_value(json!({
+            "key": "s/date=2022-10-15/hour=10/minute=05-09/h.a.data.parquet",
+            "rows": 10,
+            "size": 1024,
+            "timeRange": { "start": "2022-10-15T10:05:00Z", "end": "2022-10-15T10:10:00Z" },
+            "columns": {
+                "status": { "min": 200, "max": 404, "nullCount": 0 },
+                "host": { "min": null, "max": null, "nullCount": 10 },
+            },
+        }))
+        .unwrap()
+    }
+
+    #[rstest]
+    #[case(
+        "date=2022-10-15/hour=10/minute=05-09/h.a.data.parquet",
+        "2022-10-15T10:05:00Z",
+        "2022-10-15T10:10:00Z"
+    )]
+    #[case(
+        "date=2022-10-15/hour=10/minute=59/h.a.data.parquet",
+        "2022-10-15T10:59:00Z",
+        "2022-10-15T11:00:00Z"
+    )]
+    #[case(
+        "date=2022-10-15/hour=23/compacted.a.data.parquet",
+        "2022-10-15T23:00:00Z",
+        "2022-10-16T00:00:00Z"
+    )]
+    #[case(
+        "date=2022-10-15/compacted.a.data.parquet",
+        "2022-10-15T00:00:00Z",
+        "2022-10-16T00:00:00Z"
+    )]
+    fn time_range_of_path(#[case] path: &str, #[case] start: &str, #[case] end: &str) {
+        let range = TimeRange::from_path(path).unwrap();
+        assert_eq!(
+            range.start,
+            start.parse::<chrono::DateTime<chrono::Utc>>().unwrap()
+        );
+        assert_eq!(
+            range.end,
+            end.parse::<chrono::DateTime<chrono::Utc>>().unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case("SELECT * FROM s", true)]
+    #[case("SELECT * FROM s WHERE status = 500", false)]
+    #[case("SELECT * FROM s WHERE status >= 404", true)]
+    #[case("SELECT * FROM s WHERE 404 < status", false)]
+    #[case("SELECT * FROM s WHERE status < 500 AND status > 404", false)]
+    #[case("SELECT * FROM s WHERE status = 500 OR status = 200", true)]
+    #[case("SELECT * FROM s WHERE host = 'a'", false)]
+    #[case("SELECT * FROM s WHERE status = 500 UNION ALL SELECT * FROM s", true)]
+    #[case(
+        "SELECT * FROM s WHERE status = 500 AND host IN (SELECT host FROM s)",
+        true
+    )]
+    #[case(
+        "SELECT * FROM s WHERE status = 500 AND status < (SELECT max(status) FROM s)",
+        true
+    )]
+    #[case(
+        "SELECT * FROM s WHERE status = 500 AND EXISTS (SELECT * FROM s WHERE host = 'a')",
+        true
+    )]
+    fn filter_files(schema: SchemaRef, file: DataFile, #[case] query: &str, #[case] read: bool) {
+        let filter = Filter::new(query, "s", &schema);
+        assert_eq!(filter.may_match(&file), read);
+    }
+
+    #[rstest]
+    fn files_of_time_range(file: DataFile) {
+        let time = |time: &str| time.parse().unwrap();
+        assert!(file.overlaps(time("2022-10-15T10:09:00Z"), time("2022-10-15T10:11:00Z")));
+        assert!(!file.overlaps(time("2022-10-15T10:10:00Z"), time("2022-10-15T10:11:00Z")));
+    }
+
+    #[rstest]
+    #[case(
+        "s/date=2022-10-15/hour=10/minute=05-09/h.a.data.parquet",
+        Some("date=2022-10-15/hour=10/")
+    )]
+    #[case("s/date=2022-10-15/hour=10/", Some("date=2022-10-15/hour=10/"))]
+    #[case("s/date=2022-10-15/compacted.a.data.parquet", None)]
+    #[case("s/date=2022-10-15/", None)]
+    fn hour_of_key(#[case] key: &str, #[case] hour: Option<&str>) {
+        assert_eq!(hour_of(key), hour);
+    }
+
+    #[rstest]
+    #[case("2022-10-15T10:05:00Z", "2022-10-15T11:00:00Z", &["date=2022-10-15/hour=10/"])]
+    #[case(
+        "2022-10-14T23:30:00Z",
+        "2022-10-15T01:30:00Z",
+        &["date=2022-10-15/hour=00/", "date=2022-10-15/hour=01/"]
+    )]
+    #[case("2022-10-16T00:00:00Z", "2022-10-16T01:00:00Z", &[])]
+    fn hours_in_range(#[case] start: &str, #[case] end: &str, #[case] hours: &[&str]) {
+        let start = start.parse::<chrono::DateTime<chrono::Utc>>().unwrap();
+        let end = end.parse::<chrono::DateTime<chrono::Utc>>().unwrap();
+        assert_eq!(hours_of("date=2022-10-15/", start, end), hours);
+    }
+}
@@ -17,12 +17,25 @@
  */
 
 use datafusion::arrow::datatypes::Schema;
+use datafusion::arrow::record_batch::RecordBatch;
 use lazy_static::lazy_static;
 use std::collections::HashMap;
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
 
 use crate::alerts::Alerts;
-use crate::event::Event;
+use crate::event::coercion::{self, Coercion};
+use crate::event::dead_letter::DeadLetter;
+use crate::event::dedup::Dedup;
+use crate::event::enrichment::Enrichment;
+use crate::event::explode::Explode;
+use crate::event::guardrails::Guardrails;
+use crate::event::json_schema::JsonSchema;
+use crate::event::labels::{self, Labels};
+use crate::event::lookup::LookupJoin;
+use crate::event::nesting::Nesting;
+use crate::event::sampling::Sampling;
+use crate::parquet::ParquetConfig;
+use crate::retention::Retention;
 use crate::stats::{Stats, StatsCounter};
 use crate::storage::ObjectStorage;
 
@@ -31,7 +44,22 @@ use self::error::stream_info::{CheckAlertError, LoadError, MetadataError};
 #[derive(Debug, Default)]
 pub struct LogStreamMetadata {
     pub schema: Option<Schema>,
+    pub static_schema: bool,
+    pub data_granularity: u32,
+    pub coercion: Coercion,
+    pub json_schema: Option<Arc<JsonSchema>>,
+    pub nesting: Nesting,
+    pub explode: Option<Explode>,
+    pub guardrails: Option<Guardrails>,
+    pub labels: Labels,
+    pub enrichment: Enrichment,
+    pub lookups: Vec<LookupJoin>,
     pub alerts: Alerts,
+    pub sampling: Option<Sampling>,
+    pub dedup: Option<Arc<Dedup>>,
+    pub dead_letter: Option<DeadLetter>,
+    pub parquet: ParquetConfig,
+    pub retention: Option<Retention>,
     pub stats: StatsCounter,
 }
 
@@ -50,21 +78,42 @@ pub const LOCK_EXPECT: &str = "no method in metadata should panic while holding
 // 2. When a new stream is created (make a new entry in the map)
 // 3. When a stream is deleted (remove the entry from the map)
 // 4. When first event is sent to stream (update the schema)
+//    or when the stream is created with a declared schema
 // 5. When set alert API is called (update the alert)
+// 6. When set sampling API is called (update the sampling rules)
+// 7. When set dedup API is called (update the dedup config)
+// 8. When set coercion API is called (update the coercion config)
+// 9. When set dead-letter API is called (update the dead-letter stream)
+// 10. When set nesting API is called (update the nesting mode)
+// 11. When set explode API is called (update the array path to explode)
+// 12. When set guardrails API is called (update the schema guardrails)
+// 13. When set labels API is called (update the format of tags and metadata)
+// 14. When set enrichment API is called (update the client context columns)
+// 15. When set lookup API is called (update the lookup table joins)
+// 16. When set json schema API is called (update the event contract)
+// 17. When set parquet API is called (update the parquet writer settings)
+// 18. When set retention API is called (update the days data is kept for)
 #[allow(clippy::all)]
 impl STREAM_INFO {
-    pub async fn check_alerts(&self, event: &Event) -> Result<(), CheckAlertError> {
+    pub fn has_alerts(&self, stream_name: &str) -> Result<bool, MetadataError> {
         let map = self.read().expect(LOCK_EXPECT);
-        let meta = map
-            .get(&event.stream_name)
-            .ok_or(MetadataError::StreamMetaNotFound(
-                event.stream_name.to_owned(),
-            ))?;
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| !metadata.alerts.alerts.is_empty())
+    }
 
-        let event_json: serde_json::Value = serde_json::from_str(&event.body)?;
+    pub fn check_alerts(
+        &self,
+        stream_name: &str,
+        events: &RecordBatch,
+    ) -> Result<(), CheckAlertError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        let meta = map
+            .get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_owned()))?;
 
         for alert in &meta.alerts.alerts {
-            alert.check_alert(event.stream_name.clone(), &event_json)
+            alert.check_alert(stream_name, events)
         }
 
         Ok(())
@@ -80,6 +129,185 @@ impl STREAM_INFO {
             })
     }
 
+    // Columns are widened under the write lock, so that concurrent events
+    // widening different columns do not undo each other
+    pub fn widen_columns(
+        &self,
+        stream_name: &str,
+        columns: &[String],
+    ) -> Result<Schema, MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let metadata = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        let schema = metadata
+            .schema
+            .as_ref()
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+
+        let schema = coercion::widen(schema, columns);
+        metadata.schema = Some(schema.clone());
+        Ok(schema)
+    }
+
+    // Label columns are added under the write lock as well
+    pub fn add_label_columns(
+        &self,
+        stream_name: &str,
+        columns: &[String],
+    ) -> Result<Schema, MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let metadata = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        let schema = metadata
+            .schema
+            .as_ref()
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+
+        let schema = labels::with_columns(schema, columns);
+        metadata.schema = Some(schema.clone());
+        Ok(schema)
+    }
+
+    pub fn data_granularity(&self, stream_name: &str) -> Result<u32, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.data_granularity)
+    }
+
+    pub fn is_static_schema(&self, stream_name: &str) -> Result<bool, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.static_schema)
+    }
+
+    pub fn coercion(&self, stream_name: &str) -> Result<Coercion, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.coercion)
+    }
+
+    pub fn set_coercion(&self, stream_name: &str, coercion: Coercion) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.coercion = coercion;
+            })
+    }
+
+    pub fn nesting(&self, stream_name: &str) -> Result<Nesting, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.nesting)
+    }
+
+    pub fn set_nesting(&self, stream_name: &str, nesting: Nesting) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.nesting = nesting;
+            })
+    }
+
+    pub fn explode(&self, stream_name: &str) -> Result<Option<Explode>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.explode.to_owned())
+    }
+
+    pub fn set_explode(&self, stream_name: &str, explode: Explode) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.explode.replace(explode);
+            })
+    }
+
+    pub fn guardrails(&self, stream_name: &str) -> Result<Option<Guardrails>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.guardrails.to_owned())
+    }
+
+    pub fn set_guardrails(
+        &self,
+        stream_name: &str,
+        guardrails: Guardrails,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.guardrails.replace(guardrails);
+            })
+    }
+
+    pub fn labels(&self, stream_name: &str) -> Result<Labels, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.labels)
+    }
+
+    pub fn set_labels(&self, stream_name: &str, labels: Labels) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.labels = labels;
+            })
+    }
+
+    pub fn enrichment(&self, stream_name: &str) -> Result<Enrichment, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.enrichment)
+    }
+
+    pub fn set_enrichment(
+        &self,
+        stream_name: &str,
+        enrichment: Enrichment,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.enrichment = enrichment;
+            })
+    }
+
+    pub fn lookups(&self, stream_name: &str) -> Result<Vec<LookupJoin>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.lookups.clone())
+    }
+
+    pub fn set_lookups(
+        &self,
+        stream_name: &str,
+        lookups: Vec<LookupJoin>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.lookups = lookups;
+            })
+    }
+
     pub fn schema(&self, stream_name: &str) -> Result<Option<Schema>, MetadataError> {
         let map = self.read().expect(LOCK_EXPECT);
         map.get(stream_name)
@@ -96,16 +324,148 @@ impl STREAM_INFO {
             })
     }
 
-    pub fn add_stream(&self, stream_name: String, schema: Option<Schema>, alerts: Alerts) {
+    pub fn sampling(&self, stream_name: &str) -> Result<Option<Sampling>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.sampling.to_owned())
+    }
+
+    pub fn set_sampling(&self, stream_name: &str, sampling: Sampling) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.sampling.replace(sampling);
+            })
+    }
+
+    pub fn json_schema(&self, stream_name: &str) -> Result<Option<Arc<JsonSchema>>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.json_schema.clone())
+    }
+
+    pub fn set_json_schema(
+        &self,
+        stream_name: &str,
+        json_schema: JsonSchema,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.json_schema.replace(Arc::new(json_schema));
+            })
+    }
+
+    pub fn dedup(&self, stream_name: &str) -> Result<Option<Arc<Dedup>>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.dedup.clone())
+    }
+
+    pub fn set_dedup(&self, stream_name: &str, dedup: Dedup) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.dedup.replace(Arc::new(dedup));
+            })
+    }
+
+    pub fn parquet(&self, stream_name: &str) -> Result<ParquetConfig, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.parquet.clone())
+    }
+
+    pub fn set_parquet(
+        &self,
+        stream_name: &str,
+        parquet: ParquetConfig,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.parquet = parquet;
+            })
+    }
+
+    pub fn retention(&self, stream_name: &str) -> Result<Option<Retention>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.retention)
+    }
+
+    pub fn set_retention(
+        &self,
+        stream_name: &str,
+        retention: Retention,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.retention.replace(retention);
+            })
+    }
+
+    pub fn dead_letter(&self, stream_name: &str) -> Result<Option<DeadLetter>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.dead_letter.to_owned())
+    }
+
+    pub fn set_dead_letter(
+        &self,
+        stream_name: &str,
+        dead_letter: DeadLetter,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.dead_letter.replace(dead_letter);
+            })
+    }
+
+    pub fn add_stream(
+        &self,
+        stream_name: String,
+        schema: Option<Schema>,
+        alerts: Alerts,
+        data_granularity: u32,
+    ) {
         let mut map = self.write().expect(LOCK_EXPECT);
         let metadata = LogStreamMetadata {
             schema,
+            data_granularity,
             alerts,
             ..Default::default()
         };
         map.insert(stream_name, metadata);
     }
 
+    // The schema is set along with the stream, so that no event
+    // can infer a schema before the declared one is in place
+    pub fn add_static_stream(&self, stream_name: String, schema: Schema, data_granularity: u32) {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let metadata = LogStreamMetadata {
+            schema: Some(schema),
+            static_schema: true,
+            data_granularity,
+            ..Default::default()
+        };
+        map.insert(stream_name, metadata);
+    }
+
     pub fn delete_stream(&self, stream_name: &str) {
         let mut map = self.write().expect(LOCK_EXPECT);
         map.remove(stream_name);
@@ -120,12 +480,27 @@ impl STREAM_INFO {
         for stream in storage.list_streams().await? {
             let alerts = storage.get_alerts(&stream.name).await?;
             let schema = storage.get_schema(&stream.name).await?;
-            let stats = storage.get_stats(&stream.name).await?;
+            let format = storage.get_stream_metadata(&stream.name).await?;
 
             let metadata = LogStreamMetadata {
                 schema,
+                static_schema: format.static_schema,
+                data_granularity: format.data_granularity,
+                coercion: format.coercion,
+                json_schema: format.json_schema.map(Arc::new),
+                nesting: format.nesting,
+                explode: format.explode,
+                guardrails: format.guardrails,
+                labels: format.labels,
+                enrichment: format.enrichment,
+                lookups: format.lookups,
                 alerts,
-                stats: stats.into(),
+                sampling: format.sampling,
+                dedup: format.dedup.map(Arc::new),
+                dead_letter: format.dead_letter,
+                parquet: format.parquet,
+                retention: format.retention,
+                stats: format.stats.into(),
             };
 
             let mut map = self.write().expect(LOCK_EXPECT);
@@ -155,6 +530,24 @@ impl STREAM_INFO {
         Ok(())
     }
 
+    /// Account for objects of a stream replaced in object storage
+    pub fn replace_storage_size(
+        &self,
+        stream_name: &str,
+        removed: u64,
+        added: u64,
+    ) -> Result<Stats, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        let stream = map
+            .get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_owned()))?;
+
+        stream.stats.sub_storage_size(removed);
+        stream.stats.add_storage_size(added);
+
+        Ok(Stats::from(&stream.stats))
+    }
+
     pub fn get_stats(&self, stream_name: &str) -> Result<Stats, MetadataError> {
         self.read()
             .expect(LOCK_EXPECT)
@@ -170,8 +563,6 @@ pub mod error {
 
         #[derive(Debug, thiserror::Error)]
         pub enum CheckAlertError {
-            #[error("Serde Json Error: {0}")]
-            Serde(#[from] serde_json::Error),
             #[error("Metadata Error: {0}")]
             Metadata(#[from] MetadataError),
         }
@@ -17,14 +17,15 @@
  */
 
 use clap::builder::ArgPredicate;
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use crossterm::style::Stylize;
+use std::net::IpAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
 
 use crate::banner;
 use crate::s3::S3Config;
-use crate::storage::{ObjectStorage, ObjectStorageError, LOCAL_SYNC_INTERVAL};
+use crate::storage::{ObjectStorage, ObjectStorageError};
 
 lazy_static::lazy_static! {
     #[derive(Debug)]
@@ -77,8 +78,51 @@ where
     }
 
     pub fn validate(&self) {
-        if CONFIG.parseable.upload_interval < LOCAL_SYNC_INTERVAL {
-            panic!("object storage upload_interval (P_STORAGE_UPLOAD_INTERVAL) must be 60 seconds or more");
+        if CONFIG.parseable.local_sync_interval == 0 {
+            panic!("local sync interval (P_LOCAL_SYNC_INTERVAL) must be 1 second or more");
+        }
+        if CONFIG.parseable.upload_interval < CONFIG.parseable.local_sync_interval {
+            panic!("object storage upload_interval (P_STORAGE_UPLOAD_INTERVAL) must be at least the local sync interval (P_LOCAL_SYNC_INTERVAL)");
+        }
+        if CONFIG.parseable.upload_concurrency == 0 {
+            panic!("upload concurrency (P_UPLOAD_CONCURRENCY) must be greater than zero");
+        }
+        let granularity = CONFIG.parseable.data_granularity;
+        if granularity == 0 || 60 % granularity != 0 {
+            panic!("data granularity (P_DATA_GRANULARITY) must be a number of minutes which divides an hour");
+        }
+        if CONFIG.parseable.compaction_delay == Some(0) {
+            panic!("compaction delay (P_COMPACTION_DELAY) must be 1 hour or more");
+        }
+        if CONFIG.parseable.rotate_max_rows == Some(0)
+            || CONFIG.parseable.rotate_max_bytes == Some(0)
+        {
+            panic!("local file limits (P_ROTATE_MAX_ROWS, P_ROTATE_MAX_BYTES) must be greater than zero");
+        }
+        let (low, high) = (
+            CONFIG.parseable.staging_low_watermark,
+            CONFIG.parseable.staging_high_watermark,
+        );
+        if !(0.0..=100.0).contains(&high) || !(0.0..high).contains(&low) {
+            panic!("staging watermarks (P_STAGING_LOW_WATERMARK, P_STAGING_HIGH_WATERMARK) must be percentages with low under high");
+        }
+        if CONFIG.parseable.buffer_max_rows == 0 || CONFIG.parseable.buffer_flush_interval == 0 {
+            panic!("buffer limits (P_BUFFER_MAX_ROWS, P_BUFFER_FLUSH_INTERVAL) must be greater than zero");
+        }
+        if CONFIG.parseable.wal_fsync_interval == 0 {
+            panic!(
+                "write-ahead log fsync interval (P_WAL_FSYNC_INTERVAL) must be 1 second or more"
+            );
+        }
+        if CONFIG.parseable.alert_workers == 0
+            || CONFIG.parseable.alert_queue_size < CONFIG.parseable.alert_workers
+        {
+            panic!("alert queue (P_ALERT_WORKERS, P_ALERT_QUEUE_SIZE) must have at least one worker and one slot for each worker");
+        }
+        if CONFIG.parseable.ack_mode == AckMode::Durable
+            && CONFIG.parseable.wal_fsync == FsyncPolicy::None
+        {
+            panic!("durable ack mode (P_ACK_MODE) requires an fsync policy (P_WAL_FSYNC) other than none");
         }
     }
 
@@ -215,6 +259,162 @@ where
     )]
     pub upload_interval: u64,
 
+    /// Number of files uploaded to object storage at the same
+    /// time during a sync. Defaults to 8.
+    #[arg(
+        long,
+        env = "P_UPLOAD_CONCURRENCY",
+        default_value = "8",
+        value_name = "count"
+    )]
+    pub upload_concurrency: usize,
+
+    /// Interval after which the local log file of every stream is closed,
+    /// and becomes ready for upload. Defaults to 1 min.
+    #[arg(
+        long,
+        env = "P_LOCAL_SYNC_INTERVAL",
+        default_value = "60",
+        value_name = "seconds"
+    )]
+    pub local_sync_interval: u64,
+
+    /// Maximum number of rows in a local log file, a file
+    /// over it is closed right away. No limit by default.
+    #[arg(long, env = "P_ROTATE_MAX_ROWS", value_name = "rows")]
+    pub rotate_max_rows: Option<usize>,
+
+    /// Maximum size of a local log file, a file over it
+    /// is closed right away. No limit by default.
+    #[arg(long, env = "P_ROTATE_MAX_BYTES", value_name = "bytes")]
+    pub rotate_max_bytes: Option<u64>,
+
+    /// Length of the time slots data is partitioned by in object storage, in minutes.
+    /// Must divide an hour. Only applies to streams created after it is set,
+    /// existing streams keep the granularity they were created with. Defaults to 1.
+    #[arg(
+        long,
+        env = "P_DATA_GRANULARITY",
+        default_value = "1",
+        value_name = "minutes"
+    )]
+    pub data_granularity: u32,
+
+    /// Hours after which the objects of an hour are compacted into a single object,
+    /// and those of a day once it is over. A query over a compacted period reads
+    /// all of the period. No compaction by default.
+    #[arg(long, env = "P_COMPACTION_DELAY", value_name = "hours")]
+    pub compaction_delay: Option<u64>,
+
+    /// Number of rows an in memory buffer of a stream collects
+    /// before writing them to the local log file. Defaults to 10000.
+    #[arg(
+        long,
+        env = "P_BUFFER_MAX_ROWS",
+        default_value = "10000",
+        value_name = "rows"
+    )]
+    pub buffer_max_rows: usize,
+
+    /// Maximum time events stay in the in memory buffer of a stream before
+    /// they are written to the local log file. Defaults to 1 second.
+    #[arg(
+        long,
+        env = "P_BUFFER_FLUSH_INTERVAL",
+        default_value = "1",
+        value_name = "seconds"
+    )]
+    pub buffer_flush_interval: u64,
+
+    /// When the events written to the local write-ahead log are flushed to disk.
+    /// Defaults to every P_WAL_FSYNC_INTERVAL.
+    #[arg(
+        long,
+        env = "P_WAL_FSYNC",
+        value_enum,
+        default_value = "interval",
+        value_name = "policy"
+    )]
+    pub wal_fsync: FsyncPolicy,
+
+    /// Interval between flushes of the write-ahead log to disk,
+    /// with the interval fsync policy. Defaults to 1 second.
+    #[arg(
+        long,
+        env = "P_WAL_FSYNC_INTERVAL",
+        default_value = "1",
+        value_name = "seconds"
+    )]
+    pub wal_fsync_interval: u64,
+
+    /// When a request with events is acknowledged. Durable mode responds only
+    /// after the events are flushed to disk. In buffered mode events which are
+    /// acknowledged are lost on a crash until they are written out, which takes
+    /// up to P_BUFFER_FLUSH_INTERVAL, and until they are flushed to disk as set
+    /// by P_WAL_FSYNC. Defaults to buffered.
+    #[arg(
+        long,
+        env = "P_ACK_MODE",
+        value_enum,
+        default_value = "buffered",
+        value_name = "mode"
+    )]
+    pub ack_mode: AckMode,
+
+    /// Usage of the disk holding the local storage, in percent, above which
+    /// ingestion is paused until uploads drain it. Defaults to 90.
+    #[arg(
+        long,
+        env = "P_STAGING_HIGH_WATERMARK",
+        default_value = "90",
+        value_name = "percent"
+    )]
+    pub staging_high_watermark: f64,
+
+    /// Usage of the disk holding the local storage, in percent, under which
+    /// paused ingestion is resumed. Defaults to 80.
+    #[arg(
+        long,
+        env = "P_STAGING_LOW_WATERMARK",
+        default_value = "80",
+        value_name = "percent"
+    )]
+    pub staging_low_watermark: f64,
+
+    /// Number of workers evaluating the alerts of ingested events. Defaults to 2.
+    #[arg(
+        long,
+        env = "P_ALERT_WORKERS",
+        default_value = "2",
+        value_name = "count"
+    )]
+    pub alert_workers: usize,
+
+    /// Number of ingested batches waiting for alert evaluation, further
+    /// batches are not evaluated while the queue is full. Defaults to 10000.
+    #[arg(
+        long,
+        env = "P_ALERT_QUEUE_SIZE",
+        default_value = "10000",
+        value_name = "batches"
+    )]
+    pub alert_queue_size: usize,
+
+    /// Maximum number of tag headers, and of metadata headers,
+    /// accepted with an event. Defaults to 10.
+    #[arg(long, env = "P_MAX_LABELS", default_value = "10", value_name = "count")]
+    pub max_labels: usize,
+
+    /// Comma separated addresses of the proxies in front of the server.
+    /// Client address of a request from these is read from X-Forwarded-For.
+    #[arg(
+        long,
+        env = "P_TRUSTED_PROXIES",
+        value_name = "ip",
+        value_delimiter = ','
+    )]
+    pub trusted_proxies: Vec<IpAddr>,
+
     /// Optional username to enable basic auth on the server
     #[arg(
         long,
@@ -262,6 +462,25 @@ where
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FsyncPolicy {
+    /// Flush at the end of every request
+    Request,
+    /// Flush periodically in the background
+    Interval,
+    /// Leave flushing to the operating system
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AckMode {
+    /// Respond once the events are buffered, these reach the write-ahead log
+    /// within the buffer flush interval and are lost on a crash before that
+    Buffered,
+    /// Respond once the events are flushed to disk
+    Durable,
+}
+
 pub(self) mod validation {
     use std::path::PathBuf;
 
@@ -0,0 +1,367 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+
+use datafusion::arrow::compute::{lexsort_to_indices, take, SortColumn, SortOptions};
+use datafusion::arrow::datatypes::{Schema, SchemaRef};
+use datafusion::arrow::error::ArrowError;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::parquet::arrow::arrow_to_parquet_schema;
+use datafusion::parquet::basic::Compression;
+use datafusion::parquet::file::properties::{EnabledStatistics, WriterProperties};
+use datafusion::parquet::schema::types::{ColumnPath, SchemaDescriptor};
+use serde::{Deserialize, Serialize};
+
+/// Settings of the parquet files a stream is converted to. Every setting
+/// left out keeps the default of the parquet writer.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ParquetConfig {
+    #[serde(default)]
+    pub compression: Codec,
+    /// Not supported by the parquet writer yet, rejected when set
+    pub compression_level: Option<i32>,
+    pub max_row_group_size: Option<usize>,
+    #[serde(default)]
+    pub dictionary: Toggle,
+    #[serde(default)]
+    pub statistics: Toggle,
+    /// Not supported by the parquet writer yet, rejected when set
+    #[serde(default)]
+    pub bloom_filter_columns: Vec<String>,
+    /// Column holding the time of an event, rows of a file are sorted by it
+    /// so that row groups cover narrow time ranges
+    pub time_column: Option<String>,
+    /// Columns rows with the same time are sorted by, in order
+    #[serde(default)]
+    pub sort_keys: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    Uncompressed,
+    Snappy,
+    Gzip,
+    Lz4,
+    Zstd,
+    Brotli,
+}
+
+/// A setting for all the columns, with overrides for some of them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Toggle {
+    pub enabled: Option<bool>,
+    #[serde(default)]
+    pub columns: BTreeMap<String, bool>,
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Self::Uncompressed
+    }
+}
+
+impl From<Codec> for Compression {
+    fn from(codec: Codec) -> Self {
+        match codec {
+            Codec::Uncompressed => Compression::UNCOMPRESSED,
+            Codec::Snappy => Compression::SNAPPY,
+            Codec::Gzip => Compression::GZIP,
+            Codec::Lz4 => Compression::LZ4,
+            Codec::Zstd => Compression::ZSTD,
+            Codec::Brotli => Compression::BROTLI,
+        }
+    }
+}
+
+fn statistics(enabled: bool) -> EnabledStatistics {
+    if enabled {
+        EnabledStatistics::Page
+    } else {
+        EnabledStatistics::None
+    }
+}
+
+impl ParquetConfig {
+    /// Properties of the parquet writer for files of the schema
+    pub fn writer_properties(&self, schema: &Schema) -> WriterProperties {
+        let parquet_schema = arrow_to_parquet_schema(schema).ok();
+        let column_paths = |column| column_paths(parquet_schema.as_ref(), column);
+
+        let mut builder = WriterProperties::builder().set_compression(self.compression.into());
+
+        if let Some(max_row_group_size) = self.max_row_group_size {
+            builder = builder.set_max_row_group_size(max_row_group_size);
+        }
+
+        if let Some(enabled) = self.dictionary.enabled {
+            builder = builder.set_dictionary_enabled(enabled);
+        }
+        for (column, &enabled) in &self.dictionary.columns {
+            for path in column_paths(column) {
+                builder = builder.set_column_dictionary_enabled(path, enabled);
+            }
+        }
+
+        if let Some(enabled) = self.statistics.enabled {
+            builder = builder.set_statistics_enabled(statistics(enabled));
+        }
+        for (column, &enabled) in &self.statistics.columns {
+            for path in column_paths(column) {
+                builder = builder.set_column_statistics_enabled(path, statistics(enabled));
+            }
+        }
+
+        builder.build()
+    }
+
+    /// Settings the parquet writer of this version does not support yet,
+    /// these are rejected rather than stored without an effect
+    pub fn unsupported(&self) -> Vec<&'static str> {
+        let mut unsupported = Vec::new();
+        if self.compression_level.is_some() {
+            unsupported.push("compressionLevel");
+        }
+        if !self.bloom_filter_columns.is_empty() {
+            unsupported.push("bloomFilterColumns");
+        }
+        unsupported
+    }
+
+    pub fn columns(&self) -> impl Iterator<Item = &String> {
+        self.dictionary
+            .columns
+            .keys()
+            .chain(self.statistics.columns.keys())
+            .chain(self.bloom_filter_columns.iter())
+            .chain(self.sort_columns())
+    }
+
+    fn sort_columns(&self) -> impl Iterator<Item = &String> {
+        self.time_column.iter().chain(self.sort_keys.iter())
+    }
+
+    /// Rows of the batches sorted by the time column and then the sort keys, as a
+    /// single batch. Sort columns missing from the schema are skipped, None is
+    /// returned when there is nothing to sort by.
+    pub fn sort(
+        &self,
+        schema: &SchemaRef,
+        batches: &[RecordBatch],
+    ) -> Result<Option<RecordBatch>, ArrowError> {
+        let indices: Vec<usize> = self
+            .sort_columns()
+            .filter_map(|column| schema.index_of(column).ok())
+            .collect();
+        if indices.is_empty() || batches.is_empty() {
+            return Ok(None);
+        }
+
+        let record = RecordBatch::concat(schema, batches)?;
+        let sort_columns: Vec<SortColumn> = indices
+            .into_iter()
+            .map(|index| SortColumn {
+                values: record.column(index).clone(),
+                options: Some(SortOptions {
+                    descending: false,
+                    nulls_first: false,
+                }),
+            })
+            .collect();
+
+        let sorted = lexsort_to_indices(&sort_columns, None)?;
+        let columns = record
+            .columns()
+            .iter()
+            .map(|column| take(column.as_ref(), &sorted, None))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        RecordBatch::try_new(schema.clone(), columns).map(Some)
+    }
+}
+
+// Parquet columns a setting of a stream column applies to. A List or Struct column
+// of a nested stream is stored as a parquet column for each of its leaf fields.
+fn column_paths(schema: Option<&SchemaDescriptor>, column: &str) -> Vec<ColumnPath> {
+    let Some(schema) = schema else {
+        return vec![ColumnPath::new(vec![column.to_owned()])];
+    };
+
+    schema
+        .columns()
+        .iter()
+        .map(|descriptor| descriptor.path())
+        .filter(|path| path.parts().first().map(String::as_str) == Some(column))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use datafusion::arrow::array::StringArray;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::parquet::basic::Compression;
+    use datafusion::parquet::file::properties::EnabledStatistics;
+    use datafusion::parquet::schema::types::ColumnPath;
+    use rstest::*;
+    use serde_json::json;
+
+    use super::ParquetConfig;
+
+    fn column_path(path: &[&str]) -> ColumnPath {
+        ColumnPath::new(path.iter().map(|part| part.to_string()).collect())
+    }
+
+    #[fixture]
+    fn schema() -> Schema {
+        Schema::new(vec![
+            Field::new("host", DataType::Utf8, true),
+            Field::new("message", DataType::Utf8, true),
+            Field::new(
+                "request",
+                DataType::Struct(vec![
+                    Field::new("method", DataType::Utf8, true),
+                    Field::new("path", DataType::Utf8, true),
+                ]),
+                true,
+            ),
+        ])
+    }
+
+    #[rstest]
+    fn default_keeps_writer_defaults(schema: Schema) {
+        let config: ParquetConfig = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(config, ParquetConfig::default());
+        assert_eq!(
+            config
+                .writer_properties(&schema)
+                .compression(&column_path(&["host"])),
+            Compression::UNCOMPRESSED
+        );
+    }
+
+    #[rstest]
+    fn column_overrides(schema: Schema) {
+        let config: ParquetConfig = serde_json::from_value(json!({
+            "compression": "zstd",
+            "maxRowGroupSize": 8192,
+            "dictionary": { "enabled": true, "columns": { "message": false } },
+            "statistics": { "columns": { "message": false } },
+        }))
+        .unwrap();
+        let properties = config.writer_properties(&schema);
+
+        assert_eq!(
+            properties.compression(&column_path(&["host"])),
+            Compression::ZSTD
+        );
+        assert_eq!(properties.max_row_group_size(), 8192);
+        assert!(properties.dictionary_enabled(&column_path(&["host"])));
+        assert!(!properties.dictionary_enabled(&column_path(&["message"])));
+        assert_eq!(
+            properties.statistics_enabled(&column_path(&["message"])),
+            EnabledStatistics::None
+        );
+        assert!(config.unsupported().is_empty());
+    }
+
+    #[rstest]
+    fn nested_column_overrides_apply_to_leaves(schema: Schema) {
+        let config: ParquetConfig = serde_json::from_value(json!({
+            "dictionary": { "columns": { "request": false } },
+        }))
+        .unwrap();
+        let properties = config.writer_properties(&schema);
+
+        assert!(!properties.dictionary_enabled(&column_path(&["request", "method"])));
+        assert!(!properties.dictionary_enabled(&column_path(&["request", "path"])));
+        assert!(properties.dictionary_enabled(&column_path(&["host"])));
+    }
+
+    #[rstest]
+    fn sorts_by_time_then_keys() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("time", DataType::Utf8, true),
+            Field::new("host", DataType::Utf8, true),
+        ]));
+        let batch = |time: Vec<Option<&str>>, host: Vec<&str>| {
+            RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(StringArray::from(time)),
+                    Arc::new(StringArray::from(host)),
+                ],
+            )
+            .unwrap()
+        };
+        let batches = vec![
+            batch(vec![Some("2022-10-15T10:02:00Z"), None], vec!["a", "b"]),
+            batch(
+                vec![Some("2022-10-15T10:01:00Z"), Some("2022-10-15T10:01:00Z")],
+                vec!["d", "c"],
+            ),
+        ];
+        let config: ParquetConfig =
+            serde_json::from_value(json!({ "timeColumn": "time", "sortKeys": ["host"] })).unwrap();
+
+        let sorted = config.sort(&schema, &batches).unwrap().unwrap();
+
+        let host = sorted
+            .column(1)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        let host: Vec<&str> = host.iter().flatten().collect();
+        assert_eq!(host, vec!["c", "d", "a", "b"]);
+    }
+
+    #[rstest]
+    fn unsorted_without_sort_columns() {
+        let schema = Arc::new(Schema::new(vec![Field::new("host", DataType::Utf8, true)]));
+        let batches = vec![
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(StringArray::from(vec!["b"]))])
+                .unwrap(),
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(StringArray::from(vec!["a"]))])
+                .unwrap(),
+        ];
+        let config: ParquetConfig =
+            serde_json::from_value(json!({ "timeColumn": "time" })).unwrap();
+
+        assert!(config.sort(&schema, &batches).unwrap().is_none());
+    }
+
+    #[rstest]
+    fn level_and_bloom_filters_are_unsupported() {
+        let config: ParquetConfig = serde_json::from_value(json!({
+            "compression": "zstd",
+            "compressionLevel": 3,
+            "bloomFilterColumns": ["host"],
+        }))
+        .unwrap();
+        assert_eq!(
+            config.unsupported(),
+            vec!["compressionLevel", "bloomFilterColumns"]
+        );
+    }
+}
@@ -26,8 +26,12 @@ use datafusion::datasource::listing::ListingTableConfig;
 use datafusion::datasource::listing::ListingTableUrl;
 use datafusion::prelude::*;
 use serde_json::Value;
+use std::path::Path;
 use std::sync::Arc;
 
+use crate::compaction::{self, Manifest};
+use crate::manifest::{self, Filter};
+use crate::metadata::STREAM_INFO;
 use crate::option::CONFIG;
 use crate::storage;
 use crate::storage::ObjectStorage;
@@ -63,10 +67,96 @@ impl Query {
         Ok(validator::query(query, start_time, end_time)?)
     }
 
-    /// Return prefixes, each per day/hour/minutes as necessary
-    pub fn get_prefixes(&self) -> Vec<String> {
-        TimePeriod::new(self.start, self.end, storage::OBJECT_STORE_DATA_GRANULARITY)
-            .generate_prefixes(&self.stream_name)
+    /// Return prefixes, each per day/hour/minutes as necessary, and the
+    /// compacted objects which are read in place of some of these
+    pub fn get_prefixes(&self, manifest: &Manifest) -> Vec<String> {
+        let data_granularity = self.data_granularity();
+        let prefixes = TimePeriod::new(self.start, self.end, data_granularity)
+            .generate_prefixes(&self.stream_name);
+
+        manifest.resolve(&self.stream_name, data_granularity, prefixes)
+    }
+
+    // objects of a stream are in the time slots it was created with
+    fn data_granularity(&self) -> u32 {
+        STREAM_INFO
+            .data_granularity(&self.stream_name)
+            .unwrap_or(storage::LEGACY_DATA_GRANULARITY)
+    }
+
+    /// Objects and prefixes the query reads. Files of an hour with a manifest are
+    /// picked from it, leaving out the ones which can not hold rows of the query.
+    pub async fn plan(
+        &self,
+        storage: &impl ObjectStorage,
+    ) -> Result<Vec<String>, ObjectStorageError> {
+        let compaction = storage.get_compaction(&self.stream_name).await?;
+        let filter = Filter::new(&self.query, &self.stream_name, &self.schema);
+        let root = format!("{}/", self.stream_name);
+
+        // paths of each day, in time order
+        let mut days: Vec<(String, Vec<String>)> = Vec::new();
+        for path in self.get_prefixes(&compaction) {
+            let day = path
+                .strip_prefix(&root)
+                .map_or("", |path| compaction::periods_of(path).0)
+                .to_owned();
+            match days.last_mut() {
+                Some((last, paths)) if *last == day => paths.push(path),
+                _ => days.push((day, vec![path])),
+            }
+        }
+
+        let mut planned = Vec::new();
+        for (day, paths) in days {
+            let hours = manifest::hours_of(&day, self.start, self.end);
+            let Some(day_files) =
+                manifest::day_files(storage, &self.stream_name, &day, hours).await?
+            else {
+                planned.extend(paths);
+                continue;
+            };
+
+            planned.extend(
+                day_files
+                    .files
+                    .into_iter()
+                    .filter(|file| file.overlaps(self.start, self.end) && filter.may_match(file))
+                    .map(|file| file.key),
+            );
+            for hour in day_files.unlisted {
+                planned.extend(self.unlisted_paths(&compaction, &root, &day, &hour, &paths));
+            }
+        }
+
+        Ok(planned)
+    }
+
+    // Paths of an hour without a manifest, out of the paths of its day
+    fn unlisted_paths(
+        &self,
+        compaction: &Manifest,
+        root: &str,
+        day: &str,
+        hour: &str,
+        paths: &[String],
+    ) -> Vec<String> {
+        let in_hour: Vec<String> = paths
+            .iter()
+            .filter(|path| manifest::hour_of(path) == Some(hour))
+            .cloned()
+            .collect();
+
+        // the whole day is read, the hour is on its own
+        if in_hour.is_empty() && paths.iter().any(|path| *path == format!("{}{}", root, day)) {
+            return compaction.resolve(
+                &self.stream_name,
+                self.data_granularity(),
+                vec![format!("{}{}", root, hour)],
+            );
+        }
+
+        in_hour
     }
 
     /// Execute query on object storage(and if necessary on cache as well) with given stream information
@@ -88,6 +178,17 @@ impl Query {
     }
 
     async fn execute_on_cache(&self, results: &mut Vec<RecordBatch>) -> Result<(), ExecuteError> {
+        let cache_path = CONFIG.parseable.get_cache_path(&self.stream_name);
+        self.execute_on_path(&cache_path, results).await
+    }
+
+    // Files written before a column was widened keep its earlier type,
+    // their columns are cast to the stream schema as they are read
+    async fn execute_on_path(
+        &self,
+        path: &Path,
+        results: &mut Vec<RecordBatch>,
+    ) -> Result<(), ExecuteError> {
         let ctx = SessionContext::new();
         let file_format = ParquetFormat::default().with_enable_pruning(true);
 
@@ -99,10 +200,8 @@ impl Query {
             target_partitions: 1,
         };
 
-        let cache_path = CONFIG.parseable.get_cache_path(&self.stream_name);
-
         let table_path = match ListingTableUrl::parse(
-            cache_path.to_str().expect("path should is valid unicode"),
+            path.to_str().expect("path should is valid unicode"),
         ) {
             Ok(table_path) => table_path,
             Err(e) => {
@@ -161,12 +260,22 @@ pub mod error {
 #[cfg(test)]
 mod tests {
     use super::Query;
-    use crate::{alerts::Alerts, metadata::STREAM_INFO};
+    use crate::compaction::Manifest;
+    use crate::event::coercion;
+    use crate::utils;
+    use crate::{alerts::Alerts, metadata::STREAM_INFO, storage::LEGACY_DATA_GRANULARITY};
+    use chrono::Utc;
+    use datafusion::arrow::array::{Array, Float64Array, Int64Array};
     use datafusion::arrow::datatypes::Schema;
     use datafusion::arrow::datatypes::{DataType, Field};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::parquet::arrow::ArrowWriter;
     use rstest::*;
     use serde_json::Value;
+    use std::fs::{self, File};
+    use std::path::Path;
     use std::str::FromStr;
+    use std::sync::Arc;
 
     #[fixture]
     fn schema() -> Schema {
@@ -200,12 +309,17 @@ mod tests {
     #[serial_test::serial]
     fn query_parse_prefix_with_some_schema(#[case] prefix: &str, #[case] right: &[&str]) {
         clear_map();
-        STREAM_INFO.add_stream("stream_name".to_string(), Some(schema()), Alerts::default());
+        STREAM_INFO.add_stream(
+            "stream_name".to_string(),
+            Some(schema()),
+            Alerts::default(),
+            LEGACY_DATA_GRANULARITY,
+        );
 
         let query = Value::from_str(prefix).unwrap();
         let query = Query::parse(query).unwrap();
         assert_eq!(&query.stream_name, "stream_name");
-        let prefixes = query.get_prefixes();
+        let prefixes = query.get_prefixes(&Manifest::default());
         let left = prefixes.iter().map(String::as_str).collect::<Vec<&str>>();
         assert_eq!(left.as_slice(), right);
     }
@@ -222,9 +336,65 @@ mod tests {
     #[serial_test::serial]
     fn query_parse_prefix_with_no_schema(#[case] prefix: &str) {
         clear_map();
-        STREAM_INFO.add_stream("stream_name".to_string(), None, Alerts::default());
+        STREAM_INFO.add_stream(
+            "stream_name".to_string(),
+            None,
+            Alerts::default(),
+            LEGACY_DATA_GRANULARITY,
+        );
 
         let query = Value::from_str(prefix).unwrap();
         assert!(Query::parse(query).is_err());
     }
+
+    fn write_parquet(path: &Path, array: Arc<dyn Array>) {
+        let schema = Schema::new(vec![Field::new("a", array.data_type().clone(), true)]);
+        let record = RecordBatch::try_new(Arc::new(schema), vec![array]).unwrap();
+        let mut writer =
+            ArrowWriter::try_new(File::create(path).unwrap(), record.schema(), None).unwrap();
+        writer.write(&record).unwrap();
+        writer.close().unwrap();
+    }
+
+    // Files written before an integer column was widened are still read, with
+    // their values cast to Float64 alongside those of the later files
+    #[rstest]
+    #[tokio::test]
+    async fn widened_column_reads_older_files() {
+        let dir = std::env::temp_dir().join(format!("parseable-{}", utils::uuid::gen().simple()));
+        fs::create_dir_all(&dir).unwrap();
+        write_parquet(
+            &dir.join("before.parquet"),
+            Arc::new(Int64Array::from(vec![1, 2])),
+        );
+        write_parquet(
+            &dir.join("after.parquet"),
+            Arc::new(Float64Array::from(vec![2.5])),
+        );
+
+        let schema = Schema::new(vec![Field::new("a", DataType::Int64, true)]);
+        let query = Query {
+            query: "SELECT a FROM widened ORDER BY a".to_owned(),
+            stream_name: "widened".to_owned(),
+            schema: Arc::new(coercion::widen(&schema, &["a".to_owned()])),
+            start: Utc::now(),
+            end: Utc::now(),
+        };
+        let mut results = Vec::new();
+        query.execute_on_path(&dir, &mut results).await.unwrap();
+
+        let values: Vec<f64> = results
+            .iter()
+            .flat_map(|record| {
+                let column = record
+                    .column(0)
+                    .as_any()
+                    .downcast_ref::<Float64Array>()
+                    .unwrap();
+                column.values().to_vec()
+            })
+            .collect();
+        assert_eq!(values, [1.0, 2.0, 2.5]);
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
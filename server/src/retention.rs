@@ -0,0 +1,138 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use chrono::{Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::metadata::STREAM_INFO;
+use crate::storage::ObjectStorage;
+
+use self::error::RetentionError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Retention {
+    /// Number of past days data is kept for, besides the current day
+    pub days: u32,
+}
+
+impl Retention {
+    /// Whether the data of a day prefix `date=../` is past the retention
+    pub fn is_expired(&self, day: &str, today: NaiveDate) -> bool {
+        let date = day
+            .strip_prefix("date=")
+            .map(|date| date.trim_end_matches('/'))
+            .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok());
+
+        match date {
+            Some(date) => date < today - Duration::days(self.days as i64),
+            None => false,
+        }
+    }
+}
+
+/// Delete the days of data of every stream which are past its retention
+pub async fn run(storage: &impl ObjectStorage) {
+    let today = Utc::now().naive_utc().date();
+
+    for stream in STREAM_INFO.list_streams() {
+        let Ok(Some(retention)) = STREAM_INFO.retention(&stream) else {
+            continue;
+        };
+        if let Err(e) = apply(storage, &stream, retention, today).await {
+            log::error!("could not apply retention of stream {}. {:?}", stream, e);
+        }
+    }
+}
+
+async fn apply(
+    storage: &impl ObjectStorage,
+    stream_name: &str,
+    retention: Retention,
+    today: NaiveDate,
+) -> Result<(), RetentionError> {
+    for day in storage.list_dates(stream_name).await? {
+        if !retention.is_expired(&day, today) {
+            continue;
+        }
+
+        // the manifest of the day goes with its data
+        let objects = storage
+            .list_objects(&format!("{}/{}", stream_name, day))
+            .await?;
+        let keys: Vec<String> = objects.iter().map(|object| object.key.clone()).collect();
+        let size = objects
+            .iter()
+            .filter(|object| object.key.ends_with(".data.parquet"))
+            .map(|object| object.size)
+            .sum();
+        storage.delete_objects(&keys).await?;
+
+        let mut compaction = storage.get_compaction(stream_name).await?;
+        if compaction.forget(&day) {
+            storage.put_compaction(stream_name, &compaction).await?;
+        }
+
+        let stats = STREAM_INFO.replace_storage_size(stream_name, size, 0)?;
+        if let Err(e) = storage.put_stats(stream_name, &stats).await {
+            log::warn!("Error updating stats to s3 due to error [{}]", e);
+        }
+
+        log::info!(
+            "removed {} objects ({} bytes) of {} from stream {}, past its retention of {} days",
+            keys.len(),
+            size,
+            day,
+            stream_name,
+            retention.days
+        );
+    }
+
+    Ok(())
+}
+
+pub mod error {
+    use crate::metadata::error::stream_info::MetadataError;
+    use crate::storage::ObjectStorageError;
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum RetentionError {
+        #[error("Metadata Error: {0}")]
+        Metadata(#[from] MetadataError),
+        #[error("Object storage Error: {0}")]
+        ObjectStorage(#[from] ObjectStorageError),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use rstest::*;
+
+    use super::Retention;
+
+    #[rstest]
+    #[case("date=2022-10-10/", true)]
+    #[case("date=2022-10-11/", false)]
+    #[case("date=2022-10-18/", false)]
+    #[case(".schema", false)]
+    fn expired_days(#[case] day: &str, #[case] expired: bool) {
+        let today = NaiveDate::from_ymd_opt(2022, 10, 18).unwrap();
+        let retention = Retention { days: 7 };
+        assert_eq!(retention.is_expired(day, today), expired);
+    }
+}
@@ -8,30 +8,33 @@ use aws_sdk_s3::{Client, Credentials, Endpoint, Region};
 use aws_smithy_async::rt::sleep::default_async_sleep;
 use bytes::Bytes;
 use clap::builder::ArgPredicate;
-use datafusion::arrow::datatypes::Schema;
+use datafusion::arrow::datatypes::{Schema, SchemaRef};
 use datafusion::arrow::record_batch::RecordBatch;
 use datafusion::datasource::file_format::parquet::ParquetFormat;
 use datafusion::datasource::listing::{
     ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl,
 };
 use datafusion::datasource::object_store::ObjectStoreRegistry;
+use datafusion::error::DataFusionError;
 use datafusion::execution::runtime_env::{RuntimeConfig, RuntimeEnv};
+use datafusion::physical_plan::SendableRecordBatchStream;
 use datafusion::prelude::{SessionConfig, SessionContext};
 use futures::StreamExt;
 use http::Uri;
 use object_store::aws::AmazonS3Builder;
 use object_store::limit::LimitStore;
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
 use std::fs;
 use std::iter::Iterator;
 use std::sync::Arc;
 
 use crate::alerts::Alerts;
+use crate::compaction::Manifest;
+use crate::manifest;
 use crate::option::{StorageOpt, CONFIG};
 use crate::query::Query;
-use crate::stats::Stats;
-use crate::storage::{LogStream, ObjectStorage, ObjectStorageError};
+use crate::storage::{
+    DataObject, LogStream, LookupObject, ObjectStorage, ObjectStorageError, ObjectStoreFormat,
+};
 
 // Default object storage currently is DO Spaces bucket
 // Any user who starts the Parseable server with default configuration
@@ -45,19 +48,9 @@ const DEFAULT_S3_SECRET_KEY: &str = "minioadmin";
 // max concurrent request allowed for datafusion object store
 const MAX_OBJECT_STORE_REQUESTS: usize = 1000;
 
-#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub struct ObjectStoreFormat {
-    #[serde(rename = "objectstore-format")]
-    pub version: String,
-}
-
-impl ObjectStoreFormat {
-    pub fn new() -> Self {
-        Self {
-            version: "v1".to_string(),
-        }
-    }
-}
+// lookup tables are shared by all the streams, these are kept under a prefix
+// which can not be a stream name
+const LOOKUP_PREFIX: &str = ".lookups";
 
 lazy_static::lazy_static! {
     #[derive(Debug)]
@@ -211,15 +204,21 @@ impl S3 {
         Ok(())
     }
 
-    async fn _create_stream(&self, stream_name: &str, format: Vec<u8>) -> Result<(), AwsSdkError> {
-        // create ./schema empty file in the stream-name prefix
-        // this indicates that the stream has been created
-        // but doesn't have any content yet
+    async fn _create_stream(
+        &self,
+        stream_name: &str,
+        schema: Option<String>,
+        format: Vec<u8>,
+    ) -> Result<(), AwsSdkError> {
+        // create ./schema file in the stream-name prefix
+        // this indicates that the stream has been created.
+        // Schema file is empty unless the schema is declared upfront
         let _resp = self
             .client
             .put_object()
             .bucket(&S3_CONFIG.s3_bucket_name)
             .key(format!("{}/.schema", stream_name))
+            .set_body(schema.map(|schema| schema.into_bytes().into()))
             .send()
             .await?;
         self._put_parseable_config(stream_name, format).await?;
@@ -289,6 +288,52 @@ impl S3 {
         Ok(())
     }
 
+    async fn _put_compaction(&self, stream_name: &str, body: Vec<u8>) -> Result<(), AwsSdkError> {
+        let _resp = self
+            .client
+            .put_object()
+            .bucket(&S3_CONFIG.s3_bucket_name)
+            .key(format!("{}/.compaction.json", stream_name))
+            .body(body.into())
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    // manifest of a period is kept with its data, `period` is
+    // the `date=../` or `date=../hour=../` prefix
+    async fn _put_manifest(
+        &self,
+        stream_name: &str,
+        period: &str,
+        body: Vec<u8>,
+    ) -> Result<(), AwsSdkError> {
+        let _resp = self
+            .client
+            .put_object()
+            .bucket(&S3_CONFIG.s3_bucket_name)
+            .key(format!("{}/{}.manifest.json", stream_name, period))
+            .body(body.into())
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    async fn _get_manifest(&self, stream_name: &str, period: &str) -> Result<Bytes, AwsSdkError> {
+        let resp = self
+            .client
+            .get_object()
+            .bucket(&S3_CONFIG.s3_bucket_name)
+            .key(format!("{}/{}.manifest.json", stream_name, period))
+            .send()
+            .await?;
+        let body = resp.body.collect().await;
+        let body_bytes = body.unwrap().into_bytes();
+        Ok(body_bytes)
+    }
+
     async fn _get_schema(&self, stream_name: &str) -> Result<Bytes, AwsSdkError> {
         self._get(stream_name, "schema").await
     }
@@ -347,6 +392,7 @@ impl S3 {
             .iter()
             .filter_map(CommonPrefix::prefix)
             .filter_map(|name| name.strip_suffix('/'))
+            .filter(|name| !name.starts_with('.'))
             .map(String::from)
             .map(|name| LogStream { name })
             .collect();
@@ -354,6 +400,149 @@ impl S3 {
         Ok(logstreams)
     }
 
+    async fn _put_lookup_table(&self, name: &str, body: Vec<u8>) -> Result<String, AwsSdkError> {
+        let resp = self
+            .client
+            .put_object()
+            .bucket(&S3_CONFIG.s3_bucket_name)
+            .key(format!("{}/{}.csv", LOOKUP_PREFIX, name))
+            .body(body.into())
+            .send()
+            .await?;
+
+        Ok(resp.e_tag().unwrap_or_default().to_string())
+    }
+
+    async fn _get_lookup_table(&self, name: &str) -> Result<Bytes, AwsSdkError> {
+        let resp = self
+            .client
+            .get_object()
+            .bucket(&S3_CONFIG.s3_bucket_name)
+            .key(format!("{}/{}.csv", LOOKUP_PREFIX, name))
+            .send()
+            .await?;
+        let body = resp.body.collect().await;
+        let body_bytes = body.unwrap().into_bytes();
+        Ok(body_bytes)
+    }
+
+    async fn _list_lookup_tables(&self) -> Result<Vec<LookupObject>, AwsSdkError> {
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&S3_CONFIG.s3_bucket_name)
+            .prefix(format!("{}/", LOOKUP_PREFIX))
+            .into_paginator()
+            .send();
+
+        let mut lookups = Vec::new();
+        while let Some(page) = pages.next().await {
+            let page = page?;
+            for obj in page.contents().unwrap_or_default() {
+                let name = obj
+                    .key()
+                    .and_then(|key| key.strip_prefix(LOOKUP_PREFIX))
+                    .and_then(|key| key.strip_prefix('/'))
+                    .and_then(|key| key.strip_suffix(".csv"));
+
+                if let Some(name) = name {
+                    lookups.push(LookupObject {
+                        name: name.to_string(),
+                        version: obj.e_tag().unwrap_or_default().to_string(),
+                    });
+                }
+            }
+        }
+
+        Ok(lookups)
+    }
+
+    // Day prefixes `date=../` of a stream
+    async fn _list_dates(&self, stream_name: &str) -> Result<Vec<String>, AwsSdkError> {
+        let root = format!("{}/", stream_name);
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&S3_CONFIG.s3_bucket_name)
+            .prefix(&root)
+            .delimiter('/')
+            .into_paginator()
+            .send();
+
+        let mut dates = Vec::new();
+        while let Some(page) = pages.next().await {
+            let page = page?;
+            let prefixes = page.common_prefixes().unwrap_or_default();
+            dates.extend(
+                prefixes
+                    .iter()
+                    .filter_map(CommonPrefix::prefix)
+                    .filter_map(|prefix| prefix.strip_prefix(&root))
+                    .filter(|prefix| prefix.starts_with("date="))
+                    .map(String::from),
+            );
+        }
+
+        Ok(dates)
+    }
+
+    async fn _list_objects(&self, prefix: &str) -> Result<Vec<DataObject>, AwsSdkError> {
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&S3_CONFIG.s3_bucket_name)
+            .prefix(prefix)
+            .into_paginator()
+            .send();
+
+        let mut objects = Vec::new();
+        while let Some(page) = pages.next().await {
+            let page = page?;
+            for obj in page.contents().unwrap_or_default() {
+                if let Some(key) = obj.key() {
+                    objects.push(DataObject {
+                        key: key.to_string(),
+                        size: obj.size() as u64,
+                    });
+                }
+            }
+        }
+
+        Ok(objects)
+    }
+
+    // Returns the keys which could not be deleted
+    async fn _delete_objects(&self, keys: &[String]) -> Result<Vec<String>, AwsSdkError> {
+        let mut failed = Vec::new();
+
+        // a delete request takes up to 1000 keys
+        for keys in keys.chunks(1000) {
+            let delete_objects = keys
+                .iter()
+                .map(|key| ObjectIdentifier::builder().key(key).build())
+                .collect();
+            let delete = Delete::builder().set_objects(Some(delete_objects)).build();
+
+            let resp = self
+                .client
+                .delete_objects()
+                .bucket(&S3_CONFIG.s3_bucket_name)
+                .delete(delete)
+                .send()
+                .await?;
+
+            failed.extend(
+                resp.errors()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|error| error.key())
+                    .map(String::from),
+            );
+        }
+
+        Ok(failed)
+    }
+
     async fn _upload_file(&self, key: &str, path: &str) -> Result<(), AwsSdkError> {
         let body = ByteStream::from_path(path).await.unwrap();
         let resp = self
@@ -397,10 +586,15 @@ impl ObjectStorage for S3 {
         Ok(())
     }
 
-    async fn create_stream(&self, stream_name: &str) -> Result<(), ObjectStorageError> {
-        let format = ObjectStoreFormat::new();
+    async fn create_stream(
+        &self,
+        stream_name: &str,
+        schema: Option<&Schema>,
+    ) -> Result<(), ObjectStorageError> {
+        let format = ObjectStoreFormat::new(schema.is_some());
         let body = serde_json::to_vec(&format)?;
-        self._create_stream(stream_name, body).await?;
+        let schema = schema.map(serde_json::to_string).transpose()?;
+        self._create_stream(stream_name, schema, body).await?;
 
         Ok(())
     }
@@ -440,28 +634,65 @@ impl ObjectStorage for S3 {
         }
     }
 
-    async fn get_stats(&self, stream_name: &str) -> Result<Stats, ObjectStorageError> {
-        let parseable_metadata = self._get_parseable_config(stream_name).await?;
-        let parseable_metadata: Value =
-            serde_json::from_slice(&parseable_metadata).expect("parseable config is valid json");
+    async fn get_stream_metadata(
+        &self,
+        stream_name: &str,
+    ) -> Result<ObjectStoreFormat, ObjectStorageError> {
+        let body = self._get_parseable_config(stream_name).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
 
-        let stats = &parseable_metadata["stats"];
+    async fn get_compaction(&self, stream_name: &str) -> Result<Manifest, ObjectStorageError> {
+        match self._get(stream_name, "compaction.json").await {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(AwsSdkError::NoSuchKey(_)) => Ok(Manifest::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn get_manifest(
+        &self,
+        stream_name: &str,
+        period: &str,
+    ) -> Result<Option<manifest::Manifest>, ObjectStorageError> {
+        match self._get_manifest(stream_name, period).await {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(AwsSdkError::NoSuchKey(_)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
 
-        let stats = serde_json::from_value(stats.clone()).unwrap_or_default();
+    async fn put_stream_metadata(
+        &self,
+        stream_name: &str,
+        format: &ObjectStoreFormat,
+    ) -> Result<(), ObjectStorageError> {
+        let body = serde_json::to_vec(format)?;
+        self._put_parseable_config(stream_name, body).await?;
 
-        Ok(stats)
+        Ok(())
     }
 
-    async fn put_stats(&self, stream_name: &str, stats: &Stats) -> Result<(), ObjectStorageError> {
-        let stats = serde_json::to_value(stats).expect("stats are perfectly serializable");
-        let parseable_metadata = self._get_parseable_config(stream_name).await?;
-        let mut parseable_metadata: Value =
-            serde_json::from_slice(&parseable_metadata).expect("parseable config is valid json");
+    async fn put_compaction(
+        &self,
+        stream_name: &str,
+        manifest: &Manifest,
+    ) -> Result<(), ObjectStorageError> {
+        let body = serde_json::to_vec(manifest)?;
+        self._put_compaction(stream_name, body).await?;
 
-        parseable_metadata["stats"] = stats;
+        Ok(())
+    }
+
+    async fn put_manifest(
+        &self,
+        stream_name: &str,
+        period: &str,
+        manifest: &manifest::Manifest,
+    ) -> Result<(), ObjectStorageError> {
+        let body = serde_json::to_vec(manifest)?;
+        self._put_manifest(stream_name, period, body).await?;
 
-        self._put_parseable_config(stream_name, parseable_metadata.to_string().into_bytes())
-            .await?;
         Ok(())
     }
 
@@ -471,12 +702,71 @@ impl ObjectStorage for S3 {
         Ok(streams)
     }
 
+    async fn put_lookup_table(
+        &self,
+        name: &str,
+        body: Vec<u8>,
+    ) -> Result<String, ObjectStorageError> {
+        let version = self._put_lookup_table(name, body).await?;
+
+        Ok(version)
+    }
+
+    async fn get_lookup_table(&self, name: &str) -> Result<Bytes, ObjectStorageError> {
+        let body = self._get_lookup_table(name).await?;
+
+        Ok(body)
+    }
+
+    async fn list_lookup_tables(&self) -> Result<Vec<LookupObject>, ObjectStorageError> {
+        let lookups = self._list_lookup_tables().await?;
+
+        Ok(lookups)
+    }
+
     async fn upload_file(&self, key: &str, path: &str) -> Result<(), ObjectStorageError> {
         self._upload_file(key, path).await?;
 
         Ok(())
     }
 
+    async fn list_dates(&self, stream_name: &str) -> Result<Vec<String>, ObjectStorageError> {
+        let dates = self._list_dates(stream_name).await?;
+
+        Ok(dates)
+    }
+
+    async fn list_objects(&self, prefix: &str) -> Result<Vec<DataObject>, ObjectStorageError> {
+        let objects = self._list_objects(prefix).await?;
+
+        Ok(objects)
+    }
+
+    async fn delete_objects(&self, keys: &[String]) -> Result<(), ObjectStorageError> {
+        let failed = self._delete_objects(keys).await?;
+        if !failed.is_empty() {
+            return Err(ObjectStorageError::UnhandledError(Box::new(
+                DeleteObjectsError(failed),
+            )));
+        }
+
+        Ok(())
+    }
+
+    async fn read_objects(
+        &self,
+        keys: &[String],
+        schema: SchemaRef,
+    ) -> Result<SendableRecordBatchStream, ObjectStorageError> {
+        let ctx =
+            SessionContext::with_config_rt(SessionConfig::default(), Arc::clone(&STORAGE_RUNTIME));
+
+        let table = listing_table(keys, schema)?;
+        let df = ctx.read_table(Arc::new(table))?;
+
+        Ok(df.execute_stream().await?)
+    }
+
     async fn query(
         &self,
         query: &Query,
@@ -485,30 +775,10 @@ impl ObjectStorage for S3 {
         let ctx =
             SessionContext::with_config_rt(SessionConfig::default(), Arc::clone(&STORAGE_RUNTIME));
 
-        // Get all prefix paths and convert them into futures which yeilds ListingTableUrl
-        let prefixes = query
-            .get_prefixes()
-            .into_iter()
-            .map(|prefix| {
-                let path = format!("s3://{}/{}", &S3_CONFIG.s3_bucket_name, prefix);
-                ListingTableUrl::parse(path).unwrap()
-            })
-            .collect();
-
-        let file_format = ParquetFormat::default().with_enable_pruning(true);
-        let listing_options = ListingOptions {
-            file_extension: ".data.parquet".to_string(),
-            format: Arc::new(file_format),
-            table_partition_cols: vec![],
-            collect_stat: true,
-            target_partitions: 1,
-        };
-
-        let config = ListingTableConfig::new_with_multi_paths(prefixes)
-            .with_listing_options(listing_options)
-            .with_schema(Arc::clone(&query.schema));
+        // only the files which can hold rows of the query are read
+        let paths = query.plan(self).await?;
 
-        let table = ListingTable::try_new(config)?;
+        let table = listing_table(&paths, Arc::clone(&query.schema))?;
         ctx.register_table(query.stream_name.as_str(), Arc::new(table))?;
 
         // execute the query and collect results
@@ -519,6 +789,33 @@ impl ObjectStorage for S3 {
     }
 }
 
+// Table of the data objects at the given prefixes or keys, read with the schema of the stream
+fn listing_table(paths: &[String], schema: SchemaRef) -> Result<ListingTable, DataFusionError> {
+    let paths = paths
+        .iter()
+        .map(|path| ListingTableUrl::parse(format!("s3://{}/{}", &S3_CONFIG.s3_bucket_name, path)))
+        .collect::<Result<_, _>>()?;
+
+    let file_format = ParquetFormat::default().with_enable_pruning(true);
+    let listing_options = ListingOptions {
+        file_extension: ".data.parquet".to_string(),
+        format: Arc::new(file_format),
+        table_partition_cols: vec![],
+        collect_stat: true,
+        target_partitions: 1,
+    };
+
+    let config = ListingTableConfig::new_with_multi_paths(paths)
+        .with_listing_options(listing_options)
+        .with_schema(schema);
+
+    ListingTable::try_new(config)
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Could not delete objects {0:?}")]
+struct DeleteObjectsError(Vec<String>);
+
 impl From<AwsSdkError> for ObjectStorageError {
     fn from(error: AwsSdkError) -> Self {
         ObjectStorageError::UnhandledError(Box::new(error))
@@ -0,0 +1,127 @@
+/*
+ * Parseable Server (C) 2022 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use sysinfo::{DiskExt, System, SystemExt};
+
+use crate::option::CONFIG;
+
+/// Interval between checks of the staging disk usage
+pub const DISK_CHECK_INTERVAL: u64 = 5;
+
+// Set once usage of the staging disk crosses the high watermark,
+// cleared only when uploads bring it back under the low watermark
+static UNDER_PRESSURE: AtomicBool = AtomicBool::new(false);
+
+/// Staging disk is too full to accept more events
+pub fn under_pressure() -> bool {
+    UNDER_PRESSURE.load(Ordering::Relaxed)
+}
+
+/// Warn at startup when the usage of the staging disk can not be read,
+/// ingestion is then never paused however full the disk gets
+pub fn check_monitored() {
+    let path = &CONFIG.parseable.local_disk_path;
+    if disk_usage(path).is_none() {
+        log::warn!(
+            "usage of the disk holding the local storage {} can not be read, ingestion is not paused when it fills up",
+            path.display()
+        );
+    }
+}
+
+/// Update the pressure state from the current usage of the staging disk
+pub fn check_disk_usage() {
+    let Some(usage) = disk_usage(&CONFIG.parseable.local_disk_path) else {
+        return;
+    };
+
+    let high = CONFIG.parseable.staging_high_watermark;
+    let low = CONFIG.parseable.staging_low_watermark;
+    let was_under_pressure = under_pressure();
+
+    let pressure = next_state(was_under_pressure, usage, low, high);
+    if pressure != was_under_pressure {
+        if pressure {
+            log::warn!(
+                "staging disk is {:.1}% full, above the high watermark of {}%. Ingestion is paused until uploads bring it under {}%",
+                usage,
+                high,
+                low
+            );
+        } else {
+            log::info!(
+                "staging disk is {:.1}% full, under the low watermark of {}%. Ingestion is resumed",
+                usage,
+                low
+            );
+        }
+        UNDER_PRESSURE.store(pressure, Ordering::Relaxed);
+    }
+}
+
+fn next_state(under_pressure: bool, usage: f64, low: f64, high: f64) -> bool {
+    if under_pressure {
+        usage >= low
+    } else {
+        usage >= high
+    }
+}
+
+// Percentage of the disk holding `path` which is in use
+fn disk_usage(path: &Path) -> Option<f64> {
+    // the directory is created with the first event, until then
+    // its disk is the one of the closest parent which exists
+    let path = path.ancestors().find_map(|path| path.canonicalize().ok())?;
+
+    let mut system = System::new();
+    system.refresh_disks_list();
+
+    // the disk is the one with the most specific mount point containing the path
+    let disk = system
+        .disks()
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())?;
+
+    let total = disk.total_space();
+    if total == 0 {
+        return None;
+    }
+
+    let used = total.saturating_sub(disk.available_space());
+    Some(used as f64 * 100.0 / total as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use super::next_state;
+
+    #[rstest]
+    #[case::below_high(false, 85.0, false)]
+    #[case::above_high(false, 95.0, true)]
+    #[case::between_watermarks(true, 85.0, true)]
+    #[case::below_low(true, 75.0, false)]
+    fn hysteresis(#[case] under_pressure: bool, #[case] usage: f64, #[case] expected: bool) {
+        assert_eq!(next_state(under_pressure, usage, 80.0, 90.0), expected);
+    }
+}
@@ -47,6 +47,14 @@ impl StatsCounter {
     pub fn add_storage_size(&self, size: u64) {
         self.storage_size.fetch_add(size, Ordering::AcqRel);
     }
+
+    pub fn sub_storage_size(&self, size: u64) {
+        let _ =
+            self.storage_size
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |storage_size| {
+                    Some(storage_size.saturating_sub(size))
+                });
+    }
 }
 
 /// Helper struct type created by copying stats values from metadata
@@ -17,36 +17,133 @@
  */
 
 use crate::alerts::Alerts;
+use crate::compaction::{self, Manifest};
+use crate::event::coercion::Coercion;
+use crate::event::dead_letter::DeadLetter;
+use crate::event::dedup::Dedup;
+use crate::event::enrichment::Enrichment;
+use crate::event::explode::Explode;
+use crate::event::guardrails::Guardrails;
+use crate::event::json_schema::JsonSchema;
+use crate::event::labels::Labels;
+use crate::event::lookup::LookupJoin;
+use crate::event::nesting::Nesting;
+use crate::event::sampling::Sampling;
+use crate::event::STREAM_WRITERS;
+use crate::manifest::{self, DataFile};
 use crate::metadata::{LOCK_EXPECT, STREAM_INFO};
 use crate::option::CONFIG;
+use crate::parquet::ParquetConfig;
 use crate::query::Query;
+use crate::retention::Retention;
 use crate::stats::Stats;
 use crate::utils;
 
 use async_trait::async_trait;
+use bytes::Bytes;
 use chrono::{NaiveDateTime, Timelike, Utc};
-use datafusion::arrow::datatypes::Schema;
+use datafusion::arrow::datatypes::{Schema, SchemaRef};
 use datafusion::arrow::error::ArrowError;
 use datafusion::arrow::ipc::reader::StreamReader;
 use datafusion::arrow::record_batch::RecordBatch;
 use datafusion::parquet::arrow::ArrowWriter;
 use datafusion::parquet::errors::ParquetError;
-use datafusion::parquet::file::properties::WriterProperties;
-use serde::Serialize;
+use datafusion::physical_plan::SendableRecordBatchStream;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::task;
 
 use std::collections::HashMap;
-use std::fmt::Debug;
+use std::fmt::{Debug, Display};
 use std::fs::{self, File};
 use std::iter::Iterator;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-/// local sync interval to move data.records to /tmp dir of that stream.
-/// 60 sec is a reasonable value.
-pub const LOCAL_SYNC_INTERVAL: u64 = 60;
+/// duration used to configure prefix in s3 and local disk structure, for the
+/// streams created before it was configurable. 1 min.
+pub const LEGACY_DATA_GRANULARITY: u32 = 1;
 
-/// duration used to configure prefix in s3 and local disk structure
-/// used for storage. Defaults to 1 min.
-pub const OBJECT_STORE_DATA_GRANULARITY: u32 = (LOCAL_SYNC_INTERVAL as u32) / 60;
+lazy_static::lazy_static! {
+    // serializes the read-modify-write of stream metadata objects
+    static ref STREAM_METADATA_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+/// Contents of the `.parseable.json` of a stream, which holds the format of
+/// the stream along with all of its settings and stats.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectStoreFormat {
+    #[serde(rename = "objectstore-format")]
+    pub version: String,
+    #[serde(rename = "static-schema", default)]
+    pub static_schema: bool,
+    #[serde(rename = "data-granularity", default = "legacy_data_granularity")]
+    pub data_granularity: u32,
+    #[serde(default)]
+    pub stats: Stats,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sampling: Option<Sampling>,
+    #[serde(default)]
+    pub coercion: Coercion,
+    #[serde(
+        rename = "json-schema",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub json_schema: Option<JsonSchema>,
+    #[serde(default)]
+    pub nesting: Nesting,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explode: Option<Explode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guardrails: Option<Guardrails>,
+    #[serde(default)]
+    pub labels: Labels,
+    #[serde(default)]
+    pub enrichment: Enrichment,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lookups: Vec<LookupJoin>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dedup: Option<Dedup>,
+    #[serde(
+        rename = "dead-letter",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub dead_letter: Option<DeadLetter>,
+    #[serde(default)]
+    pub parquet: ParquetConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention: Option<Retention>,
+}
+
+fn legacy_data_granularity() -> u32 {
+    LEGACY_DATA_GRANULARITY
+}
+
+impl ObjectStoreFormat {
+    pub fn new(static_schema: bool) -> Self {
+        Self {
+            version: "v1".to_string(),
+            static_schema,
+            data_granularity: CONFIG.parseable.data_granularity,
+            stats: Stats::default(),
+            sampling: None,
+            coercion: Coercion::default(),
+            json_schema: None,
+            nesting: Nesting::default(),
+            explode: None,
+            guardrails: None,
+            labels: Labels::default(),
+            enrichment: Enrichment::default(),
+            lookups: Vec::new(),
+            dedup: None,
+            dead_letter: None,
+            parquet: ParquetConfig::default(),
+            retention: None,
+        }
+    }
+}
 
 #[async_trait]
 pub trait ObjectStorage: Sync + 'static {
@@ -57,7 +154,11 @@ pub trait ObjectStorage: Sync + 'static {
         stream_name: String,
         schema: &Schema,
     ) -> Result<(), ObjectStorageError>;
-    async fn create_stream(&self, stream_name: &str) -> Result<(), ObjectStorageError>;
+    async fn create_stream(
+        &self,
+        stream_name: &str,
+        schema: Option<&Schema>,
+    ) -> Result<(), ObjectStorageError>;
     async fn delete_stream(&self, stream_name: &str) -> Result<(), ObjectStorageError>;
 
     async fn put_alerts(
@@ -65,75 +166,188 @@ pub trait ObjectStorage: Sync + 'static {
         stream_name: &str,
         alerts: &Alerts,
     ) -> Result<(), ObjectStorageError>;
-    async fn put_stats(&self, stream_name: &str, stats: &Stats) -> Result<(), ObjectStorageError>;
+    async fn put_stream_metadata(
+        &self,
+        stream_name: &str,
+        format: &ObjectStoreFormat,
+    ) -> Result<(), ObjectStorageError>;
+    async fn put_compaction(
+        &self,
+        stream_name: &str,
+        manifest: &Manifest,
+    ) -> Result<(), ObjectStorageError>;
+    async fn put_manifest(
+        &self,
+        stream_name: &str,
+        period: &str,
+        manifest: &manifest::Manifest,
+    ) -> Result<(), ObjectStorageError>;
     async fn get_schema(&self, stream_name: &str) -> Result<Option<Schema>, ObjectStorageError>;
     async fn get_alerts(&self, stream_name: &str) -> Result<Alerts, ObjectStorageError>;
-    async fn get_stats(&self, stream_name: &str) -> Result<Stats, ObjectStorageError>;
+    async fn get_stream_metadata(
+        &self,
+        stream_name: &str,
+    ) -> Result<ObjectStoreFormat, ObjectStorageError>;
+    async fn get_compaction(&self, stream_name: &str) -> Result<Manifest, ObjectStorageError>;
+    async fn get_manifest(
+        &self,
+        stream_name: &str,
+        period: &str,
+    ) -> Result<Option<manifest::Manifest>, ObjectStorageError>;
     async fn list_streams(&self) -> Result<Vec<LogStream>, ObjectStorageError>;
+    async fn put_lookup_table(
+        &self,
+        name: &str,
+        body: Vec<u8>,
+    ) -> Result<String, ObjectStorageError>;
+    async fn get_lookup_table(&self, name: &str) -> Result<Bytes, ObjectStorageError>;
+    async fn list_lookup_tables(&self) -> Result<Vec<LookupObject>, ObjectStorageError>;
     async fn upload_file(&self, key: &str, path: &str) -> Result<(), ObjectStorageError>;
+    async fn list_dates(&self, stream_name: &str) -> Result<Vec<String>, ObjectStorageError>;
+    async fn list_objects(&self, prefix: &str) -> Result<Vec<DataObject>, ObjectStorageError>;
+    async fn delete_objects(&self, keys: &[String]) -> Result<(), ObjectStorageError>;
+    async fn read_objects(
+        &self,
+        keys: &[String],
+        schema: SchemaRef,
+    ) -> Result<SendableRecordBatchStream, ObjectStorageError>;
     async fn query(
         &self,
         query: &Query,
         results: &mut Vec<RecordBatch>,
     ) -> Result<(), ObjectStorageError>;
 
+    /// Change the `.parseable.json` of a stream. Every setting of a stream is kept in
+    /// this one object, so changes are made one at a time and do not undo each other.
+    async fn update_stream_metadata<F>(
+        &self,
+        stream_name: &str,
+        update: F,
+    ) -> Result<(), ObjectStorageError>
+    where
+        F: FnOnce(&mut ObjectStoreFormat) + Send,
+    {
+        let _guard = STREAM_METADATA_LOCK.lock().await;
+        let mut format = self.get_stream_metadata(stream_name).await?;
+        update(&mut format);
+        self.put_stream_metadata(stream_name, &format).await
+    }
+
+    async fn put_stats(&self, stream_name: &str, stats: &Stats) -> Result<(), ObjectStorageError> {
+        let stats = *stats;
+        self.update_stream_metadata(stream_name, |format| format.stats = stats)
+            .await
+    }
+
     async fn s3_sync(&self) -> Result<(), MoveDataError> {
         if !Path::new(&CONFIG.parseable.local_disk_path).exists() {
             return Ok(());
         }
 
+        let started = Instant::now();
         let streams = STREAM_INFO.list_streams();
+        let mut round = SyncRound::default();
+
+        // conversion is blocking file io and cpu bound, it runs on the blocking
+        // pool with one stream per core at a time
+        let mut conversions = stream::iter(streams.iter().map(|stream| {
+            // walk dir, find all closed .arrows files and convert to parquet
+            let files = StorageDir::new(stream).arrow_files();
+            let config = STREAM_INFO.parquet(stream).unwrap_or_default();
+            task::spawn_blocking(move || {
+                files
+                    .iter()
+                    .filter(|file| match arrows_to_parquet(file, &config) {
+                        Ok(()) => false,
+                        Err(e) => {
+                            log::error!(
+                                "could not convert local log file {}. {:?}",
+                                file.display(),
+                                e
+                            );
+                            true
+                        }
+                    })
+                    .count()
+            })
+        }))
+        .buffer_unordered(num_cpus::get());
+
+        while let Some(failed) = conversions.next().await {
+            round.failures += failed.unwrap_or_else(|e| {
+                log::error!("local log file conversion task failed. {:?}", e);
+                1
+            });
+        }
 
-        let mut stream_stats = HashMap::new();
-
-        for stream in &streams {
-            // get dir
-            let dir = StorageDir::new(stream);
-            // walk dir, find all .arrows files and convert to parquet
-            for file in dir.arrow_files() {
-                let arrow_file = File::open(&file).map_err(|_| MoveDataError::Open)?;
-                let reader = StreamReader::try_new(arrow_file, None)?;
-                let schema = reader.schema();
-                let records = reader.filter_map(|record| match record {
-                    Ok(record) => Some(record),
-                    Err(e) => {
-                        log::warn!("warning from arrow stream {:?}", e);
-                        None
-                    }
-                });
-
-                let mut parquet_path = file.clone();
-                parquet_path.set_extension("parquet");
-
-                let parquet_file =
-                    fs::File::create(&parquet_path).map_err(|_| MoveDataError::Create)?;
-                let props = WriterProperties::builder().build();
-                let mut writer = ArrowWriter::try_new(parquet_file, schema, Some(props))?;
+        let files = streams.iter().flat_map(|stream| {
+            StorageDir::new(stream)
+                .parquet_files()
+                .into_iter()
+                .map(move |file| (stream, file))
+        });
 
-                for ref record in records {
-                    writer.write(record)?;
+        let mut uploads = stream::iter(files.map(|(stream, file)| async move {
+            let filename = file
+                .file_name()
+                .expect("only parquet files are returned by iterator")
+                .to_str()
+                .expect("filename is valid string");
+            let file_suffix = str::replacen(filename, ".", "/", 3);
+            let s3_path = format!("{}/{}", stream, file_suffix);
+            let (day, hour) = compaction::periods_of(&file_suffix);
+            let period = hour.unwrap_or(day).to_owned();
+
+            let uploaded = match DataFile::from_parquet(s3_path.clone(), &file) {
+                Ok(entry) => self
+                    .upload_file(&s3_path, file.to_str().unwrap())
+                    .await
+                    .map(|()| entry)
+                    .map_err(MoveDataError::from),
+                Err(e) => Err(e.into()),
+            };
+            (stream, period, file, uploaded)
+        }))
+        .buffer_unordered(CONFIG.parseable.upload_concurrency);
+
+        let mut uploaded_files: HashMap<(&String, String), Vec<(PathBuf, DataFile)>> =
+            HashMap::new();
+
+        while let Some((stream, period, file, uploaded)) = uploads.next().await {
+            match uploaded {
+                Ok(entry) => uploaded_files
+                    .entry((stream, period))
+                    .or_default()
+                    .push((file, entry)),
+                Err(e) => {
+                    // the file is kept, it is uploaded again with the next sync
+                    log::error!("could not upload {}. {:?}", file.display(), e);
+                    round.failures += 1;
                 }
-
-                writer.close()?;
-
-                fs::remove_file(file).map_err(|_| MoveDataError::Delete)?;
             }
+        }
 
-            for file in dir.parquet_files() {
-                let filename = file
-                    .file_name()
-                    .expect("only parquet files are returned by iterator")
-                    .to_str()
-                    .expect("filename is valid string");
-                let file_suffix = str::replacen(filename, ".", "/", 3);
-                let s3_path = format!("{}/{}", stream, file_suffix);
+        let mut stream_stats = HashMap::new();
 
-                let _put_parquet_file = self.upload_file(&s3_path, file.to_str().unwrap()).await?;
+        // a file is read by queries once it is in the manifest of its hour
+        for ((stream, period), files) in uploaded_files {
+            let entries = files.iter().map(|(_, entry)| entry.clone()).collect();
+            if let Err(e) = manifest::add_files(self, stream, &period, entries).await {
+                // the files are kept, these are uploaded and added again with the next sync
+                log::error!(
+                    "could not update manifest of {}/{}. {:?}",
+                    stream,
+                    period,
+                    e
+                );
+                round.failures += files.len();
+                continue;
+            }
 
-                stream_stats
-                    .entry(stream)
-                    .and_modify(|size| *size += file.metadata().map_or(0, |meta| meta.len()))
-                    .or_insert_with(|| file.metadata().map_or(0, |meta| meta.len()));
+            for (file, entry) in files {
+                round.files += 1;
+                round.bytes += entry.size;
+                *stream_stats.entry(stream).or_insert(0) += entry.size;
 
                 if let Err(e) = fs::remove_file(&file) {
                     log::error!(
@@ -162,65 +376,212 @@ pub trait ObjectStorage: Sync + 'static {
             }
         }
 
+        round.duration = started.elapsed();
+        if round.failures > 0 {
+            log::warn!("{}", round);
+        } else {
+            log::info!("{}", round);
+        }
+
         Ok(())
     }
 }
 
+/// Outcome of one sync of local data with object storage
+#[derive(Debug, Default)]
+struct SyncRound {
+    files: usize,
+    bytes: u64,
+    failures: usize,
+    duration: Duration,
+}
+
+impl Display for SyncRound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "object store sync uploaded {} files ({} bytes) in {:?} with {} failures",
+            self.files, self.bytes, self.duration, self.failures
+        )
+    }
+}
+
+/// Convert the log files left behind by a previous run of the server. None of these
+/// has a writer yet, so this includes the file of the current minute, which new events
+/// could otherwise be appended to after a truncated batch.
+pub fn recover_local_data() {
+    recover_dirs(STREAM_INFO.list_streams().iter().map(|stream| {
+        let config = STREAM_INFO.parquet(stream).unwrap_or_default();
+        (StorageDir::new(stream), config)
+    }))
+}
+
+// A file which can not be converted is moved aside, the files after it
+// and the ones of the other streams are still recovered
+fn recover_dirs(dirs: impl Iterator<Item = (StorageDir, ParquetConfig)>) {
+    for (dir, config) in dirs {
+        for file in dir.all_arrow_files() {
+            log::info!("recovering local log file {}", file.display());
+            if let Err(e) = arrows_to_parquet(&file, &config) {
+                log::error!(
+                    "could not recover local log file {}. {:?}",
+                    file.display(),
+                    e
+                );
+                let mut parquet_path = file.clone();
+                parquet_path.set_extension("parquet");
+                let _ = fs::remove_file(parquet_path);
+                if let Err(e) = quarantine(&file) {
+                    log::error!("{:?}", e);
+                }
+            }
+        }
+    }
+}
+
+// Keep a local log file aside under the .corrupt extension
+fn quarantine(file: &Path) -> Result<(), MoveDataError> {
+    let mut corrupt_path = file.to_path_buf();
+    corrupt_path.set_extension("corrupt");
+    log::error!(
+        "moving local log file {} to {}",
+        file.display(),
+        corrupt_path.display()
+    );
+    fs::rename(file, corrupt_path).map_err(|_| MoveDataError::Quarantine)
+}
+
+// Convert a local log file into a parquet file next to it, with the rows sorted as set
+// for the stream. Batches after the first unreadable one are dropped, which is where
+// a crash leaves a file truncated.
+fn arrows_to_parquet(file: &Path, config: &ParquetConfig) -> Result<(), MoveDataError> {
+    let arrow_file = File::open(file).map_err(|_| MoveDataError::Open)?;
+    let reader = match StreamReader::try_new(arrow_file, None) {
+        Ok(reader) => reader,
+        Err(e) => {
+            // nothing can be salvaged without the schema,
+            // the file is kept aside so that it is not retried on every sync
+            log::error!("could not read local log file {}. {:?}", file.display(), e);
+            return quarantine(file);
+        }
+    };
+    let schema = reader.schema();
+
+    let mut parquet_path = file.to_path_buf();
+    parquet_path.set_extension("parquet");
+
+    let mut batches = Vec::new();
+    for record in reader {
+        match record {
+            Ok(record) => batches.push(record),
+            Err(e) => {
+                log::warn!(
+                    "local log file {} is truncated, recovered {} batches before it. {:?}",
+                    file.display(),
+                    batches.len(),
+                    e
+                );
+                break;
+            }
+        }
+    }
+
+    // written as it is when the rows can not be sorted, rather than never uploaded
+    let sorted = config.sort(&schema, &batches).unwrap_or_else(|e| {
+        log::warn!("could not sort local log file {}. {:?}", file.display(), e);
+        None
+    });
+    let batches = sorted.map_or(batches, |sorted| vec![sorted]);
+
+    let parquet_file = fs::File::create(&parquet_path).map_err(|_| MoveDataError::Create)?;
+    let props = config.writer_properties(&schema);
+    let mut writer = ArrowWriter::try_new(parquet_file, schema, Some(props))?;
+    for record in &batches {
+        writer.write(record)?;
+    }
+
+    writer.close()?;
+
+    fs::remove_file(file).map_err(|_| MoveDataError::Delete)
+}
+
 #[derive(Serialize)]
 pub struct LogStream {
     pub name: String,
 }
 
 #[derive(Debug)]
+pub struct LookupObject {
+    pub name: String,
+    /// Changes every time the table is replaced
+    pub version: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct DataObject {
+    pub key: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone)]
 pub struct StorageDir {
     pub data_path: PathBuf,
+    stream_name: String,
 }
 
 impl StorageDir {
     pub fn new(stream_name: &str) -> Self {
         let data_path = CONFIG.parseable.local_stream_data_path(stream_name);
+        Self::with_data_path(stream_name, data_path)
+    }
 
-        Self { data_path }
+    pub fn with_data_path(stream_name: &str, data_path: PathBuf) -> Self {
+        Self {
+            data_path,
+            stream_name: stream_name.to_owned(),
+        }
     }
 
-    fn filename_by_time(time: NaiveDateTime) -> String {
+    fn slot_by_time(time: NaiveDateTime, data_granularity: u32) -> String {
         let uri = utils::date_to_prefix(time.date())
             + &utils::hour_to_prefix(time.hour())
-            + &utils::minute_to_prefix(time.minute(), OBJECT_STORE_DATA_GRANULARITY).unwrap();
-        let local_uri = str::replace(&uri, "/", ".");
-        let hostname = utils::hostname_unchecked();
-        format!("{}{}.data.arrows", local_uri, hostname)
+            + &utils::minute_to_prefix(time.minute(), data_granularity).unwrap();
+        str::replace(&uri, "/", ".")
     }
 
-    fn filename_by_current_time() -> String {
-        let datetime = Utc::now();
-        Self::filename_by_time(datetime.naive_utc())
+    /// Time slot the events arriving now belong to, every event
+    /// of a local log file is from the slot in its name
+    pub fn slot_by_current_time(&self, data_granularity: u32) -> String {
+        Self::slot_by_time(Utc::now().naive_utc(), data_granularity)
     }
 
-    pub fn path_by_current_time(&self) -> PathBuf {
-        self.data_path.join(Self::filename_by_current_time())
+    /// Path of a new log file for `slot`. A slot can have many files, each of
+    /// these has a unique id so that none overwrites another once uploaded.
+    pub fn path_for_slot(&self, slot: &str) -> PathBuf {
+        let hostname = utils::hostname_unchecked();
+        let id = utils::uuid::gen().simple();
+        self.data_path
+            .join(format!("{}{}.{}.data.arrows", slot, hostname, id))
     }
 
-    pub fn arrow_files(&self) -> Vec<PathBuf> {
+    pub fn all_arrow_files(&self) -> Vec<PathBuf> {
         let Ok(dir) = self.data_path
             .read_dir() else { return vec![] };
 
-        let mut paths: Vec<PathBuf> = dir
-            .flatten()
+        dir.flatten()
             .map(|file| file.path())
             .filter(|file| file.extension().map_or(false, |ext| ext.eq("arrows")))
-            .collect();
+            .collect()
+    }
 
-        // Do not include file which is being written to
-        let hot_file = self.path_by_current_time();
-        let hot_filename = hot_file.file_name().expect("is a not none filename");
+    pub fn arrow_files(&self) -> Vec<PathBuf> {
+        let mut paths = self.all_arrow_files();
 
-        paths.retain(|file| {
-            !file
-                .file_name()
-                .expect("is a not none filename")
-                .eq(hot_filename)
-        });
+        // Do not include file which is being written to. Listed before this is looked
+        // up, a file created in between is either not listed or found open here.
+        if let Ok(Some(hot_file)) = STREAM_WRITERS::open_file(&self.stream_name) {
+            paths.retain(|file| file != &hot_file);
+        }
 
         paths
     }
@@ -250,6 +611,8 @@ pub enum MoveDataError {
     Create,
     #[error("Could not delete temp arrow file")]
     Delete,
+    #[error("Could not move unreadable arrow file aside")]
+    Quarantine,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -267,3 +630,188 @@ pub enum ObjectStorageError {
     #[error("Authentication Error: {0}")]
     AuthenticationError(Box<dyn std::error::Error + Send + 'static>),
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::ipc::writer::StreamWriter;
+    use datafusion::arrow::json::reader::{
+        infer_json_schema_from_iterator, Decoder, DecoderOptions,
+    };
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::parquet::file::reader::{FileReader, SerializedFileReader};
+    use datafusion::prelude::{ParquetReadOptions, SessionContext};
+    use rstest::*;
+    use serde_json::{json, Value};
+
+    use super::{arrows_to_parquet, recover_dirs, StorageDir};
+    use crate::event::nesting::{Nesting, NestingMode};
+    use crate::parquet::ParquetConfig;
+    use crate::utils;
+
+    #[fixture]
+    fn dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("parseable-{}", utils::uuid::gen().simple()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_arrows(path: &PathBuf, records: &[RecordBatch]) {
+        let mut writer =
+            StreamWriter::try_new(File::create(path).unwrap(), &records[0].schema()).unwrap();
+        for record in records {
+            writer.write(record).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn record(values: Vec<i64>) -> RecordBatch {
+        let schema = Schema::new(vec![Field::new("a", DataType::Int64, true)]);
+        RecordBatch::try_new(Arc::new(schema), vec![Arc::new(Int64Array::from(values))]).unwrap()
+    }
+
+    // A crash leaves the end of a log file unwritten, the batches before it are kept
+    #[rstest]
+    fn truncated_file_is_salvaged(dir: PathBuf) {
+        let path = dir.join("truncated.data.arrows");
+        write_arrows(&path, &[record(vec![1, 2]), record(vec![3, 4, 5])]);
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        // the end of stream marker and the end of the last batch
+        let len = file.metadata().unwrap().len();
+        file.set_len(len - 24).unwrap();
+        drop(file);
+
+        arrows_to_parquet(&path, &ParquetConfig::default()).unwrap();
+
+        assert!(!path.exists());
+        let parquet = File::open(dir.join("truncated.data.parquet")).unwrap();
+        let reader = SerializedFileReader::new(parquet).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    // A file without a readable schema is kept aside, it is not retried on every sync
+    #[rstest]
+    fn unreadable_file_is_quarantined(dir: PathBuf) {
+        let path = dir.join("unreadable.data.arrows");
+        fs::write(&path, b"not an arrow ipc stream").unwrap();
+
+        arrows_to_parquet(&path, &ParquetConfig::default()).unwrap();
+
+        assert!(!path.exists());
+        assert!(dir.join("unreadable.data.corrupt").exists());
+        assert!(!dir.join("unreadable.data.parquet").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    // A file which fails to convert is moved aside and does not stop the recovery
+    // of the other streams
+    #[rstest]
+    fn recovery_continues_past_failed_file(dir: PathBuf) {
+        let failing = StorageDir::with_data_path("failing", dir.join("failing"));
+        let healthy = StorageDir::with_data_path("healthy", dir.join("healthy"));
+        fs::create_dir_all(&failing.data_path).unwrap();
+        fs::create_dir_all(&healthy.data_path).unwrap();
+
+        let failed = failing.data_path.join("failed.data.arrows");
+        write_arrows(&failed, &[record(vec![1, 2])]);
+        // the parquet file can not be created where a directory is
+        fs::create_dir(failing.data_path.join("failed.data.parquet")).unwrap();
+        let recovered = healthy.data_path.join("recovered.data.arrows");
+        write_arrows(&recovered, &[record(vec![3])]);
+
+        recover_dirs(
+            [failing.clone(), healthy.clone()]
+                .into_iter()
+                .map(|dir| (dir, ParquetConfig::default())),
+        );
+
+        assert!(!failed.exists());
+        assert!(failing.data_path.join("failed.data.corrupt").exists());
+        assert!(!recovered.exists());
+        assert!(healthy.data_path.join("recovered.data.parquet").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    // Nested events go through the local log file and its parquet conversion,
+    // and their List and Struct columns can be queried
+    #[rstest]
+    #[tokio::test]
+    async fn nested_columns_are_queried(dir: PathBuf) {
+        let nesting = Nesting {
+            mode: NestingMode::Nested,
+            ..Nesting::default()
+        };
+        let events: Vec<Value> = [
+            json!({"host": "a", "tags": ["x", "y"], "request": {"method": "GET", "path": "/"}}),
+            json!({"host": "b", "tags": ["z"], "request": {"method": "POST", "path": "/q"}}),
+        ]
+        .iter()
+        .map(|event| nesting.apply(event).unwrap())
+        .collect();
+        let schema = infer_json_schema_from_iterator(events.iter().cloned().map(Ok)).unwrap();
+        let record = Decoder::new(Arc::new(schema), DecoderOptions::new())
+            .next_batch(&mut events.into_iter().map(Ok))
+            .unwrap()
+            .unwrap();
+
+        let path = dir.join("nested.data.arrows");
+        write_arrows(&path, &[record]);
+        arrows_to_parquet(&path, &ParquetConfig::default()).unwrap();
+        assert!(!path.exists());
+
+        let ctx = SessionContext::new();
+        let parquet_path = dir.join("nested.data.parquet");
+        ctx.register_parquet(
+            "nested",
+            parquet_path.to_str().unwrap(),
+            ParquetReadOptions::default(),
+        )
+        .await
+        .unwrap();
+
+        let table = ctx.table("nested").unwrap();
+        let schema = table.schema();
+        assert!(matches!(
+            schema
+                .field_with_unqualified_name("tags")
+                .unwrap()
+                .data_type(),
+            DataType::List(_)
+        ));
+        assert!(matches!(
+            schema
+                .field_with_unqualified_name("request")
+                .unwrap()
+                .data_type(),
+            DataType::Struct(_)
+        ));
+
+        let batches = ctx
+            .sql("SELECT host FROM nested WHERE request['method'] = 'POST'")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let hosts: Vec<&str> = batches
+            .iter()
+            .flat_map(|batch| {
+                let hosts = batch
+                    .column(0)
+                    .as_any()
+                    .downcast_ref::<StringArray>()
+                    .unwrap();
+                (0..hosts.len()).map(move |row| hosts.value(row))
+            })
+            .collect();
+        assert_eq!(hosts, ["b"]);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
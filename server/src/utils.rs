@@ -16,33 +16,33 @@
  *
  */
 
-use actix_web::web;
 use chrono::{DateTime, NaiveDate, Timelike, Utc};
-use serde_json::{json, Value};
-use std::collections::HashMap;
+use serde_json::{json, Map, Value};
 
-pub fn flatten_json_body(body: web::Json<serde_json::Value>) -> Result<String, serde_json::Error> {
+pub fn flatten_json_body(body: &serde_json::Value) -> Result<Value, FlattenError> {
     let mut flat_value: Value = json!({});
-    flatten_json::flatten(&body, &mut flat_value, None, true, Some("_")).unwrap();
-    let flattened = serde_json::to_string(&flat_value)?;
-
-    Ok(flattened)
+    flatten_json::flatten(body, &mut flat_value, None, true, Some("_"))
+        .map_err(|e| FlattenError(e.to_string()))?;
+    Ok(flat_value)
 }
 
-pub fn merge(value: Value, fields: HashMap<String, String>) -> Value {
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to flatten event: {0}")]
+pub struct FlattenError(String);
+
+// String fields are appended to an existing string field of the same name,
+// other fields replace it
+pub fn merge(value: Value, fields: &Map<String, Value>) -> Value {
     match value {
         Value::Object(mut m) => {
             for (k, v) in fields {
-                match m.get_mut(&k) {
-                    Some(val) => {
-                        let mut final_val = String::new();
-                        final_val.push_str(val.as_str().unwrap());
-                        final_val.push(',');
-                        final_val.push_str(&v);
-                        *val = Value::String(final_val);
+                match (m.get_mut(k), v) {
+                    (Some(Value::String(val)), Value::String(v)) => {
+                        val.push(',');
+                        val.push_str(v);
                     }
-                    None => {
-                        m.insert(k, Value::String(v));
+                    _ => {
+                        m.insert(k.to_owned(), v.clone());
                     }
                 }
             }
@@ -53,27 +53,46 @@ pub fn merge(value: Value, fields: HashMap<String, String>) -> Value {
 }
 
 pub mod header_parsing {
-    const MAX_HEADERS_ALLOWED: usize = 10;
     use actix_web::{HttpRequest, HttpResponse, ResponseError};
 
-    pub fn collect_labelled_headers(
+    /// Collect `key`, `value` pairs of the headers with the given prefix
+    pub fn collect_labels(
         req: &HttpRequest,
         prefix: &str,
-        kv_separator: char,
-    ) -> Result<String, ParseHeaderError> {
+        max_headers: usize,
+    ) -> Result<Vec<(String, String)>, ParseHeaderError> {
         // filter out headers which has right prefix label and convert them into str;
         let headers = req.headers().iter().filter_map(|(key, value)| {
             let key = key.as_str().strip_prefix(prefix)?;
             Some((key, value))
         });
 
-        let mut labels: Vec<String> = Vec::new();
+        let mut labels: Vec<(String, String)> = Vec::new();
 
         for (key, value) in headers {
             let value = value.to_str().map_err(|_| ParseHeaderError::InvalidValue)?;
             if key.is_empty() {
                 return Err(ParseHeaderError::Emptykey);
             }
+
+            labels.push((key.to_owned(), value.to_owned()));
+        }
+
+        if labels.len() > max_headers {
+            return Err(ParseHeaderError::MaxHeadersLimitExceeded(max_headers));
+        }
+
+        Ok(labels)
+    }
+
+    /// Join labels into a single string of `key=value` pairs
+    pub fn join_labels(
+        labels: &[(String, String)],
+        kv_separator: char,
+    ) -> Result<String, ParseHeaderError> {
+        let mut joined: Vec<String> = Vec::new();
+
+        for (key, value) in labels {
             if key.contains(kv_separator) {
                 return Err(ParseHeaderError::SeperatorInKey(kv_separator));
             }
@@ -81,20 +100,16 @@ pub mod header_parsing {
                 return Err(ParseHeaderError::SeperatorInValue(kv_separator));
             }
 
-            labels.push(format!("{}={}", key, value));
-        }
-
-        if labels.len() > MAX_HEADERS_ALLOWED {
-            return Err(ParseHeaderError::MaxHeadersLimitExceeded);
+            joined.push(format!("{}={}", key, value));
         }
 
-        Ok(labels.join(&kv_separator.to_string()))
+        Ok(joined.join(&kv_separator.to_string()))
     }
 
     #[derive(Debug, thiserror::Error)]
     pub enum ParseHeaderError {
-        #[error("Too many headers received. Limit is of 5 headers")]
-        MaxHeadersLimitExceeded,
+        #[error("Too many headers received. Limit is of {0} headers")]
+        MaxHeadersLimitExceeded(usize),
         #[error("A value passed in header is not formattable to plain visible ASCII")]
         InvalidValue,
         #[error("Invalid Key was passed which terminated just after the end of prefix")]
@@ -116,7 +131,6 @@ pub mod header_parsing {
     }
 }
 
-#[allow(dead_code)]
 pub fn hostname() -> Option<String> {
     hostname::get()
         .ok()
@@ -458,4 +472,41 @@ mod tests {
         let left = prefixes.iter().map(String::as_str).collect::<Vec<&str>>();
         assert_eq!(left.as_slice(), right);
     }
+
+    #[rstest]
+    #[case::five_minutes(
+        5, "2022-06-11T16:03:00+00:00", "2022-06-11T16:17:00+00:00",
+        &[
+            "stream_name/date=2022-06-11/hour=16/minute=00-04/",
+            "stream_name/date=2022-06-11/hour=16/minute=05-09/",
+            "stream_name/date=2022-06-11/hour=16/minute=10-14/",
+            "stream_name/date=2022-06-11/hour=16/minute=15-19/"
+        ]
+    )]
+    #[case::five_minutes_across_hours(
+        5, "2022-06-11T16:57:00+00:00", "2022-06-11T17:01:00+00:00",
+        &[
+            "stream_name/date=2022-06-11/hour=16/minute=55-59/",
+            "stream_name/date=2022-06-11/hour=17/minute=00-04/"
+        ]
+    )]
+    #[case::hour(
+        60, "2022-06-11T16:03:00+00:00", "2022-06-11T16:17:00+00:00",
+        &["stream_name/date=2022-06-11/hour=16/minute=00-59/"]
+    )]
+    fn prefix_generation_with_granularity(
+        #[case] data_granularity: u32,
+        #[case] start: &str,
+        #[case] end: &str,
+        #[case] right: &[&str],
+    ) {
+        let time_period = TimePeriod::new(
+            DateTime::parse_from_rfc3339(start).unwrap().into(),
+            DateTime::parse_from_rfc3339(end).unwrap().into(),
+            data_granularity,
+        );
+        let prefixes = time_period.generate_prefixes("stream_name");
+        let left = prefixes.iter().map(String::as_str).collect::<Vec<&str>>();
+        assert_eq!(left.as_slice(), right);
+    }
 }
@@ -21,11 +21,28 @@ use std::sync::Arc;
 use crate::alerts::rule::base::{NumericRule, StringRule};
 use crate::alerts::rule::{ColumnRule, ConsecutiveNumericRule, ConsecutiveStringRule};
 use crate::alerts::{Alerts, Rule};
+use crate::event::dead_letter::DeadLetter;
+use crate::event::dedup::Dedup;
+use crate::event::explode::{Explode, PATH_SEPARATOR};
+use crate::event::guardrails::Guardrails;
+use crate::event::lookup::LookupJoin;
+use crate::event::nesting::Nesting;
+use crate::event::sampling::Sampling;
+use crate::lookup::LOOKUP_TABLES;
 use crate::metadata::STREAM_INFO;
+use crate::parquet::ParquetConfig;
 use crate::query::Query;
+use crate::retention::Retention;
 use chrono::{DateTime, Utc};
+use datafusion::arrow::datatypes::{DataType, Schema};
+use std::collections::HashSet;
 
-use self::error::{AlertValidationError, QueryValidationError, StreamNameValidationError};
+use self::error::{
+    AlertValidationError, DeadLetterValidationError, DedupValidationError, ExplodeValidationError,
+    GuardrailsValidationError, LookupValidationError, NestingValidationError,
+    ParquetValidationError, QueryValidationError, RetentionValidationError,
+    SamplingValidationError, SchemaValidationError, StreamNameValidationError,
+};
 
 // Add more sql keywords here in lower case
 const DENIED_NAMES: &[&str] = &[
@@ -67,6 +84,192 @@ pub fn alert(alerts: &Alerts) -> Result<(), AlertValidationError> {
     Ok(())
 }
 
+pub fn sampling(sampling: &Sampling) -> Result<(), SamplingValidationError> {
+    if !(0.0..=1.0).contains(&sampling.rate) {
+        return Err(SamplingValidationError::InvalidRate(sampling.rate));
+    }
+
+    for rule in &sampling.rules {
+        if rule.column.is_empty() {
+            return Err(SamplingValidationError::EmptyRuleField);
+        }
+        if !(0.0..=1.0).contains(&rule.rate) {
+            return Err(SamplingValidationError::InvalidRate(rule.rate));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn dedup(dedup: &Dedup) -> Result<(), DedupValidationError> {
+    if matches!(dedup.field, Some(ref field) if field.is_empty()) {
+        return Err(DedupValidationError::EmptyField);
+    }
+    if dedup.window.is_zero() {
+        return Err(DedupValidationError::ZeroWindow);
+    }
+    if dedup.capacity == 0 {
+        return Err(DedupValidationError::ZeroCapacity);
+    }
+
+    Ok(())
+}
+
+pub fn parquet(parquet: &ParquetConfig) -> Result<(), ParquetValidationError> {
+    if let Some(setting) = parquet.unsupported().first() {
+        return Err(ParquetValidationError::Unsupported(setting));
+    }
+    if parquet.max_row_group_size == Some(0) {
+        return Err(ParquetValidationError::ZeroRowGroupSize);
+    }
+    if parquet.columns().any(String::is_empty) {
+        return Err(ParquetValidationError::EmptyColumn);
+    }
+
+    Ok(())
+}
+
+pub fn retention(retention: &Retention) -> Result<(), RetentionValidationError> {
+    if retention.days == 0 {
+        return Err(RetentionValidationError::ZeroDays);
+    }
+
+    Ok(())
+}
+
+pub fn nesting(nesting: &Nesting) -> Result<(), NestingValidationError> {
+    if nesting.max_depth == 0 {
+        return Err(NestingValidationError::ZeroDepth);
+    }
+
+    Ok(())
+}
+
+pub fn explode(explode: &Explode) -> Result<(), ExplodeValidationError> {
+    if explode.path.split(PATH_SEPARATOR).any(str::is_empty) {
+        return Err(ExplodeValidationError::EmptyKey(explode.path.to_owned()));
+    }
+
+    Ok(())
+}
+
+pub fn guardrails(guardrails: &Guardrails) -> Result<(), GuardrailsValidationError> {
+    // a stream needs at least one column besides the overflow column
+    if matches!(guardrails.max_columns, Some(max) if max < 2) {
+        return Err(GuardrailsValidationError::TooFewColumns);
+    }
+    if guardrails.max_key_length == Some(0) || guardrails.max_depth == Some(0) {
+        return Err(GuardrailsValidationError::ZeroLimit);
+    }
+
+    Ok(())
+}
+
+pub fn lookup_table_name(name: &str) -> Result<(), LookupValidationError> {
+    // lookup tables follow the same naming rules as log streams
+    self::stream_name(name)?;
+
+    Ok(())
+}
+
+pub fn lookups(lookups: &[LookupJoin]) -> Result<(), LookupValidationError> {
+    let mut columns = HashSet::new();
+
+    for lookup in lookups {
+        if lookup.field.is_empty() {
+            return Err(LookupValidationError::EmptyField);
+        }
+
+        let Some(table) = LOOKUP_TABLES.get(&lookup.table) else {
+            return Err(LookupValidationError::TableNotFound(
+                lookup.table.to_owned(),
+            ));
+        };
+
+        for column in std::iter::once(&lookup.key).chain(&lookup.columns) {
+            if !table.has_column(column) {
+                return Err(LookupValidationError::ColumnNotFound(
+                    lookup.table.to_owned(),
+                    column.to_owned(),
+                ));
+            }
+        }
+
+        for column in &lookup.columns {
+            if column.starts_with("p_") {
+                return Err(LookupValidationError::ReservedColumn(column.to_owned()));
+            }
+            if !columns.insert(column) {
+                return Err(LookupValidationError::DuplicateColumn(column.to_owned()));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn dead_letter(
+    stream_name: &str,
+    dead_letter: &DeadLetter,
+) -> Result<(), DeadLetterValidationError> {
+    self::stream_name(&dead_letter.stream)?;
+
+    if dead_letter.stream == stream_name {
+        return Err(DeadLetterValidationError::SameStream);
+    }
+
+    Ok(())
+}
+
+pub fn schema(schema: &Schema) -> Result<(), SchemaValidationError> {
+    if schema.fields().is_empty() {
+        return Err(SchemaValidationError::NoFields);
+    }
+
+    let mut names = HashSet::new();
+    for field in schema.fields() {
+        if field.name().is_empty() {
+            return Err(SchemaValidationError::EmptyName);
+        }
+        if !names.insert(field.name()) {
+            return Err(SchemaValidationError::DuplicateField(
+                field.name().to_owned(),
+            ));
+        }
+        if !supported_type(field.data_type()) {
+            return Err(SchemaValidationError::UnsupportedType(
+                field.name().to_owned(),
+                field.data_type().clone(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// Scalar types, and lists and structs of these for streams in nested mode
+fn supported_type(data_type: &DataType) -> bool {
+    match data_type {
+        DataType::Boolean
+        | DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32
+        | DataType::UInt64
+        | DataType::Float32
+        | DataType::Float64
+        | DataType::Utf8 => true,
+        DataType::List(field) => supported_type(field.data_type()),
+        DataType::Struct(fields) => {
+            !fields.is_empty() && fields.iter().all(|field| supported_type(field.data_type()))
+        }
+        _ => false,
+    }
+}
+
 pub fn stream_name(stream_name: &str) -> Result<(), StreamNameValidationError> {
     if stream_name.is_empty() {
         return Err(StreamNameValidationError::EmptyName);
@@ -175,6 +378,8 @@ pub fn query(query: &str, start_time: &str, end_time: &str) -> Result<Query, Que
 }
 
 pub mod error {
+    use datafusion::arrow::datatypes::DataType;
+
     use crate::metadata::error::stream_info::MetadataError;
 
     #[derive(Debug, thiserror::Error)]
@@ -191,6 +396,96 @@ pub mod error {
         NoTarget,
     }
 
+    #[derive(Debug, thiserror::Error)]
+    pub enum SamplingValidationError {
+        #[error("Sampling rate {0} is invalid, it must be between 0 and 1")]
+        InvalidRate(f64),
+        #[error("Sampling rule's column cannot be empty")]
+        EmptyRuleField,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum ParquetValidationError {
+        #[error("{0} is not supported by the parquet writer yet")]
+        Unsupported(&'static str),
+        #[error("Max row group size must be greater than zero")]
+        ZeroRowGroupSize,
+        #[error("Column name cannot be empty")]
+        EmptyColumn,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum RetentionValidationError {
+        #[error("Retention days must be greater than zero")]
+        ZeroDays,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum DedupValidationError {
+        #[error("Dedup field cannot be empty")]
+        EmptyField,
+        #[error("Dedup window must be greater than zero")]
+        ZeroWindow,
+        #[error("Dedup capacity must be greater than zero")]
+        ZeroCapacity,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum NestingValidationError {
+        #[error("Nesting maxDepth must be greater than zero")]
+        ZeroDepth,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum ExplodeValidationError {
+        #[error("Explode path {0:?} cannot be empty or contain an empty key")]
+        EmptyKey(String),
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum GuardrailsValidationError {
+        #[error("Guardrails maxColumns must be at least 2")]
+        TooFewColumns,
+        #[error("Guardrails maxKeyLength and maxDepth must be greater than zero")]
+        ZeroLimit,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum LookupValidationError {
+        #[error("Invalid lookup table name: {0}")]
+        TableName(#[from] StreamNameValidationError),
+        #[error("Lookup field cannot be empty")]
+        EmptyField,
+        #[error("Lookup table {0} does not exist")]
+        TableNotFound(String),
+        #[error("Lookup table {0} does not have column {1}")]
+        ColumnNotFound(String, String),
+        #[error("Column {0} is reserved for the server")]
+        ReservedColumn(String),
+        #[error("Column {0} is added by more than one lookup")]
+        DuplicateColumn(String),
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum DeadLetterValidationError {
+        #[error("Invalid dead-letter stream name: {0}")]
+        StreamName(#[from] StreamNameValidationError),
+        #[error("A log stream cannot be its own dead-letter stream")]
+        SameStream,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum SchemaValidationError {
+        #[error("Schema must have at least one field")]
+        NoFields,
+        #[error("Field name cannot be empty")]
+        EmptyName,
+        #[error("Field {0} is declared more than once")]
+        DuplicateField(String),
+        #[error("Field {0} has unsupported type {1:?}")]
+        UnsupportedType(String, DataType),
+    }
+
     #[derive(Debug, thiserror::Error)]
     pub enum QueryValidationError {
         #[error("Query cannot be empty")]